@@ -0,0 +1,452 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result, bail};
+use flate2::read::GzDecoder;
+use log::debug;
+use sha2::{Digest, Sha256};
+
+use crate::{cache, git, integrity, scanner};
+
+/// Extractions inside an archive's cache directory, one per checksum.
+const EXTRACTED_DIR: &str = "archives";
+
+/// Archive formats that can be used as sources.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ArchiveFormat {
+    TarGz,
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// Detect the format from a URL or file name, ignoring any query string
+    /// or fragment.
+    fn detect(input: &str) -> Option<Self> {
+        let name = input
+            .split(['?', '#'])
+            .next()
+            .unwrap_or(input)
+            .to_ascii_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(ArchiveFormat::TarGz)
+        } else if name.ends_with(".zip") {
+            Some(ArchiveFormat::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// Check whether a source is an archive (`.tar.gz`, `.tgz` or `.zip`),
+/// given either as a URL or as a local file.
+pub fn is_archive(input: &str) -> bool {
+    ArchiveFormat::detect(input).is_some()
+}
+
+/// Check whether an archive source is downloaded rather than read from disk.
+pub fn is_remote_archive(input: &str) -> bool {
+    input.starts_with("https://") || input.starts_with("http://")
+}
+
+/// Download (or read) an archive and extract it into the cache, returning
+/// the directory holding its contents.
+///
+/// Extractions live at `<cache>/<hash>/archives/<sha256>/`, where the hash
+/// is of the URL (or, for local files, the canonical path) and `sha256` is
+/// the archive's checksum, so a URL whose content changes gets a fresh
+/// extraction. An expected `checksum` (`sha256:<hex>`) is verified before
+/// anything is extracted, and an archive already extracted with that
+/// checksum is used without downloading it again.
+///
+/// Archives that wrap everything in a single top-level directory (as
+/// release tarballs usually do) resolve to that directory. With `offline`,
+/// remote archives are only taken from the cache.
+pub fn resolve_archive(source: &str, checksum: Option<&str>, offline: bool) -> Result<PathBuf> {
+    resolve_archive_in(&git::cache_root()?, source, checksum, offline)
+}
+
+fn resolve_archive_in(
+    cache_root: &Path,
+    source: &str,
+    checksum: Option<&str>,
+    offline: bool,
+) -> Result<PathBuf> {
+    debug!(
+        "Resolving archive: {}, checksum={:?}, offline={}",
+        source, checksum, offline
+    );
+    let format = ArchiveFormat::detect(source)
+        .with_context(|| format!("unsupported archive format: {source}"))?;
+    let expected = checksum.map(integrity::parse).transpose()?;
+    let remote = is_remote_archive(source);
+    if source.starts_with("http://") {
+        bail!("archive URLs must use https: {source}");
+    }
+
+    let key = if remote {
+        source.to_string()
+    } else {
+        fs::canonicalize(source)
+            .with_context(|| format!("archive not found: {source}"))?
+            .to_string_lossy()
+            .into_owned()
+    };
+    let entry = cache_root.join(git::hash_url(&key));
+    let extracted = entry.join(EXTRACTED_DIR);
+
+    // Verified content never changes, so a previous extraction is as good
+    // as a fresh download
+    if let Some(expected) = &expected {
+        let dir = extracted.join(expected);
+        if dir.is_dir() {
+            debug!("Archive {} is cached as {}", source, dir.display());
+            return content_root(&dir);
+        }
+    }
+
+    if remote && offline {
+        let cached = match &expected {
+            Some(_) => None,
+            None => cache::read_meta(&entry)
+                .and_then(|meta| meta.checksum)
+                .map(|checksum| extracted.join(checksum))
+                .filter(|dir| dir.is_dir()),
+        };
+        return match cached {
+            Some(dir) => content_root(&dir),
+            None => bail!("{source} is not cached (offline mode)"),
+        };
+    }
+
+    fs::create_dir_all(&entry)
+        .with_context(|| format!("failed to create directory: {}", entry.display()))?;
+    let download = if remote {
+        Some(download(source, &entry)?)
+    } else {
+        None
+    };
+    let archive = download.as_deref().unwrap_or(Path::new(source));
+
+    let result = sha256_file(archive).and_then(|checksum| {
+        if let Some(expected) = &expected
+            && *expected != checksum
+        {
+            bail!(
+                "integrity check failed for {source}:\n  expected sha256:{expected}\n  got      sha256:{checksum}"
+            );
+        }
+        let dir = extracted.join(&checksum);
+        if !dir.is_dir() {
+            extract(format, archive, &dir)?;
+        }
+        Ok((checksum, dir))
+    });
+    if let Some(download) = &download {
+        let _ = fs::remove_file(download);
+    }
+    let (checksum, dir) = result?;
+
+    cache::write_meta(&entry, &cache::CacheMeta::downloaded(&key, &checksum))?;
+    content_root(&dir)
+}
+
+/// Download `url` into a temporary file inside `dir`.
+fn download(url: &str, dir: &Path) -> Result<PathBuf> {
+    debug!("Downloading {}", url);
+    let response = ureq::get(url)
+        .call()
+        .with_context(|| format!("failed to download {url}"))?;
+    let path = dir.join(format!("{}.download", tmp_name()));
+    let written = File::create(&path).and_then(|mut file| {
+        io::copy(&mut response.into_body().into_reader(), &mut file)?;
+        file.sync_all()
+    });
+    if let Err(err) = written {
+        let _ = fs::remove_file(&path);
+        return Err(err).with_context(|| format!("failed to download {url}"));
+    }
+    Ok(path)
+}
+
+/// SHA-256 of a file, as lowercase hex.
+fn sha256_file(path: &Path) -> Result<String> {
+    let mut file =
+        File::open(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Extract `archive` to `dest`, via a temporary sibling directory so an
+/// interrupted extraction is never mistaken for a complete one.
+fn extract(format: ArchiveFormat, archive: &Path, dest: &Path) -> Result<()> {
+    debug!("Extracting {} -> {}", archive.display(), dest.display());
+    let parent = dest
+        .parent()
+        .context("extraction directory has no parent")?;
+    let tmp = parent.join(tmp_name());
+    let unpacked = fs::create_dir_all(&tmp)
+        .with_context(|| format!("failed to create directory: {}", tmp.display()))
+        .and_then(|()| match format {
+            ArchiveFormat::TarGz => extract_tar_gz(archive, &tmp),
+            ArchiveFormat::Zip => extract_zip(archive, &tmp),
+        });
+    let placed = unpacked.and_then(|()| {
+        if let Err(err) = fs::rename(&tmp, dest) {
+            // A concurrent install may have extracted the same archive
+            if !dest.is_dir() {
+                return Err(err).with_context(|| format!("failed to write {}", dest.display()));
+            }
+        }
+        Ok(())
+    });
+    let _ = fs::remove_dir_all(&tmp);
+    placed.with_context(|| format!("failed to extract {}", archive.display()))
+}
+
+fn extract_tar_gz(archive: &Path, dest: &Path) -> Result<()> {
+    let file = File::open(archive)?;
+    let mut tar = tar::Archive::new(GzDecoder::new(file));
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let path = entry_path(&entry.path()?)?;
+        let target = dest.join(&path);
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_dir() {
+            fs::create_dir_all(&target)?;
+        } else if entry_type.is_file() {
+            create_parent(&target)?;
+            entry.unpack(&target)?;
+        } else {
+            debug!("Skipping {:?} entry {}", entry_type, path.display());
+        }
+    }
+    Ok(())
+}
+
+fn extract_zip(archive: &Path, dest: &Path) -> Result<()> {
+    let mut zip = zip::ZipArchive::new(File::open(archive)?)?;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        let path = entry_path(Path::new(entry.name()))?;
+        let target = dest.join(&path);
+        if entry.is_dir() {
+            fs::create_dir_all(&target)?;
+        } else if entry.is_symlink() {
+            debug!("Skipping symlink entry {}", path.display());
+        } else {
+            create_parent(&target)?;
+            io::copy(&mut entry, &mut File::create(&target)?)?;
+            #[cfg(unix)]
+            if entry.unix_mode().is_some_and(|mode| mode & 0o111 != 0) {
+                use std::os::unix::fs::PermissionsExt;
+                fs::set_permissions(&target, fs::Permissions::from_mode(0o755))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Check that an archive entry stays inside the extraction directory and
+/// return it as a relative path.
+fn entry_path(path: &Path) -> Result<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => relative.push(part),
+            Component::CurDir => {}
+            _ => bail!(
+                "archive entry '{}' points outside the archive",
+                path.display()
+            ),
+        }
+    }
+    Ok(relative)
+}
+
+fn create_parent(path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(())
+}
+
+/// The directory an extraction's contents live in: its only entry when
+/// that is a wrapper directory (e.g. `pack-1.0/`), otherwise the extraction
+/// itself. A lone `skills/` or `.claude/` is content, not a wrapper.
+fn content_root(dir: &Path) -> Result<PathBuf> {
+    let entries = fs::read_dir(dir)
+        .with_context(|| format!("failed to read directory: {}", dir.display()))?
+        .collect::<io::Result<Vec<_>>>()?;
+    if let [only] = entries.as_slice()
+        && only.file_type()?.is_dir()
+    {
+        let name = only.file_name();
+        let is_scan_root = scanner::scan_roots(None)
+            .iter()
+            .any(|root| root.split('/').next() == name.to_str());
+        if !is_scan_root {
+            return Ok(only.path());
+        }
+    }
+    Ok(dir.to_path_buf())
+}
+
+/// Unique name for a temporary file or directory.
+fn tmp_name() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    format!(".tmp-{}-{nanos}", std::process::id())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    /// Write a `.tar.gz` with the given `(path, content)` entries. Paths are
+    /// written into the header verbatim, so unsafe ones can be tested.
+    fn write_tar_gz(path: &Path, files: &[(&str, &str)]) {
+        let gz = GzEncoder::new(File::create(path).unwrap(), Compression::default());
+        let mut builder = tar::Builder::new(gz);
+        for (name, content) in files {
+            let mut header = tar::Header::new_gnu();
+            let raw = &mut header.as_old_mut().name;
+            raw[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_cksum();
+            builder.append(&header, content.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    fn write_zip(path: &Path, files: &[(&str, &str)]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        let options = zip::write::SimpleFileOptions::default()
+            .compression_method(zip::CompressionMethod::Stored);
+        for (name, content) in files {
+            zip.start_file(*name, options).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn detects_archive_sources() {
+        assert!(is_archive("https://example.com/pack-1.0.tar.gz"));
+        assert!(is_archive("https://example.com/pack.zip?download=1"));
+        assert!(is_archive("./vendor/pack.TGZ"));
+        assert!(!is_archive("github.com/org/repo"));
+        assert!(!is_archive("./skills"));
+    }
+
+    #[test]
+    fn tar_gz_is_extracted_and_unwrapped() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let archive = tmp.path().join("pack.tar.gz");
+        write_tar_gz(
+            &archive,
+            &[
+                ("pack-1.0/skills/review/SKILL.md", "# Review"),
+                ("pack-1.0/commands/deploy.md", "# Deploy"),
+            ],
+        );
+
+        let cache = tmp.path().join("cache");
+        let root = resolve_archive_in(&cache, archive.to_str().unwrap(), None, false)?;
+        assert!(root.ends_with("pack-1.0"));
+        assert_eq!(
+            fs::read_to_string(root.join("skills/review/SKILL.md"))?,
+            "# Review"
+        );
+
+        let meta = cache::read_meta(root.parent().unwrap().parent().unwrap().parent().unwrap());
+        assert_eq!(meta.and_then(|m| m.checksum), Some(sha256_file(&archive)?));
+        Ok(())
+    }
+
+    #[test]
+    fn zip_is_extracted() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let archive = tmp.path().join("pack.zip");
+        write_zip(
+            &archive,
+            &[("skills/review/SKILL.md", "# Review"), ("README.md", "hi")],
+        );
+
+        let cache = tmp.path().join("cache");
+        let root = resolve_archive_in(&cache, archive.to_str().unwrap(), None, false)?;
+        assert!(root.join("skills/review/SKILL.md").is_file());
+        assert!(root.join("README.md").is_file());
+        Ok(())
+    }
+
+    #[test]
+    fn traversal_entries_are_rejected() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let cache = tmp.path().join("cache");
+
+        let tarball = tmp.path().join("evil.tar.gz");
+        write_tar_gz(&tarball, &[("../../evil.md", "gotcha")]);
+        let err = resolve_archive_in(&cache, tarball.to_str().unwrap(), None, false).unwrap_err();
+        assert!(format!("{err:#}").contains("points outside the archive"));
+
+        let zipball = tmp.path().join("evil.zip");
+        write_zip(&zipball, &[("/tmp/evil.md", "gotcha")]);
+        let err = resolve_archive_in(&cache, zipball.to_str().unwrap(), None, false).unwrap_err();
+        assert!(format!("{err:#}").contains("points outside the archive"));
+
+        assert!(!tmp.path().join("evil.md").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn integrity_is_checked_before_extraction() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let archive = tmp.path().join("pack.tar.gz");
+        write_tar_gz(&archive, &[("skills/review/SKILL.md", "# Review")]);
+        let cache = tmp.path().join("cache");
+        let source = archive.to_str().unwrap();
+
+        let wrong = format!("sha256:{}", "0".repeat(64));
+        let err = resolve_archive_in(&cache, source, Some(&wrong), false).unwrap_err();
+        assert!(err.to_string().contains("integrity check failed"));
+        assert!(
+            !cache
+                .join(git::hash_url(&archive.canonicalize()?.to_string_lossy()))
+                .join(EXTRACTED_DIR)
+                .exists()
+        );
+
+        let right = format!("sha256:{}", sha256_file(&archive)?);
+        let root = resolve_archive_in(&cache, source, Some(&right), false)?;
+        assert!(root.join("skills/review/SKILL.md").is_file());
+        Ok(())
+    }
+
+    #[test]
+    fn offline_remote_archive_needs_cache() {
+        let tmp = TempDir::new().unwrap();
+        let err = resolve_archive_in(tmp.path(), "https://example.com/pack.tar.gz", None, true)
+            .unwrap_err();
+        assert!(err.to_string().contains("offline mode"));
+    }
+}
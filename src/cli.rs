@@ -1,3 +1,4 @@
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
 
 use clap::{Parser, Subcommand};
@@ -15,6 +16,11 @@ pub struct Cli {
     #[arg(short, long, global = true)]
     pub verbose: bool,
 
+    /// Never touch the network: resolve remote sources from the cache only,
+    /// failing if a needed ref isn't cached
+    #[arg(long, global = true)]
+    pub offline: bool,
+
     #[command(subcommand)]
     pub command: Command,
 }
@@ -27,7 +33,9 @@ pub enum Command {
         /// If omitted, installs all dependencies from agentfiles.json.
         source: Option<String>,
 
-        /// Installation scope: project or global
+        /// Installation scope: project, global or local. Global installs are
+        /// recorded in the user manifest at $XDG_CONFIG_HOME/agentfiles/agentfiles.json;
+        /// local installs in agentfiles.local.json and excluded from git.
         #[arg(short, long, default_value = "project")]
         scope: FileScope,
 
@@ -41,10 +49,21 @@ pub enum Command {
         strategy: Option<FileStrategy>,
 
         /// Cherry-pick specific items by name (comma-separated).
-        /// Supports kind prefix: skills/review, commands/deploy, or plain: review
+        /// Supports kind prefix: skills/review, commands/deploy, or plain: review,
+        /// and globs: skills/review-*, commands/**/deploy
         #[arg(long, value_delimiter = ',')]
         pick: Option<Vec<String>>,
 
+        /// Leave out items matching these patterns (comma-separated), written
+        /// like --pick
+        #[arg(long, value_delimiter = ',')]
+        exclude: Option<Vec<String>>,
+
+        /// Install the single picked item under a new name (e.g. --pick
+        /// skills/review --as acme-review)
+        #[arg(long = "as", value_name = "NAME", requires = "pick")]
+        alias: Option<String>,
+
         /// Do not save the source to agentfiles.json after installing
         #[arg(long)]
         no_save: bool,
@@ -53,6 +72,23 @@ pub enum Command {
         #[arg(long)]
         dry_run: bool,
 
+        /// Overwrite installed files that were edited locally, and files
+        /// agentfiles didn't create
+        #[arg(long, conflicts_with_all = ["backup", "keep_local"])]
+        force: bool,
+
+        /// Move edited or unknown files to <name>.orig before installing over them
+        #[arg(long, conflicts_with = "keep_local")]
+        backup: bool,
+
+        /// Keep edited or unknown files as they are and install everything else
+        #[arg(long)]
+        keep_local: bool,
+
+        /// Number of dependencies to fetch in parallel. Defaults to the number of CPUs.
+        #[arg(short, long)]
+        jobs: Option<NonZeroUsize>,
+
         /// Project root directory (for project scope installations)
         #[arg(long, default_value = ".")]
         root: PathBuf,
@@ -76,6 +112,22 @@ pub enum Command {
         source: String,
     },
 
+    /// Audit dependency contents for scripts, hooks and other risky files
+    Audit {
+        /// Source to audit (local path, archive or git URL). If omitted,
+        /// audits every dependency in the manifest.
+        source: Option<String>,
+
+        /// Manifest scope whose dependencies and policy to use: project,
+        /// local or global
+        #[arg(short, long, default_value = "project")]
+        scope: FileScope,
+
+        /// Project root directory
+        #[arg(long, default_value = ".")]
+        root: PathBuf,
+    },
+
     /// Remove a dependency from agentfiles.json
     Remove {
         /// Source to remove (matches by normalized URL)
@@ -85,7 +137,8 @@ pub enum Command {
         #[arg(long)]
         clean: bool,
 
-        /// Installation scope used when installing (for --clean)
+        /// Installation scope: selects the manifest to edit and, with --clean,
+        /// where installed files live
         #[arg(short, long, default_value = "project")]
         scope: FileScope,
 
@@ -93,6 +146,20 @@ pub enum Command {
         #[arg(short, long, value_delimiter = ',')]
         providers: Option<Vec<AgentProvider>>,
 
+        /// With --clean, also delete installed files that were edited
+        /// locally, and files agentfiles didn't create
+        #[arg(long, requires = "clean", conflicts_with_all = ["backup", "keep_local"])]
+        force: bool,
+
+        /// With --clean, move edited or unknown files to <name>.orig
+        /// instead of deleting them
+        #[arg(long, requires = "clean", conflicts_with = "keep_local")]
+        backup: bool,
+
+        /// With --clean, leave edited or unknown files in place
+        #[arg(long, requires = "clean")]
+        keep_local: bool,
+
         /// Project root directory
         #[arg(long, default_value = ".")]
         root: PathBuf,
@@ -103,8 +170,67 @@ pub enum Command {
         /// Project root directory
         #[arg(default_value = ".")]
         root: PathBuf,
+
+        /// Manifest scope: project (agentfiles.json in ROOT), local
+        /// (agentfiles.local.json in ROOT) or global (user manifest)
+        #[arg(short, long, default_value = "project")]
+        scope: FileScope,
     },
 
     /// Show the provider compatibility matrix
     Matrix,
+
+    /// Inspect and clean up the cache of remote sources
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+
+    /// Manage the content-addressed store used by link installs
+    Store {
+        #[command(subcommand)]
+        command: StoreCommand,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum CacheCommand {
+    /// List cached sources with their size, last fetch and checked-out ref
+    List,
+
+    /// Remove cached sources no manifest or install references anymore
+    Prune {
+        /// Also remove sources not fetched for this many days, even if referenced
+        #[arg(long, value_name = "DAYS")]
+        older_than: Option<u64>,
+
+        /// List what would be removed without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Remove every cached source, or only the one for SOURCE
+    Clear {
+        /// Source whose cache entry to remove (e.g., github.com/org/repo)
+        source: Option<String>,
+    },
+
+    /// Print the cache directory, or the cache entry for SOURCE
+    Path {
+        /// Source to locate (e.g., github.com/org/repo)
+        source: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum StoreCommand {
+    /// Remove snapshots that no installed link points to anymore
+    Gc {
+        /// List what would be removed without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Print the store directory
+    Path,
 }
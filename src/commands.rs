@@ -1,30 +1,201 @@
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{self, Write as _};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 
 use anyhow::{Context, Result};
 use log::debug;
 
-use crate::manifest::{Dependency, FileMapping};
-use crate::types::{AgentProvider, FileKind, FileScope, FileStrategy};
-use crate::{git, installer, manifest, scanner};
+use crate::manifest::{Condition, Dependency, FileMapping};
+use crate::types::{AgentProvider, FileKind, FileScope, FileStrategy, OnConflict};
+use crate::{
+    archive, audit, cache, condition, credentials, git, ignore, installer, integrity, ledger,
+    manifest, overlay, scanner, store, template, trust,
+};
 
 /// Options for the install command, collected from CLI arguments.
+///
+/// `data_dir` is where the link store and install ledgers live (normally
+/// `store::data_dir()`), and `config_dir` holds the user manifest (normally
+/// `manifest::user_manifest_dir()`). `jobs` bounds how many dependencies are resolved
+/// at once; `None` uses the available parallelism.
 pub struct InstallOptions {
     pub source: Option<String>,
     pub scope: FileScope,
     pub providers: Option<Vec<AgentProvider>>,
     pub strategy: Option<FileStrategy>,
     pub pick: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    /// New name for the single picked item (`--as`).
+    pub alias: Option<String>,
     pub no_save: bool,
     pub dry_run: bool,
+    pub offline: bool,
+    pub jobs: Option<NonZeroUsize>,
+    /// What to do with targets that were edited or not created by agentfiles.
+    pub on_conflict: OnConflict,
+    pub root: PathBuf,
+    pub data_dir: PathBuf,
+    pub config_dir: PathBuf,
+}
+
+/// Options for the remove command, collected from CLI arguments.
+///
+/// With `clean`, installed files of the dependency are deleted for
+/// `providers` (all when `None`); `on_conflict` decides what happens to the
+/// ones that were edited since they were installed.
+pub struct RemoveOptions {
+    pub source: String,
+    pub clean: bool,
+    pub scope: FileScope,
+    pub providers: Option<Vec<AgentProvider>>,
+    pub on_conflict: OnConflict,
+    pub offline: bool,
     pub root: PathBuf,
+    pub data_dir: PathBuf,
+}
+
+/// Settings shared by every dependency installed in one run.
+struct InstallContext<'a> {
+    project_root: &'a Path,
+    /// Directory of the manifest, which patches and `overrides/` are
+    /// relative to.
+    manifest_dir: &'a Path,
+    providers: &'a [AgentProvider],
+    /// CLI `--strategy`, which beats any manifest setting.
+    strategy_override: Option<FileStrategy>,
+    data_dir: &'a Path,
+    /// Directory of the user manifest.
+    config_dir: &'a Path,
+    dry_run: bool,
+    offline: bool,
+    /// Where sources may come from, per the manifest and user config.
+    sources: &'a trust::SourceRules,
+    /// Maximum number of dependencies resolved concurrently.
+    jobs: NonZeroUsize,
+    /// What to do with targets that were edited or not created by agentfiles.
+    on_conflict: OnConflict,
+}
+
+/// Where progress messages go: straight to stdout, or into a buffer so the
+/// output of concurrent resolutions can be printed in manifest order.
+enum Progress {
+    Print,
+    Buffer(String),
+}
+
+impl InstallContext<'_> {
+    /// Path of the manifest for `scope` (see `manifest::manifest_path`).
+    fn manifest_path(&self, scope: &FileScope) -> PathBuf {
+        manifest::manifest_path_in(scope, self.project_root, self.config_dir)
+    }
+}
+
+impl Progress {
+    fn line(&mut self, message: fmt::Arguments) {
+        match self {
+            Progress::Print => println!("{message}"),
+            Progress::Buffer(buf) => {
+                let _ = writeln!(buf, "{message}");
+            }
+        }
+    }
+
+    /// The buffered output; empty when printing directly.
+    fn into_buffer(self) -> String {
+        match self {
+            Progress::Print => String::new(),
+            Progress::Buffer(buf) => buf,
+        }
+    }
+}
+
+/// How remote sources are fetched for one resolution.
+#[derive(Clone, Copy)]
+struct FetchOptions<'a> {
+    /// Never touch the network (see `git::resolve_remote`).
+    offline: bool,
+    /// Keys the source's tag or commit must be signed with, when it has to
+    /// be verified.
+    verify: Option<&'a git::SignaturePolicy>,
+    /// Rules the source must pass before anything is fetched.
+    sources: Option<&'a trust::SourceRules>,
+}
+
+/// Signature verification settings of the manifest dependencies come from.
+#[derive(Default)]
+struct Verification {
+    /// Manifest-wide `verify`, for dependencies that don't set their own.
+    default: bool,
+    policy: git::SignaturePolicy,
+}
+
+impl Verification {
+    /// Settings from `manifest`, read from `manifest_path`. Trusted keys come
+    /// from its `signing`, or else from the user manifest's in `user_dir`;
+    /// they are only looked up when something has to be verified.
+    fn load(manifest: &manifest::Manifest, manifest_path: &Path, user_dir: &Path) -> Result<Self> {
+        let needed = manifest.verify
+            || manifest
+                .dependencies
+                .iter()
+                .any(|d| d.verify() == Some(true));
+        if !needed {
+            return Ok(Verification::default());
+        }
+        let policy = match &manifest.signing {
+            Some(signing) => signing.policy(manifest_path.parent().unwrap_or(Path::new(".")))?,
+            None => user_signing_policy(user_dir)?,
+        };
+        debug!("Signature policy: {:?}", policy);
+        Ok(Verification {
+            default: manifest.verify,
+            policy,
+        })
+    }
+
+    /// The policy `dep` must satisfy, if it has to be verified.
+    fn for_dependency(&self, dep: &Dependency) -> Option<&git::SignaturePolicy> {
+        dep.verify().unwrap_or(self.default).then_some(&self.policy)
+    }
+}
+
+/// Trusted keys configured in the user manifest in `user_dir`, if any.
+fn user_signing_policy(user_dir: &Path) -> Result<git::SignaturePolicy> {
+    match manifest::load_user_manifest_in(user_dir)?.and_then(|m| m.signing) {
+        Some(signing) => signing.policy(user_dir),
+        None => Ok(git::SignaturePolicy::default()),
+    }
+}
+
+/// A dependency resolved to the files it will install.
+struct ResolvedDependency {
+    source_dir: PathBuf,
+    files: Vec<FileMapping>,
+    /// Patched copies of the items, which `source_dir` points into when
+    /// any patch applied.
+    overlay: Option<overlay::Overlay>,
+    /// The dependency's `when` conditions, by item.
+    when: BTreeMap<String, Condition>,
+    /// New names for items, by item (see `installer::check_renames`).
+    rename: BTreeMap<String, String>,
+    /// Commit a git source resolved to.
+    commit: Option<String>,
 }
 
+/// A dependency's buffered progress output and its resolution.
+type Resolution = (String, Result<ResolvedDependency>);
+
 /// Install agent files. Two flows:
 ///
-/// - **No source**: reads `agentfiles.json` from the project root and installs
-///   all dependencies listed there.
+/// - **No source**: reads `agentfiles.json` for the scope (the project root,
+///   or the user manifest for global scope) and installs all dependencies
+///   listed there.
 /// - **With source**: resolves the source, scans it for agent files, installs
-///   them, and (unless `no_save` is set) adds the source to `agentfiles.json`.
+///   them, and (unless `no_save` is set) adds the source to that manifest.
 pub fn cmd_install(opts: InstallOptions) -> Result<()> {
     debug!(
         "cmd_install: source={:?}, scope={}, dry_run={}",
@@ -39,141 +210,449 @@ pub fn cmd_install(opts: InstallOptions) -> Result<()> {
         .canonicalize()
         .context("could not resolve project root")?;
 
+    let manifest_path = manifest::manifest_path_in(&opts.scope, &project_root, &opts.config_dir);
+    let sources = if manifest_path.is_file() {
+        let loaded = manifest::load_manifest(&manifest_path)?;
+        trust::SourceRules::load(Some((&loaded, &manifest_path)), &opts.config_dir)?
+    } else {
+        trust::SourceRules::load(None, &opts.config_dir)?
+    };
+
+    let manifest_dir = manifest_path.parent().unwrap_or(&project_root);
+    let ctx = InstallContext {
+        project_root: &project_root,
+        manifest_dir,
+        providers: &providers,
+        strategy_override: opts.strategy,
+        data_dir: &opts.data_dir,
+        config_dir: &opts.config_dir,
+        dry_run: opts.dry_run,
+        offline: opts.offline,
+        sources: &sources,
+        on_conflict: opts.on_conflict,
+        jobs: opts
+            .jobs
+            .unwrap_or_else(|| thread::available_parallelism().unwrap_or(NonZeroUsize::MIN)),
+    };
+
     match opts.source {
-        None => install_from_manifest(
-            &project_root,
-            &providers,
-            &opts.scope,
-            opts.strategy,
-            opts.dry_run,
-        ),
+        None => install_from_manifest(&ctx, &opts.scope),
         Some(src) => install_from_source(
+            &ctx,
             &src,
-            &project_root,
-            &providers,
             &opts.scope,
-            opts.strategy,
             opts.pick.as_deref(),
+            opts.exclude.as_deref(),
+            opts.alias.as_deref(),
             opts.no_save,
-            opts.dry_run,
         ),
     }
 }
 
-/// Install all dependencies listed in the project's `agentfiles.json`.
-fn install_from_manifest(
-    project_root: &std::path::Path,
-    providers: &[AgentProvider],
-    scope: &FileScope,
-    strategy_override: Option<FileStrategy>,
-    dry_run: bool,
-) -> Result<()> {
-    let manifest_path = project_root.join("agentfiles.json");
+/// Install all dependencies listed in the manifest for `scope`.
+///
+/// Project scope reads `agentfiles.json` in the project root and layers
+/// `agentfiles.local.json` on top of it when present. Local scope reads only
+/// the local manifest; global scope reads the user manifest (see
+/// `manifest::user_manifest_dir`).
+fn install_from_manifest(ctx: &InstallContext, scope: &FileScope) -> Result<()> {
+    let project_root = ctx.project_root;
+    let dry_run = ctx.dry_run;
+    let manifest_path = ctx.manifest_path(scope);
     if !manifest_path.is_file() {
         anyhow::bail!(
-            "no agentfiles.json found in {}. Run 'agentfiles init' first or specify a source.",
-            project_root.display()
+            "no {} found at {}. Run 'agentfiles init' first or specify a source.",
+            manifest_file_name(&manifest_path),
+            manifest_path.display()
         );
     }
 
-    let loaded = manifest::load_manifest(project_root)?;
+    let loaded = manifest::load_manifest(&manifest_path)?;
     debug!(
         "Loaded manifest '{}' v{} with {} dependencies",
         loaded.name,
         loaded.version,
         loaded.dependencies.len()
     );
-    if loaded.dependencies.is_empty() {
-        println!("No dependencies in agentfiles.json. Add one with 'agentfiles install <source>'.");
+
+    let dependencies = manifest_dependencies(&loaded, scope, project_root)?;
+
+    if dependencies.is_empty() {
+        println!(
+            "No dependencies in {}. Add one with 'agentfiles install <source>'.",
+            manifest_file_name(&manifest_path)
+        );
         return Ok(());
     }
 
     println!(
         "Installing {} dependency(ies) from '{}' (v{})...\n",
-        loaded.dependencies.len(),
+        dependencies.len(),
         loaded.name,
         loaded.version,
     );
 
+    let vars = template::Vars::new(project_root, Some(&loaded.name), &loaded.vars, &loaded.env);
+    let mut ledger = ledger::Ledger::load(ctx.data_dir, &ledger_root(scope, project_root)?)?;
     let mut total_results = Vec::new();
+    let mut project_targets = Vec::new();
+    let mut local_targets = Vec::new();
+
+    // Fetching is the slow part, so it runs concurrently; installs then
+    // happen one dependency at a time in manifest order, as before.
+    let verification = Verification::load(&loaded, &manifest_path, ctx.config_dir)?;
+    let resolutions = resolve_dependencies(ctx, &dependencies, &verification);
+    let mut checked = Vec::with_capacity(dependencies.len());
+    for ((dep, _), (log, resolved)) in dependencies.iter().zip(resolutions) {
+        println!("  -> {}", dep.source());
+        print!("{log}");
+        let resolved = resolved?;
+        if resolved.files.is_empty() {
+            println!("    (no matching files found)");
+        }
+        // Every dependency passes the audit before any of them is installed
+        let policies: Vec<_> = dep.policy().into_iter().chain(&loaded.policy).collect();
+        enforce_policy(dep.source(), &resolved, &policies)?;
+        checked.push(resolved);
+    }
 
-    for dep in &loaded.dependencies {
-        let dep_results = install_dependency(
-            dep,
-            project_root,
-            providers,
-            scope,
-            strategy_override,
-            dry_run,
+    // One transaction for all dependencies: a failure anywhere leaves every
+    // target as it was before this install
+    let mut transaction = installer::Transaction::new(dry_run);
+    for ((dep, dep_scope), resolved) in dependencies.iter().zip(checked) {
+        let key = ledger_source(dep.source(), dep.subdir());
+        let commit = resolved.commit.clone();
+        let dep_results = install_resolved(
+            ctx,
+            &mut transaction,
+            &ledger,
+            &key,
+            resolved,
+            dep_scope,
+            &vars.with(dep.vars()),
         )?;
+        ledger.record(ledger_entries(&key, commit.as_deref(), &dep_results));
+        let targets = dep_results.iter().map(|r| PathBuf::from(&r.target));
+        match dep_scope {
+            FileScope::Project => project_targets.extend(targets),
+            FileScope::Local => local_targets.extend(targets),
+            FileScope::Global => {}
+        }
         total_results.extend(dep_results);
     }
 
+    transaction.commit();
+
+    // Recorded only once the install is final, so a rollback never leaves
+    // hashes behind for files that were restored
+    if !dry_run {
+        ledger.save(ctx.data_dir)?;
+        if !local_targets.is_empty() {
+            exclude_local_targets(project_root, &local_targets, &[])?;
+        }
+        if *scope == FileScope::Project && loaded.gitignore {
+            // Entries for targets that no longer exist are dropped; the rest
+            // may belong to providers or items this run left out
+            ignore_project_targets(project_root, &project_targets, &[], true)?;
+        }
+    }
+
     print_results(&total_results, dry_run);
     Ok(())
 }
 
+/// Dependencies of `loaded`, the manifest for `scope`, paired with the scope
+/// each installs into. Project scope layers `agentfiles.local.json` on top.
+fn manifest_dependencies(
+    loaded: &manifest::Manifest,
+    scope: &FileScope,
+    project_root: &Path,
+) -> Result<Vec<(Dependency, FileScope)>> {
+    Ok(match scope {
+        FileScope::Project => {
+            let local_path = manifest::manifest_path(&FileScope::Local, project_root)?;
+            if local_path.is_file() {
+                let local = manifest::load_manifest(&local_path)?;
+                debug!(
+                    "Layering {} local dependency(ies) from {}",
+                    local.dependencies.len(),
+                    local_path.display()
+                );
+                loaded.layer_local(&local)
+            } else {
+                loaded.layer_local(&manifest::Manifest::default())
+            }
+        }
+        _ => loaded
+            .dependencies
+            .iter()
+            .map(|d| (d.clone(), *scope))
+            .collect(),
+    })
+}
+
 /// Install from a specific source, optionally saving it to agentfiles.json.
-#[allow(clippy::too_many_arguments)]
 fn install_from_source(
+    ctx: &InstallContext,
     source: &str,
-    project_root: &std::path::Path,
-    providers: &[AgentProvider],
     scope: &FileScope,
-    strategy_override: Option<FileStrategy>,
     pick: Option<&[String]>,
+    exclude: Option<&[String]>,
+    alias: Option<&str>,
     no_save: bool,
-    dry_run: bool,
 ) -> Result<()> {
     debug!("Installing from source: {}", source);
-    let (source_dir, mut files) = resolve_source(source, None)?;
-
-    // Apply pick filter
-    if let Some(pick_list) = pick {
-        files = scanner::filter_by_pick(files, pick_list);
+    let (project_root, dry_run) = (ctx.project_root, ctx.dry_run);
+    let manifest_path = ctx.manifest_path(scope);
+    // Manifest-wide settings also cover sources installed directly
+    let loaded = if manifest_path.is_file() {
+        Some(manifest::load_manifest(&manifest_path)?)
+    } else {
+        None
+    };
+    let verify = match &loaded {
+        Some(loaded) if loaded.verify => {
+            Some(Verification::load(loaded, &manifest_path, ctx.config_dir)?)
+        }
+        _ => None,
+    };
+    let (source_dir, mut files, commit) = resolve_source(
+        source,
+        None,
+        None,
+        None,
+        None,
+        FetchOptions {
+            offline: ctx.offline,
+            verify: verify.as_ref().map(|v| &v.policy),
+            sources: Some(ctx.sources),
+        },
+        &mut Progress::Print,
+    )?;
+
+    // Apply pick and exclude filters
+    if pick.is_some() || exclude.is_some() {
+        let unmatched;
+        (files, unmatched) = scanner::select(files, pick, exclude.unwrap_or_default());
         if files.is_empty() {
-            anyhow::bail!("no files matched the pick filter");
+            let filters = match (pick, exclude) {
+                (Some(_), Some(_)) => "--pick/--exclude",
+                (Some(_), None) => "--pick",
+                (None, _) => "--exclude",
+            };
+            anyhow::bail!("no items left after {filters}");
+        }
+        for pattern in unmatched {
+            println!("warning: pick '{pattern}' matches no item of {source}");
         }
         debug!("After pick filter: {} file(s) remaining", files.len());
     }
 
+    // `--as` renames the one picked item
+    let rename = match alias {
+        Some(alias) => {
+            let [file] = files.as_slice() else {
+                let items: Vec<String> = files.iter().map(scanner::item_key).collect();
+                anyhow::bail!(
+                    "--as renames a single item, but the pick selected {}: {}",
+                    files.len(),
+                    items.join(", ")
+                );
+            };
+            BTreeMap::from([(scanner::item_key(file), alias.to_string())])
+        }
+        None => BTreeMap::new(),
+    };
+    installer::check_renames(&files, &rename, source)?;
+
+    let overlay = overlay::apply(source, &source_dir, &mut files, &[], ctx.manifest_dir)?;
+    let source_dir = overlay.as_ref().map_or(source_dir, |o| o.dir.clone());
+
+    let policies: Vec<_> = loaded.iter().filter_map(|m| m.policy.as_ref()).collect();
+    let findings = audit_findings(&source_dir, &files, &policies)?;
+    report_policy(source, &findings, "")?;
+
     // Apply strategy override (CLI flag takes highest precedence)
-    if let Some(strategy) = strategy_override {
+    if let Some(strategy) = ctx.strategy_override {
         for file in &mut files {
             file.strategy = strategy;
         }
     }
 
-    let results = installer::install(&files, providers, scope, project_root, &source_dir, dry_run)?;
+    let mut ledger = ledger::Ledger::load(ctx.data_dir, &ledger_root(scope, project_root)?)?;
+    let vars = match &loaded {
+        Some(loaded) => {
+            template::Vars::new(project_root, Some(&loaded.name), &loaded.vars, &loaded.env)
+        }
+        None => template::Vars::new(project_root, None, &Default::default(), &[]),
+    };
+    let planned = plan_items(
+        ctx,
+        &files,
+        &source_dir,
+        scope,
+        &vars,
+        &BTreeMap::new(),
+        &rename,
+    )?;
+    let key = ledger_source(source, None);
+    let in_manifest = loaded
+        .iter()
+        .flat_map(|m| &m.dependencies)
+        .any(|dep| ledger_source(dep.source(), dep.subdir()) == key);
+    let planned = resolve_conflicts(
+        ctx,
+        planned,
+        &ledger,
+        in_manifest && !ledger.has_source(&key),
+    )?;
+
+    // The user manifest is replayed from any working directory, so local
+    // sources are recorded as absolute paths.
+    let saved_source = if no_save || dry_run {
+        None
+    } else if *scope == FileScope::Global && !git::is_git_url(source) {
+        let path = PathBuf::from(source)
+            .canonicalize()
+            .context("could not resolve source path")?;
+        Some(path.to_string_lossy().into_owned())
+    } else {
+        Some(source.to_string())
+    };
+
+    let mut transaction = installer::Transaction::new(dry_run);
+    let results = transaction.install(&planned, &store::store_dir(ctx.data_dir))?;
+    transaction.commit();
 
-    if !no_save && !dry_run {
-        save_dependency(source, pick, project_root)?;
+    // Recorded only once the install is final, so a rollback never leaves
+    // hashes or a dependency behind for files that were restored
+    if !dry_run {
+        ledger.record(ledger_entries(&key, commit.as_deref(), &results));
+        ledger.save(ctx.data_dir)?;
+    }
+    if let Some(saved_source) = saved_source {
+        save_dependency(&saved_source, pick, exclude, &rename, &manifest_path)?;
+    }
+    if !dry_run {
+        let targets: Vec<PathBuf> = results.iter().map(|r| PathBuf::from(&r.target)).collect();
+        match scope {
+            FileScope::Local => exclude_local_targets(project_root, &targets, &[])?,
+            FileScope::Project if gitignore_enabled(project_root)? => {
+                ignore_project_targets(project_root, &targets, &[], false)?
+            }
+            _ => {}
+        }
     }
 
     print_results(&results, dry_run);
     Ok(())
 }
 
-/// Install a single dependency from the manifest.
-fn install_dependency(
+/// Resolve the dependencies in `deps` with up to `ctx.jobs` workers.
+///
+/// Returns, in the order of `deps`, each dependency's buffered progress
+/// output alongside its resolution. Dependencies sharing a repository are
+/// resolved one after another by the same worker, so a cache entry is never
+/// fetched into concurrently.
+fn resolve_dependencies(
+    ctx: &InstallContext,
+    deps: &[(Dependency, FileScope)],
+    verification: &Verification,
+) -> Vec<Resolution> {
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut by_repo: HashMap<String, usize> = HashMap::new();
+    for (i, (dep, _)) in deps.iter().enumerate() {
+        if git::is_git_url(dep.source()) {
+            let url = git::parse_remote(dep.source()).url;
+            if let Some(&group) = by_repo.get(&url) {
+                groups[group].push(i);
+                continue;
+            }
+            by_repo.insert(url, groups.len());
+        }
+        groups.push(vec![i]);
+    }
+
+    let workers = ctx.jobs.get().min(groups.len());
+    debug!(
+        "Resolving {} dependency(ies) in {} group(s) with {} worker(s)",
+        deps.len(),
+        groups.len(),
+        workers
+    );
+    let next = AtomicUsize::new(0);
+    let slots: Mutex<Vec<Option<Resolution>>> = Mutex::new(deps.iter().map(|_| None).collect());
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|| {
+                while let Some(group) = groups.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    for &i in group {
+                        let mut progress = Progress::Buffer(String::new());
+                        let resolved =
+                            resolve_dependency(ctx, &deps[i].0, verification, &mut progress);
+                        slots.lock().unwrap_or_else(|e| e.into_inner())[i] =
+                            Some((progress.into_buffer(), resolved));
+                    }
+                }
+            });
+        }
+    });
+
+    slots
+        .into_inner()
+        .unwrap_or_else(|e| e.into_inner())
+        .into_iter()
+        .map(|slot| slot.expect("every dependency is resolved"))
+        .collect()
+}
+
+/// Resolve a single dependency from the manifest and select its files.
+fn resolve_dependency(
+    ctx: &InstallContext,
     dep: &Dependency,
-    project_root: &std::path::Path,
-    providers: &[AgentProvider],
-    scope: &FileScope,
-    strategy_override: Option<FileStrategy>,
-    dry_run: bool,
-) -> Result<Vec<installer::InstallResult>> {
+    verification: &Verification,
+    progress: &mut Progress,
+) -> Result<ResolvedDependency> {
     let source = dep.source();
-    debug!("Installing dependency: {}", source);
-    println!("  -> {source}");
+    debug!("Resolving dependency: {}", source);
 
-    let (source_dir, mut files) = resolve_source(source, dep.paths())?;
+    let (source_dir, mut files, commit) = resolve_source(
+        source,
+        dep.git_ref(),
+        dep.subdir(),
+        archive_checksum(dep),
+        dep.paths(),
+        FetchOptions {
+            offline: ctx.offline,
+            verify: verification.for_dependency(dep),
+            sources: Some(ctx.sources),
+        },
+        progress,
+    )?;
+
+    // Apply pick and exclude filters
+    let unmatched;
+    (files, unmatched) = scanner::select(files, dep.pick(), dep.exclude());
+    for pattern in unmatched {
+        progress.line(format_args!(
+            "    warning: pick '{pattern}' matches no item of {source}"
+        ));
+    }
 
-    // Apply pick filter
-    if let Some(pick_list) = dep.pick() {
-        files = scanner::filter_by_pick(files, pick_list);
+    if let Some(expected) = dep.integrity() {
+        integrity::verify(source, expected, &files, archive::is_archive(source))?;
     }
 
+    // Integrity covers upstream content; patches apply on top of it
+    let overlay = overlay::apply(
+        source,
+        &source_dir,
+        &mut files,
+        dep.patches(),
+        ctx.manifest_dir,
+    )?;
+    let source_dir = overlay.as_ref().map_or(source_dir, |o| o.dir.clone());
+
     // Apply strategy: dep-level overrides default, CLI overrides everything
     let dep_strategy = dep.strategy();
     for file in &mut files {
@@ -182,85 +661,448 @@ fn install_dependency(
         {
             file.strategy = strategy;
         }
-        if let Some(strategy) = strategy_override {
+        if let Some(strategy) = ctx.strategy_override {
             file.strategy = strategy;
         }
     }
 
+    let when = dep.when().cloned().unwrap_or_default();
+    for item in when.keys() {
+        if !files.iter().any(|f| scanner::item_key(f) == *item) {
+            anyhow::bail!("condition for '{item}' matches no item of {source}");
+        }
+    }
+
+    let rename = dep.rename().cloned().unwrap_or_default();
+    installer::check_renames(&files, &rename, source)?;
+
+    Ok(ResolvedDependency {
+        source_dir,
+        files,
+        overlay,
+        when,
+        rename,
+        commit,
+    })
+}
+
+/// Audit `files` under `source_dir`, pairing each finding with the action
+/// `policies` (most specific first) take on it.
+fn audit_findings(
+    source_dir: &Path,
+    files: &[FileMapping],
+    policies: &[&manifest::Policy],
+) -> Result<Vec<(audit::Finding, audit::Action)>> {
+    Ok(audit::audit(source_dir, files)?
+        .into_iter()
+        .map(|finding| {
+            let action = audit::action(&finding, policies);
+            (finding, action)
+        })
+        .collect())
+}
+
+/// Audit a resolved dependency before it is installed: warnings are
+/// printed, and any blocked finding refuses the install.
+fn enforce_policy(
+    source: &str,
+    resolved: &ResolvedDependency,
+    policies: &[&manifest::Policy],
+) -> Result<()> {
+    let findings = audit_findings(&resolved.source_dir, &resolved.files, policies)?;
+    report_policy(source, &findings, "  ")
+}
+
+/// Print warnings from `findings` and fail if any is blocked.
+fn report_policy(
+    source: &str,
+    findings: &[(audit::Finding, audit::Action)],
+    indent: &str,
+) -> Result<()> {
+    let mut blocked = Vec::new();
+    for (finding, action) in findings {
+        match action {
+            audit::Action::Allow => {}
+            audit::Action::Warn => println!("{indent}warning: {finding}"),
+            audit::Action::Block => blocked.push(format!("  {finding}")),
+        }
+    }
+    if !blocked.is_empty() {
+        anyhow::bail!(
+            "{source} is blocked by policy:\n{}\n\
+             Review the files, then add them to a policy's \"allow\" list to install anyway.",
+            blocked.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// Expected checksum of `dep`'s archive, when it is an archive source with
+/// a dependency-wide `integrity`.
+fn archive_checksum(dep: &Dependency) -> Option<&str> {
+    match dep.integrity() {
+        Some(manifest::Integrity::Dependency(checksum)) if archive::is_archive(dep.source()) => {
+            Some(checksum)
+        }
+        _ => None,
+    }
+}
+
+/// Install the files of a resolved dependency as part of `transaction`.
+///
+/// `ledger` records what was installed before, to protect targets that were
+/// edited since (see `resolve_conflicts`); `key` is the dependency's source
+/// as recorded there (see `ledger_source`). Placeholders in the files are
+/// rendered with `vars`.
+fn install_resolved(
+    ctx: &InstallContext,
+    transaction: &mut installer::Transaction,
+    ledger: &ledger::Ledger,
+    key: &str,
+    resolved: ResolvedDependency,
+    scope: &FileScope,
+    vars: &template::Vars,
+) -> Result<Vec<installer::InstallResult>> {
+    // The overlay, if any, must outlive the install
+    let ResolvedDependency {
+        source_dir,
+        files,
+        overlay: _overlay,
+        when,
+        rename,
+        commit: _,
+    } = resolved;
     if files.is_empty() {
-        println!("    (no matching files found)");
         return Ok(vec![]);
     }
 
-    debug!(
-        "Dependency '{}': {} file(s) to install",
-        source,
-        files.len()
+    debug!("Dependency '{}': {} file(s) to install", key, files.len());
+    let planned = plan_items(ctx, &files, &source_dir, scope, vars, &when, &rename)?;
+    // A manifest dependency the ledger knows nothing about was installed
+    // before agentfiles kept ledgers, so its targets are its own
+    let planned = resolve_conflicts(ctx, planned, ledger, !ledger.has_source(key))?;
+    transaction.install(&planned, &store::store_dir(ctx.data_dir))
+}
+
+/// Plan the installs of `files`, leaving out the providers, or whole
+/// items, whose condition isn't met (see `condition::for_item`). Skipped
+/// items are reported with the reason.
+fn plan_items(
+    ctx: &InstallContext,
+    files: &[FileMapping],
+    source_dir: &Path,
+    scope: &FileScope,
+    vars: &template::Vars,
+    when: &BTreeMap<String, Condition>,
+    renames: &BTreeMap<String, String>,
+) -> Result<Vec<installer::PlannedInstall>> {
+    let prefix = if ctx.dry_run { "[dry-run] " } else { "" };
+    let mut planned = Vec::new();
+    for file in files {
+        let providers = match condition::for_item(file, source_dir, when)? {
+            None => ctx.providers.to_vec(),
+            Some(condition) => {
+                let (allowed, reasons) =
+                    condition::allowed(&condition, ctx.providers, ctx.project_root).with_context(
+                        || format!("invalid condition for {}", file.path.display()),
+                    )?;
+                let skipped: Vec<String> = ctx
+                    .providers
+                    .iter()
+                    .filter(|p| p.supports_kind(&file.kind) && !allowed.contains(p))
+                    .map(ToString::to_string)
+                    .collect();
+                if !skipped.is_empty() {
+                    let reasons = reasons.join("; ");
+                    if allowed.iter().any(|p| p.supports_kind(&file.kind)) {
+                        println!(
+                            "    {prefix}Skipping {} for {} ({reasons})",
+                            file.path.display(),
+                            skipped.join(", ")
+                        );
+                    } else {
+                        println!("    {prefix}Skipping {} ({reasons})", file.path.display());
+                    }
+                }
+                allowed
+            }
+        };
+        planned.extend(installer::plan(
+            std::slice::from_ref(file),
+            &providers,
+            scope,
+            ctx.project_root,
+            source_dir,
+            vars,
+            renames,
+        )?);
+    }
+    Ok(planned)
+}
+
+/// Apply `ctx.on_conflict` to planned targets that were edited since they
+/// were installed, or that agentfiles didn't install at all, and return the
+/// installs to carry out.
+///
+/// By default nothing is overwritten: the install fails, listing each such
+/// target with a diff against what would replace it. With `adopt`, targets
+/// missing from the ledger are taken to be installed by agentfiles, as for
+/// a manifest dependency installed before ledgers existed.
+fn resolve_conflicts(
+    ctx: &InstallContext,
+    planned: Vec<installer::PlannedInstall>,
+    ledger: &ledger::Ledger,
+    adopt: bool,
+) -> Result<Vec<installer::PlannedInstall>> {
+    let prefix = if ctx.dry_run { "[dry-run] " } else { "" };
+    let mut accepted = Vec::with_capacity(planned.len());
+    let mut refused = Vec::new();
+    for mut item in planned {
+        let conflict = installer::conflict(&item, ledger)
+            .filter(|c| !(adopt && *c == installer::Conflict::Unmanaged));
+        let Some(conflict) = conflict else {
+            accepted.push(item);
+            continue;
+        };
+        debug!("{} is {}", item.target.display(), conflict);
+        match ctx.on_conflict {
+            OnConflict::Force => accepted.push(item),
+            OnConflict::Backup => {
+                item.backup = true;
+                accepted.push(item);
+            }
+            OnConflict::Keep => {
+                println!("    {prefix}Keeping {} ({conflict})", item.target.display());
+            }
+            OnConflict::Refuse => refused.push((item, conflict)),
+        }
+    }
+    if refused.is_empty() {
+        return Ok(accepted);
+    }
+
+    let mut message = format!(
+        "refusing to overwrite {} file(s) that would lose local content:",
+        refused.len()
+    );
+    for (item, conflict) in &refused {
+        message.push_str(&format!("\n\n  {} ({conflict})", item.target.display()));
+        if let Some(diff) = git::diff_paths(&item.target, &item.source_path) {
+            for line in diff.lines() {
+                message.push_str(&format!("\n    {line}"));
+            }
+        }
+    }
+    message.push_str(
+        "\n\nRun again with --force to overwrite them, --backup to keep them as <name>.orig, \
+         or --keep-local to leave them as they are.",
     );
-    installer::install(&files, providers, scope, project_root, &source_dir, dry_run)
+    Err(anyhow::anyhow!(message))
+}
+
+/// Directory whose ledger records installs for `scope`: the project root,
+/// or the home directory for global installs.
+fn ledger_root(scope: &FileScope, project_root: &std::path::Path) -> Result<PathBuf> {
+    match scope {
+        FileScope::Project | FileScope::Local => Ok(project_root.to_path_buf()),
+        FileScope::Global => dirs::home_dir().context("could not determine home directory"),
+    }
+}
+
+/// How a dependency's source is recorded in the ledger: normalized for git
+/// sources (see `git::source_key`), as given for archives and local paths.
+fn ledger_source(source: &str, subdir: Option<&str>) -> String {
+    if git::is_git_url(source) && !archive::is_archive(source) {
+        git::source_key(source, subdir)
+    } else {
+        source.to_string()
+    }
+}
+
+/// Ledger entries for the targets installed from `source`, resolved to
+/// `commit` for git sources.
+fn ledger_entries(
+    source: &str,
+    commit: Option<&str>,
+    results: &[installer::InstallResult],
+) -> Vec<ledger::LedgerEntry> {
+    results
+        .iter()
+        .map(|r| ledger::LedgerEntry {
+            target: PathBuf::from(&r.target),
+            source: source.to_string(),
+            item: r.source.clone(),
+            provider: r.provider,
+            kind: r.kind,
+            strategy: r.strategy,
+            snapshot: r.snapshot.clone(),
+            hash: Some(r.hash.clone()),
+            commit: commit.map(str::to_string),
+        })
+        .collect()
 }
 
-/// Resolve a source (remote or local) to a local directory and scanned files.
+/// Resolve a source (archive, remote or local) to a local directory, its
+/// scanned files and, for git sources, the commit it resolved to.
+///
+/// `git_ref` and `subdir` come from a detailed manifest entry and take
+/// precedence over an inline `@ref` / `//subdir` in `source`. When `subdir`
+/// is set, scanning and `custom_paths` are relative to that subdirectory.
+/// `checksum` is the expected checksum of an archive source.
 ///
 /// When `custom_paths` is provided, the scanner uses those instead of the
-/// default directory convention.
+/// default directory convention. `fetch` controls network access, source
+/// rules and signature verification; only git sources can be verified.
 fn resolve_source(
     source: &str,
+    git_ref: Option<&str>,
+    subdir: Option<&str>,
+    checksum: Option<&str>,
     custom_paths: Option<&[manifest::PathMapping]>,
-) -> Result<(PathBuf, Vec<FileMapping>)> {
+    fetch: FetchOptions,
+    progress: &mut Progress,
+) -> Result<(PathBuf, Vec<FileMapping>, Option<String>)> {
     debug!(
-        "Resolving source: {} (is_git={})",
+        "Resolving source: {} (is_archive={}, is_git={})",
         source,
+        archive::is_archive(source),
         git::is_git_url(source)
     );
+    if let Some(rules) = fetch.sources {
+        rules.check(source, git_ref, checksum)?;
+    }
+    let is_remote_git = git::is_git_url(source) && !archive::is_archive(source);
+    if fetch.verify.is_some() && !is_remote_git {
+        anyhow::bail!(
+            "refusing to install {source}: signature verification is required, \
+             but only git sources are signed (set \"verify\": false to trust it)"
+        );
+    }
+    if archive::is_archive(source) {
+        if git_ref.is_some() {
+            anyhow::bail!("archive source {source} cannot have a git ref");
+        }
+        let (dir, files) = resolve_archive_source(
+            source,
+            subdir,
+            checksum,
+            custom_paths,
+            fetch.offline,
+            progress,
+        )?;
+        return Ok((dir, files, None));
+    }
     if git::is_git_url(source) {
-        resolve_remote_source(source, custom_paths)
+        let mut remote = git::parse_remote(source);
+        if let Some(git_ref) = git_ref {
+            remote.git_ref = Some(git_ref.to_string());
+        }
+        if let Some(subdir) = subdir {
+            remote.subdir = Some(subdir.trim_matches('/').to_string());
+        }
+        let resolved = resolve_remote_source(&remote, custom_paths, fetch, progress)?;
+        if let (Some(rules), Some(commit)) = (fetch.sources, &resolved.2) {
+            rules.check_resolved(source, remote.git_ref.as_deref(), commit)?;
+        }
+        Ok(resolved)
     } else {
-        resolve_local_source(source, custom_paths)
+        let (dir, files) = resolve_local_source(source, subdir, custom_paths)?;
+        Ok((dir, files, None))
     }
 }
 
 /// Clone/fetch a remote git repo and scan for agent files.
 fn resolve_remote_source(
-    source: &str,
+    remote: &git::ParsedRemote,
     custom_paths: Option<&[manifest::PathMapping]>,
-) -> Result<(PathBuf, Vec<FileMapping>)> {
-    debug!("Resolving remote source: {}", source);
-    let remote = git::parse_remote(source);
-
-    let ref_display = remote
-        .git_ref
-        .as_deref()
-        .map(|r| format!(" @ {r}"))
-        .unwrap_or_default();
-    println!("Resolving remote: {}{ref_display}", remote.url);
-
-    let git_source = git::resolve_remote(&remote)?;
+    fetch: FetchOptions,
+    progress: &mut Progress,
+) -> Result<(PathBuf, Vec<FileMapping>, Option<String>)> {
+    debug!("Resolving remote source: {}", remote);
+    progress.line(format_args!("Resolving remote: {remote}"));
+
+    let checkout_paths = checkout_paths(remote, custom_paths);
+    let git_source = git::resolve_remote(remote, &checkout_paths, fetch.offline, fetch.verify)?;
     let local_path = git_source.local_path;
 
-    println!("Cached at: {}\n", local_path.display());
+    progress.line(format_args!("Cached at: {}\n", local_path.display()));
 
     let files = scanner::scan_agent_files(&local_path, custom_paths)?;
     if files.is_empty() {
-        anyhow::bail!("no agent files found in {}", git_source.url);
+        anyhow::bail!("no agent files found in {remote}");
+    }
+    progress.line(format_args!("Discovered {} agent file(s).\n", files.len()));
+
+    Ok((local_path, files, Some(git_source.commit)))
+}
+
+/// Download or read an archive, extract it into the cache and scan it.
+fn resolve_archive_source(
+    source: &str,
+    subdir: Option<&str>,
+    checksum: Option<&str>,
+    custom_paths: Option<&[manifest::PathMapping]>,
+    offline: bool,
+    progress: &mut Progress,
+) -> Result<(PathBuf, Vec<FileMapping>)> {
+    debug!("Resolving archive source: {}", source);
+    progress.line(format_args!("Resolving archive: {source}"));
+
+    let mut dir = archive::resolve_archive(source, checksum, offline)?;
+    progress.line(format_args!("Cached at: {}\n", dir.display()));
+    if let Some(subdir) = subdir {
+        dir = source_subdir(&dir, subdir, source)?;
+    }
+
+    let files = scanner::scan_agent_files(&dir, custom_paths)?;
+    if files.is_empty() {
+        anyhow::bail!("no agent files found in {source}");
     }
-    println!("Discovered {} agent file(s).\n", files.len());
+    progress.line(format_args!("Discovered {} agent file(s).\n", files.len()));
+
+    Ok((dir, files))
+}
 
-    Ok((local_path, files))
+/// Repository paths to check out for `remote`: the scan roots, nested
+/// under the subdirectory when the source points into a monorepo.
+fn checkout_paths(
+    remote: &git::ParsedRemote,
+    custom_paths: Option<&[manifest::PathMapping]>,
+) -> Vec<String> {
+    let roots = scanner::scan_roots(custom_paths);
+    match &remote.subdir {
+        Some(subdir) => roots
+            .into_iter()
+            .map(|root| format!("{}/{root}", subdir.trim_matches('/')))
+            .collect(),
+        None => roots,
+    }
 }
 
 /// Resolve a local path and scan for agent files.
 fn resolve_local_source(
     source: &str,
+    subdir: Option<&str>,
     custom_paths: Option<&[manifest::PathMapping]>,
 ) -> Result<(PathBuf, Vec<FileMapping>)> {
     debug!("Resolving local source: {}", source);
     let path = PathBuf::from(source);
     if !path.exists() {
+        // `git.corp.example/team/repo` reads as a relative path unless the
+        // host is configured
+        let first = source.split('/').next().unwrap_or_default();
+        if first.contains('.') && first.len() > 1 && !first.starts_with('.') {
+            anyhow::bail!(
+                "source path not found: {}\n\
+                 If '{first}' is a git host, add it to \"remotes\": {{ \"hosts\": [...] }} \
+                 in the user manifest.",
+                path.display()
+            );
+        }
         anyhow::bail!("source path not found: {}", path.display());
     }
 
-    let dir = if path.is_dir() {
+    let mut dir = if path.is_dir() {
         path
     } else {
         path.parent()
@@ -268,6 +1110,10 @@ fn resolve_local_source(
             .unwrap_or_else(|| PathBuf::from("."))
     };
 
+    if let Some(subdir) = subdir {
+        dir = source_subdir(&dir, subdir, source)?;
+    }
+
     let files = scanner::scan_agent_files(&dir, custom_paths)?;
     if files.is_empty() {
         anyhow::bail!("no agent files found in {}", dir.display());
@@ -284,78 +1130,224 @@ fn resolve_local_source(
     Ok((canonical, files))
 }
 
-/// Add a dependency to agentfiles.json, creating the file if it doesn't exist.
+/// Validate `subdir` and return it joined onto `dir`, the root of `source`.
+fn source_subdir(dir: &Path, subdir: &str, source: &str) -> Result<PathBuf> {
+    let subdir = subdir.trim_matches('/');
+    git::validate_subdir(subdir)?;
+    let dir = dir.join(subdir);
+    if !dir.is_dir() {
+        anyhow::bail!("subdirectory '{subdir}' not found in {source}");
+    }
+    Ok(dir)
+}
+
+/// Add a dependency to the manifest at `manifest_path`, creating the file
+/// (and its directory) if it doesn't exist.
 ///
 /// Normalizes the source URL and extracts any inline `@ref` into the
 /// structured `DependencySpec.git_ref` field.
 fn save_dependency(
     source: &str,
     pick: Option<&[String]>,
-    project_root: &std::path::Path,
+    exclude: Option<&[String]>,
+    rename: &BTreeMap<String, String>,
+    manifest_path: &std::path::Path,
 ) -> Result<()> {
     debug!("Saving dependency: {}", source);
-    let manifest_path = project_root.join("agentfiles.json");
 
     let mut loaded = if manifest_path.is_file() {
-        manifest::load_manifest(project_root)?
+        manifest::load_manifest(manifest_path)?
     } else {
-        let name = scanner::infer_name(project_root);
+        let manifest_dir = manifest_path
+            .parent()
+            .context("manifest path has no parent directory")?;
+        std::fs::create_dir_all(manifest_dir).with_context(|| {
+            format!(
+                "failed to create manifest directory: {}",
+                manifest_dir.display()
+            )
+        })?;
+        let name = scanner::infer_name(manifest_dir);
         manifest::Manifest::default().with_name(name)
     };
 
-    // Parse the source to extract any inline @ref and normalize the URL
-    let parsed = git::parse_remote(source);
-    let normalized_source = if git::is_git_url(source) {
-        parsed.url.clone()
+    // Parse the source to extract any inline @ref / //subdir and normalize
+    // the URL, leaving out rewrites. Archive URLs are kept verbatim.
+    let parsed = if archive::is_archive(source) {
+        git::ParsedRemote {
+            url: source.to_string(),
+            git_ref: None,
+            subdir: None,
+        }
     } else {
-        source.to_string()
+        git::parse_remote(source)
+    };
+    let (normalized_source, subdir) = if archive::is_archive(source) {
+        (parsed.url.clone(), parsed.subdir)
+    } else if git::is_git_url(source) {
+        (git::written_url(source), parsed.subdir)
+    } else {
+        (source.to_string(), None)
     };
 
-    let has_details = parsed.git_ref.is_some() || pick.is_some();
+    let has_details = parsed.git_ref.is_some()
+        || subdir.is_some()
+        || pick.is_some()
+        || exclude.is_some()
+        || !rename.is_empty();
 
     let dep = if has_details {
         Dependency::Detailed(manifest::DependencySpec {
             source: normalized_source,
             git_ref: parsed.git_ref,
             pick: pick.map(|p| p.to_vec()),
+            exclude: exclude.map(|e| e.to_vec()),
             strategy: None,
             paths: None,
+            subdir,
+            integrity: None,
+            verify: None,
+            policy: None,
+            patches: None,
+            vars: None,
+            when: None,
+            rename: (!rename.is_empty()).then(|| rename.clone()),
         })
     } else {
         Dependency::Simple(normalized_source)
     };
 
     if loaded.add_dependency(dep) {
-        manifest::save_manifest(&loaded, project_root)?;
-        println!("Saved to agentfiles.json");
+        manifest::save_manifest_file(&loaded, manifest_path)?;
+        println!("Saved to {}", manifest_path.display());
     } else {
-        println!("Dependency already in agentfiles.json");
+        println!("Dependency already in {}", manifest_path.display());
     }
 
     Ok(())
 }
 
-fn print_results(results: &[installer::InstallResult], dry_run: bool) {
-    let prefix = if dry_run { "[dry-run] " } else { "" };
+/// File name of a manifest path, for user-facing messages.
+fn manifest_file_name(manifest_path: &std::path::Path) -> String {
+    manifest_path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| manifest::MANIFEST_FILE.to_string())
+}
 
-    if results.is_empty() {
-        println!("{prefix}No files installed (no compatible provider/kind combinations found).");
-    } else {
-        let verb = if dry_run {
-            "Would install"
-        } else {
-            "Installed"
-        };
-        println!("\n{prefix}{verb} {} file(s):\n", results.len());
-        for r in results {
-            println!(
-                "  [{:>11}] [{}] {} -> {} ({})",
+/// Keep local-scope files out of version control.
+///
+/// Maintains an agentfiles block in the repository's `.git/info/exclude`
+/// listing each local-scope target (and the local manifest itself). Does
+/// nothing when the project is not inside a git repository.
+fn exclude_local_targets(
+    project_root: &std::path::Path,
+    added: &[PathBuf],
+    removed: &[PathBuf],
+) -> Result<()> {
+    let (Some(toplevel), Some(exclude_path)) = (
+        git::repo_toplevel(project_root),
+        git::info_exclude_path(project_root),
+    ) else {
+        debug!("Not a git repository, skipping local excludes");
+        return Ok(());
+    };
+    let toplevel = toplevel.canonicalize().unwrap_or(toplevel);
+
+    let mut add: Vec<String> = added
+        .iter()
+        .filter_map(|t| ignore::target_entry(&toplevel, t))
+        .collect();
+    let local_manifest = manifest::manifest_path(&FileScope::Local, project_root)?;
+    if local_manifest.is_file()
+        && let Some(entry) = ignore::target_entry(&toplevel, &local_manifest)
+    {
+        add.push(entry);
+    }
+    let remove: Vec<String> = removed
+        .iter()
+        .filter_map(|t| ignore::target_entry(&toplevel, t))
+        .collect();
+
+    ignore::update_block(&exclude_path, "local", &add, &remove)
+}
+
+/// Label of the `.gitignore` block listing installed project-scope targets.
+const GITIGNORE_LABEL: &str = "installed";
+
+/// Whether the project manifest opts into the managed `.gitignore` block.
+fn gitignore_enabled(project_root: &std::path::Path) -> Result<bool> {
+    let manifest_path = manifest::manifest_path(&FileScope::Project, project_root)?;
+    if !manifest_path.is_file() {
+        return Ok(false);
+    }
+    Ok(manifest::load_manifest(&manifest_path)?.gitignore)
+}
+
+/// Maintain the agentfiles block in the project's `.gitignore`.
+///
+/// Each installed target is listed individually, so hand-authored files in
+/// the same provider directories stay tracked. `added` and `removed` are
+/// merged into the existing entries; with `prune`, entries whose target no
+/// longer exists are dropped as well.
+fn ignore_project_targets(
+    project_root: &std::path::Path,
+    added: &[PathBuf],
+    removed: &[PathBuf],
+    prune: bool,
+) -> Result<()> {
+    let gitignore = project_root.join(".gitignore");
+    let add: Vec<String> = added
+        .iter()
+        .filter_map(|t| ignore::target_entry(project_root, t))
+        .collect();
+    if prune {
+        return ignore::merge_block(&gitignore, GITIGNORE_LABEL, &add, |entry| {
+            std::fs::symlink_metadata(project_root.join(entry.trim_start_matches('/'))).is_ok()
+        });
+    }
+    let remove: Vec<String> = removed
+        .iter()
+        .filter_map(|t| ignore::target_entry(project_root, t))
+        .collect();
+    ignore::update_block(&gitignore, GITIGNORE_LABEL, &add, &remove)
+}
+
+fn print_results(results: &[installer::InstallResult], dry_run: bool) {
+    let prefix = if dry_run { "[dry-run] " } else { "" };
+
+    if results.is_empty() {
+        println!("{prefix}No files installed (no compatible provider/kind combinations found).");
+    } else {
+        let verb = if dry_run {
+            "Would install"
+        } else {
+            "Installed"
+        };
+        println!("\n{prefix}{verb} {} file(s):\n", results.len());
+        for r in results {
+            println!(
+                "  [{:>11}] [{}] {} -> {} ({})",
                 r.provider.to_string(),
                 r.kind,
                 r.source,
                 r.target,
                 r.strategy
             );
+            if let Some(backup) = &r.backup {
+                println!("      previous version kept as {}", backup.display());
+            }
+            if r.replaced_link {
+                println!("      replaces a link from a previous install");
+            }
+            for change in &r.changes {
+                let (mark, path) = match change {
+                    installer::FileChange::Added(path) => ('+', path),
+                    installer::FileChange::Modified(path) => ('~', path),
+                    installer::FileChange::Removed(path) => ('-', path),
+                };
+                println!("      {mark} {}", path.display());
+            }
         }
     }
 }
@@ -391,19 +1383,20 @@ pub fn cmd_init(path: PathBuf, name: Option<String>) -> Result<()> {
     Ok(())
 }
 
-pub fn cmd_scan(source: String) -> Result<()> {
-    debug!("cmd_scan: source={}", source);
-    let files = if git::is_git_url(&source) {
-        let remote = git::parse_remote(&source);
+pub fn cmd_scan(source: String, offline: bool) -> Result<()> {
+    debug!("cmd_scan: source={}, offline={}", source, offline);
+    let files = if archive::is_archive(&source) {
+        println!("Resolving archive: {source}");
+        let dir = archive::resolve_archive(&source, None, offline)?;
+        println!("Cached at: {}\n", dir.display());
 
-        let ref_display = remote
-            .git_ref
-            .as_deref()
-            .map(|r| format!(" @ {r}"))
-            .unwrap_or_default();
-        println!("Resolving remote: {}{ref_display}", remote.url);
+        scanner::scan_agent_files(&dir, None)?
+    } else if git::is_git_url(&source) {
+        let remote = git::parse_remote(&source);
+        println!("Resolving remote: {remote}");
 
-        let git_source = git::resolve_remote(&remote)?;
+        let git_source =
+            git::resolve_remote(&remote, &checkout_paths(&remote, None), offline, None)?;
         println!("Cached at: {}\n", git_source.local_path.display());
 
         scanner::scan_agent_files(&git_source.local_path, None)?
@@ -418,67 +1411,333 @@ pub fn cmd_scan(source: String) -> Result<()> {
 
     println!("Found {} agent file(s):\n", files.len());
     for f in &files {
-        println!("  [{}] {}", f.kind, f.path.display());
+        println!(
+            "  [{}] {}  {}",
+            f.kind,
+            f.path.display(),
+            integrity::format(&f.hash)
+        );
     }
+    println!(
+        "\nIntegrity of all items: {}",
+        integrity::format(&integrity::dependency_hash(&files))
+    );
 
     Ok(())
 }
 
-pub fn cmd_remove(
-    source: String,
-    clean: bool,
+/// Audit the content of `source`, or of every dependency in the manifest
+/// for `scope`, against the manifest's policy.
+///
+/// Prints every finding with the action the policy takes on it, and fails
+/// when any finding is blocked.
+pub fn cmd_audit(
+    source: Option<String>,
     scope: FileScope,
-    providers: Option<Vec<AgentProvider>>,
     root: PathBuf,
+    offline: bool,
 ) -> Result<()> {
+    debug!("cmd_audit: source={:?}, scope={}", source, scope);
+    let project_root = root
+        .canonicalize()
+        .context("could not resolve project root")?;
+    let manifest_path = manifest::manifest_path(&scope, &project_root)?;
+    let loaded = if manifest_path.is_file() {
+        Some(manifest::load_manifest(&manifest_path)?)
+    } else if source.is_none() {
+        anyhow::bail!(
+            "no {} found at {}. Specify a source to audit.",
+            manifest_file_name(&manifest_path),
+            manifest_path.display()
+        );
+    } else {
+        None
+    };
+    let manifest_policy = loaded.as_ref().and_then(|m| m.policy.as_ref());
+    let manifest_dir = manifest_path.parent().unwrap_or(&project_root);
+    let sources = trust::SourceRules::load(
+        loaded.as_ref().map(|m| (m, manifest_path.as_path())),
+        &manifest::user_manifest_dir()?,
+    )?;
+    let fetch = FetchOptions {
+        offline,
+        verify: None,
+        sources: Some(&sources),
+    };
+
+    let dependencies = match (&source, &loaded) {
+        (Some(source), _) => vec![Dependency::Simple(source.clone())],
+        (None, Some(loaded)) => manifest_dependencies(loaded, &scope, &project_root)?
+            .into_iter()
+            .map(|(dep, _)| dep)
+            .collect(),
+        (None, None) => unreachable!("checked above"),
+    };
+    if dependencies.is_empty() {
+        println!("No dependencies to audit.");
+        return Ok(());
+    }
+
+    let (mut allowed, mut warned, mut blocked) = (0, 0, 0);
+    for dep in &dependencies {
+        println!("  -> {}", dep.source());
+        let (source_dir, mut files, _) = resolve_source(
+            dep.source(),
+            dep.git_ref(),
+            dep.subdir(),
+            archive_checksum(dep),
+            dep.paths(),
+            fetch,
+            &mut Progress::Buffer(String::new()),
+        )?;
+        (files, _) = scanner::select(files, dep.pick(), dep.exclude());
+        // Audit what would be installed, patches included
+        let overlay = overlay::apply(
+            dep.source(),
+            &source_dir,
+            &mut files,
+            dep.patches(),
+            manifest_dir,
+        )?;
+        let source_dir = overlay.as_ref().map_or(source_dir, |o| o.dir.clone());
+
+        let policies: Vec<_> = dep.policy().into_iter().chain(manifest_policy).collect();
+        let findings = audit_findings(&source_dir, &files, &policies)?;
+        if findings.is_empty() {
+            println!("     no findings");
+        }
+        for (finding, action) in &findings {
+            println!("     {action:<5}  {finding}");
+            match action {
+                audit::Action::Allow => allowed += 1,
+                audit::Action::Warn => warned += 1,
+                audit::Action::Block => blocked += 1,
+            }
+        }
+    }
+
+    println!(
+        "\n{} finding(s): {blocked} blocked, {warned} warning(s), {allowed} allowed",
+        allowed + warned + blocked
+    );
+    if blocked > 0 {
+        anyhow::bail!("audit found {blocked} finding(s) blocked by policy");
+    }
+    Ok(())
+}
+
+pub fn cmd_remove(opts: RemoveOptions) -> Result<()> {
+    let RemoveOptions {
+        source,
+        clean,
+        scope,
+        providers,
+        on_conflict,
+        offline,
+        root,
+        data_dir,
+    } = opts;
     debug!("cmd_remove: source={}, clean={}", source, clean);
     let project_root = root
         .canonicalize()
         .context("could not resolve project root")?;
 
-    let manifest_path = project_root.join("agentfiles.json");
+    let manifest_path = manifest::manifest_path(&scope, &project_root)?;
     if !manifest_path.is_file() {
-        anyhow::bail!("no agentfiles.json found in {}", project_root.display());
+        anyhow::bail!(
+            "no {} found at {}",
+            manifest_file_name(&manifest_path),
+            manifest_path.display()
+        );
     }
 
-    let mut loaded = manifest::load_manifest(&project_root)?;
+    let mut loaded = manifest::load_manifest(&manifest_path)?;
 
-    if !loaded.remove_dependency(&source) {
-        anyhow::bail!("dependency '{}' not found in agentfiles.json", source);
-    }
+    let Some(dep) = loaded.find_dependency(&source).cloned() else {
+        anyhow::bail!(
+            "dependency '{}' not found in {}",
+            source,
+            manifest_path.display()
+        );
+    };
+    loaded.remove_dependency(&source);
 
     // Optionally clean installed files
     if clean {
         let providers = providers.unwrap_or_else(|| AgentProvider::ALL.to_vec());
-        clean_installed_files(&source, &project_root, &providers, &scope)?;
+        let mut ledger = ledger::Ledger::load(&data_dir, &ledger_root(&scope, &project_root)?)?;
+        let removed = clean_installed_files(
+            &dep,
+            &project_root,
+            &providers,
+            &scope,
+            &ledger,
+            on_conflict,
+            offline,
+        )?;
+        ledger.forget(&removed);
+        ledger.save(&data_dir)?;
+        match scope {
+            FileScope::Local => exclude_local_targets(&project_root, &[], &removed)?,
+            FileScope::Project if loaded.gitignore => {
+                ignore_project_targets(&project_root, &[], &removed, false)?
+            }
+            _ => {}
+        }
     }
 
-    manifest::save_manifest(&loaded, &project_root)?;
-    println!("Removed '{}' from agentfiles.json", source);
+    manifest::save_manifest_file(&loaded, &manifest_path)?;
+    println!("Removed '{}' from {}", source, manifest_path.display());
 
     Ok(())
 }
 
-/// Delete installed files for a source from all provider directories.
+/// Delete the installed files of `dep` for `providers`: the targets `ledger`
+/// recorded for it or, for a dependency installed before ledgers existed,
+/// the targets its items would be installed to.
+///
+/// Like installs, this never silently loses local work: targets edited
+/// since they were installed, or not known to be installed by agentfiles,
+/// are handled according to `on_conflict`, and by default nothing is
+/// deleted while there are any. Returns the targets agentfiles no longer
+/// manages: removed, moved to `<name>.orig`, kept, or already gone.
 fn clean_installed_files(
-    source: &str,
+    dep: &Dependency,
     project_root: &std::path::Path,
     providers: &[AgentProvider],
     scope: &FileScope,
-) -> Result<()> {
-    debug!("Cleaning installed files for source: {}", source);
+    ledger: &ledger::Ledger,
+    on_conflict: OnConflict,
+    offline: bool,
+) -> Result<Vec<PathBuf>> {
+    debug!("Cleaning installed files for source: {}", dep.source());
+    let key = ledger_source(dep.source(), dep.subdir());
+    let targets: Vec<(PathBuf, Option<installer::Conflict>)> = if ledger.has_source(&key) {
+        ledger
+            .entries
+            .iter()
+            .filter(|e| e.source == key && providers.contains(&e.provider))
+            .map(|e| {
+                let modified = e.drift() == Some(ledger::Drift::Modified);
+                (
+                    e.target.clone(),
+                    modified.then_some(installer::Conflict::Modified),
+                )
+            })
+            .collect()
+    } else {
+        unrecorded_targets(dep, project_root, providers, scope, offline)
+            .into_iter()
+            .map(|target| (target, Some(installer::Conflict::Unmanaged)))
+            .collect()
+    };
+
+    let conflicts: Vec<_> = targets
+        .iter()
+        .filter_map(|(target, conflict)| Some((target, (*conflict)?)))
+        .collect();
+    match on_conflict {
+        OnConflict::Refuse if !conflicts.is_empty() => {
+            let mut message = format!(
+                "refusing to remove {} file(s) that would lose local content:\n",
+                conflicts.len()
+            );
+            for (target, conflict) in &conflicts {
+                message.push_str(&format!("\n  {} ({conflict})", target.display()));
+            }
+            message.push_str(
+                "\n\nRun again with --force to remove them, --backup to keep them as \
+                 <name>.orig, or --keep-local to leave them in place.",
+            );
+            anyhow::bail!(message);
+        }
+        OnConflict::Backup => {
+            for (target, _) in &conflicts {
+                let orig = installer::orig_path(target);
+                if orig.exists() || orig.is_symlink() {
+                    anyhow::bail!(
+                        "cannot back up {}: {} already exists",
+                        target.display(),
+                        orig.display()
+                    );
+                }
+            }
+        }
+        _ => {}
+    }
+
+    let mut cleaned = Vec::new();
+    for (target, conflict) in targets {
+        if !target.exists() && !target.is_symlink() {
+            cleaned.push(target);
+            continue;
+        }
+        match (conflict, on_conflict) {
+            (Some(conflict), OnConflict::Keep) => {
+                println!("  Keeping {} ({conflict})", target.display());
+            }
+            (Some(_), OnConflict::Backup) => {
+                let orig = installer::orig_path(&target);
+                std::fs::rename(&target, &orig)
+                    .with_context(|| format!("failed to move {}", target.display()))?;
+                println!("  Moved {} to {}", target.display(), orig.display());
+            }
+            _ => {
+                debug!("Removing {}", target.display());
+                if target.is_dir() && !target.is_symlink() {
+                    std::fs::remove_dir_all(&target)
+                } else {
+                    std::fs::remove_file(&target)
+                }
+                .with_context(|| format!("failed to remove {}", target.display()))?;
+                println!("  Removed {}", target.display());
+            }
+        }
+        cleaned.push(target);
+    }
+
+    if cleaned.is_empty() {
+        println!("  (no installed files found to clean)");
+    }
+
+    Ok(cleaned)
+}
+
+/// Existing targets the items of `dep` would be installed to, for a
+/// dependency the ledger has no record of.
+fn unrecorded_targets(
+    dep: &Dependency,
+    project_root: &std::path::Path,
+    providers: &[AgentProvider],
+    scope: &FileScope,
+    offline: bool,
+) -> Vec<PathBuf> {
     // Resolve the source to get the file mappings
-    let scan_result = resolve_source(source, None);
+    let scan_result = resolve_source(
+        dep.source(),
+        dep.git_ref(),
+        dep.subdir(),
+        archive_checksum(dep),
+        dep.paths(),
+        FetchOptions {
+            offline,
+            verify: None,
+            sources: None,
+        },
+        &mut Progress::Print,
+    );
 
     let files = match scan_result {
-        Ok((_, files)) => files,
+        Ok((_, files, _)) => scanner::select(files, dep.pick(), dep.exclude()).0,
         Err(_) => {
             println!("  (could not resolve source for cleanup — skipping file deletion)");
-            return Ok(());
+            return vec![];
         }
     };
 
-    let mut cleaned = 0;
+    // Renamed items were installed under their new name
+    let renames = dep.rename().cloned().unwrap_or_default();
+    let mut targets = Vec::new();
     for file in &files {
         for provider in providers {
             if !provider.supports_kind(&file.kind) {
@@ -490,47 +1749,39 @@ fn clean_installed_files(
                 Err(_) => continue,
             };
 
-            let file_name = match file.path.file_name() {
-                Some(name) => name,
-                None => continue,
+            let Ok(target_path) = installer::resolve_target_path(file, &target_dir, &renames)
+            else {
+                continue;
             };
-
-            let target_path = target_dir.join(file_name);
             if target_path.exists() || target_path.is_symlink() {
-                debug!("Removing {}", target_path.display());
-                if target_path.is_dir() && !target_path.is_symlink() {
-                    std::fs::remove_dir_all(&target_path)
-                        .with_context(|| format!("failed to remove {}", target_path.display()))?;
-                } else {
-                    std::fs::remove_file(&target_path)
-                        .with_context(|| format!("failed to remove {}", target_path.display()))?;
-                }
-                println!("  Removed {}", target_path.display());
-                cleaned += 1;
+                targets.push(target_path);
             }
         }
     }
-
-    if cleaned == 0 {
-        println!("  (no installed files found to clean)");
-    }
-
-    Ok(())
+    targets
 }
 
-pub fn cmd_list(root: PathBuf) -> Result<()> {
-    debug!("cmd_list: root={}", root.display());
+pub fn cmd_list(root: PathBuf, scope: FileScope, data_dir: PathBuf) -> Result<()> {
+    debug!("cmd_list: root={}, scope={}", root.display(), scope);
     let project_root = root
         .canonicalize()
         .context("could not resolve project root")?;
 
-    let manifest_path = project_root.join("agentfiles.json");
+    let manifest_path = manifest::manifest_path(&scope, &project_root)?;
     if !manifest_path.is_file() {
-        println!("No agentfiles.json found. Run 'agentfiles init' to create one.");
+        match scope {
+            FileScope::Project => {
+                println!("No agentfiles.json found. Run 'agentfiles init' to create one.")
+            }
+            FileScope::Global | FileScope::Local => println!(
+                "No manifest found at {}. Add a dependency with 'agentfiles install -s {scope} <source>'.",
+                manifest_path.display()
+            ),
+        }
         return Ok(());
     }
 
-    let loaded = manifest::load_manifest(&project_root)?;
+    let loaded = manifest::load_manifest(&manifest_path)?;
 
     println!("{} v{}", loaded.name, loaded.version);
 
@@ -541,38 +1792,95 @@ pub fn cmd_list(root: PathBuf) -> Result<()> {
 
     if loaded.dependencies.is_empty() {
         println!("No dependencies. Add one with 'agentfiles install <source>'.");
-        return Ok(());
+    } else {
+        println!("{} dependency(ies):\n", loaded.dependencies.len());
+        for dep in &loaded.dependencies {
+            print_dependency(dep);
+        }
     }
 
-    println!("{} dependency(ies):\n", loaded.dependencies.len());
-    for dep in &loaded.dependencies {
-        let source = dep.source();
-        let mut details = Vec::new();
-
-        if let Some(r) = dep.git_ref() {
-            details.push(format!("ref={r}"));
-        }
-        if let Some(picks) = dep.pick() {
-            details.push(format!("pick=[{}]", picks.join(", ")));
-        }
-        if let Some(strategy) = dep.strategy() {
-            details.push(format!("strategy={strategy}"));
-        }
-        if let Some(paths) = dep.paths() {
-            let path_strs: Vec<&str> = paths.iter().map(|p| p.path.as_str()).collect();
-            details.push(format!("paths=[{}]", path_strs.join(", ")));
+    // The personal manifest is layered over the project one at install time
+    if scope == FileScope::Project {
+        let local_path = manifest::manifest_path(&FileScope::Local, &project_root)?;
+        if local_path.is_file() {
+            let local = manifest::load_manifest(&local_path)?;
+            if !local.dependencies.is_empty() {
+                println!(
+                    "\n{} local dependency(ies) ({}):\n",
+                    local.dependencies.len(),
+                    manifest::LOCAL_MANIFEST_FILE
+                );
+                for dep in &local.dependencies {
+                    print_dependency(dep);
+                }
+            }
         }
+    }
 
-        if details.is_empty() {
-            println!("  {source}");
-        } else {
-            println!("  {source} ({})", details.join(", "));
-        }
+    print_drift(&data_dir, &ledger_root(&scope, &project_root)?)?;
+    Ok(())
+}
+
+/// List installed targets under `root` that no longer match what was
+/// installed, so hand edits are noticed before the next install.
+fn print_drift(data_dir: &Path, root: &Path) -> Result<()> {
+    let ledger = ledger::Ledger::load(data_dir, root)?;
+    let drifted: Vec<_> = ledger
+        .entries
+        .iter()
+        .filter_map(|e| e.drift().map(|d| (e, d)))
+        .collect();
+    if drifted.is_empty() {
+        return Ok(());
     }
 
+    println!(
+        "\n{} installed file(s) changed since install:\n",
+        drifted.len()
+    );
+    for (entry, drift) in drifted {
+        let status = match drift {
+            ledger::Drift::Modified => "modified",
+            ledger::Drift::Missing => "missing ",
+        };
+        let target = entry.target.strip_prefix(root).unwrap_or(&entry.target);
+        println!("  {status} {} (from {})", target.display(), entry.source);
+    }
     Ok(())
 }
 
+/// Print one dependency line for `cmd_list`, with any configured details.
+fn print_dependency(dep: &Dependency) {
+    let source = dep.source();
+    let mut details = Vec::new();
+
+    if let Some(r) = dep.git_ref() {
+        details.push(format!("ref={r}"));
+    }
+    if let Some(picks) = dep.pick() {
+        details.push(format!("pick=[{}]", picks.join(", ")));
+    }
+    if !dep.exclude().is_empty() {
+        details.push(format!("exclude=[{}]", dep.exclude().join(", ")));
+    }
+    if let Some(strategy) = dep.strategy() {
+        details.push(format!("strategy={strategy}"));
+    }
+    if let Some(paths) = dep.paths() {
+        let path_strs: Vec<&str> = paths.iter().map(|p| p.path.as_str()).collect();
+        details.push(format!("paths=[{}]", path_strs.join(", ")));
+    }
+    if let Some(subdir) = dep.subdir() {
+        details.push(format!("subdir={subdir}"));
+    }
+
+    if details.is_empty() {
+        println!("  {source}");
+    } else {
+        println!("  {source} ({})", details.join(", "));
+    }
+}
+
 pub fn cmd_matrix() -> Result<()> {
     let kinds = [FileKind::Skill, FileKind::Command, FileKind::Agent];
     let providers = AgentProvider::ALL;
@@ -608,6 +1916,293 @@ pub fn cmd_matrix() -> Result<()> {
     Ok(())
 }
 
+pub fn cmd_cache_list(cache_root: PathBuf) -> Result<()> {
+    debug!("cmd_cache_list: cache_root={}", cache_root.display());
+    let entries = cache::list_entries(&cache_root)?;
+    if entries.is_empty() {
+        println!("Cache is empty ({}).", cache_root.display());
+        return Ok(());
+    }
+
+    let total: u64 = entries.iter().map(|e| e.size).sum();
+    println!(
+        "{} cached source(s), {} in {}:\n",
+        entries.len(),
+        cache::format_size(total),
+        cache_root.display()
+    );
+    for entry in &entries {
+        let Some(meta) = &entry.meta else {
+            println!(
+                "  (unknown source) {} ({})",
+                entry.dir.display(),
+                cache::format_size(entry.size)
+            );
+            continue;
+        };
+        let checkout = match (&meta.checksum, &meta.git_ref, &meta.commit) {
+            (Some(sum), _, _) => format!("sha256:{}", &sum[..sum.len().min(12)]),
+            (None, Some(r), Some(c)) => format!("{r} @ {}", &c[..c.len().min(12)]),
+            (None, None, Some(c)) => format!("default branch @ {}", &c[..c.len().min(12)]),
+            (None, Some(r), None) => r.clone(),
+            (None, None, None) => "default branch".to_string(),
+        };
+        println!(
+            "  {} ({}, {}, fetched {})",
+            credentials::redact(&meta.url, git::remote_config()),
+            checkout,
+            cache::format_size(entry.size),
+            cache::format_age(meta.fetched_at)
+        );
+    }
+    Ok(())
+}
+
+pub fn cmd_cache_prune(
+    cache_root: PathBuf,
+    data_dir: PathBuf,
+    older_than: Option<u64>,
+    dry_run: bool,
+) -> Result<()> {
+    debug!(
+        "cmd_cache_prune: cache_root={}, older_than={:?}",
+        cache_root.display(),
+        older_than
+    );
+    let referenced = referenced_remotes(&data_dir)?;
+    let cutoff = older_than.map(|days| cache::now().saturating_sub(days * 86400));
+
+    let prefix = if dry_run { "[dry-run] " } else { "" };
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    let mut freed = 0;
+    let mut removed = 0;
+    let mut removed_worktrees = 0;
+    for entry in cache::list_entries(&cache_root)? {
+        let uses = entry.url().and_then(|url| {
+            referenced
+                .get(url)
+                .or_else(|| referenced.get(&remote_key(url)))
+        });
+        let expired = cutoff.is_some_and(|cutoff| entry.last_used < cutoff);
+        if let Some(uses) = uses
+            && !expired
+        {
+            // The repository is in use, but maybe not every commit of it
+            for commit in unused_worktrees(&entry.dir, uses) {
+                let size = cache::dir_size(&git::worktree_path(&entry.dir, &commit));
+                if !dry_run {
+                    git::remove_worktree(&entry.dir, &commit)?;
+                }
+                println!(
+                    "{prefix}{verb} {} checkout of {} (unreferenced, {})",
+                    entry.url().unwrap_or("unknown source"),
+                    &commit[..commit.len().min(12)],
+                    cache::format_size(size)
+                );
+                freed += size;
+                removed_worktrees += 1;
+            }
+            continue;
+        }
+
+        if !dry_run {
+            cache::remove_entry(&entry.dir)?;
+        }
+        let reason = if uses.is_some() {
+            "stale"
+        } else {
+            "unreferenced"
+        };
+        println!(
+            "{prefix}{verb} {} ({reason}, {})",
+            entry.url().unwrap_or("unknown source"),
+            cache::format_size(entry.size)
+        );
+        freed += entry.size;
+        removed += 1;
+    }
+
+    println!(
+        "{prefix}{verb} {removed} cache entry(ies) and {removed_worktrees} checkout(s), {} freed.",
+        cache::format_size(freed)
+    );
+    Ok(())
+}
+
+/// What known manifests and installs use of one remote source.
+#[derive(Debug, Default)]
+struct RemoteUses {
+    /// Refs named by manifest dependencies (`None` for the default branch).
+    refs: HashSet<Option<String>>,
+    /// Commits recorded for installed targets.
+    commits: HashSet<String>,
+    /// Some installed target has no recorded commit (ledgers written before
+    /// commits were recorded), so any commit may be in use.
+    unknown: bool,
+}
+
+/// Commits checked out in the cache entry at `dir` that `uses` doesn't
+/// reference. Nothing is unused when some reference can't be resolved.
+fn unused_worktrees(dir: &std::path::Path, uses: &RemoteUses) -> Vec<String> {
+    if uses.unknown {
+        return Vec::new();
+    }
+    let mut in_use = uses.commits.clone();
+    for git_ref in &uses.refs {
+        match git::cached_commit(dir, git_ref.as_deref()) {
+            Some(commit) => in_use.insert(commit),
+            None => return Vec::new(),
+        };
+    }
+    git::cached_worktrees(dir)
+        .into_iter()
+        .filter(|commit| !in_use.contains(commit))
+        .collect()
+}
+
+/// Every remote source that some known manifest or install still refers to,
+/// keyed by cache key: the user manifest, plus the manifests and ledger
+/// entries of every project agentfiles has installed into.
+///
+/// Keys are redacted like the URLs in cache metadata, so the two compare
+/// equal for sources with credentials in their URL.
+fn referenced_remotes(data_dir: &std::path::Path) -> Result<HashMap<String, RemoteUses>> {
+    let mut manifests = vec![manifest::manifest_path(
+        &FileScope::Global,
+        std::path::Path::new("."),
+    )?];
+    let mut referenced: HashMap<String, RemoteUses> = HashMap::new();
+    for ledger in ledger::Ledger::load_all(data_dir)? {
+        manifests.push(manifest::manifest_path(&FileScope::Project, &ledger.root)?);
+        manifests.push(manifest::manifest_path(&FileScope::Local, &ledger.root)?);
+        for entry in ledger.entries {
+            let Some(key) = remote_cache_key(&entry.source) else {
+                continue;
+            };
+            let uses = referenced.entry(key).or_default();
+            match entry.commit {
+                Some(commit) => {
+                    uses.commits.insert(commit);
+                }
+                None => uses.unknown = true,
+            }
+        }
+    }
+    for path in manifests.iter().filter(|p| p.is_file()) {
+        let m = match manifest::load_manifest(path) {
+            Ok(m) => m,
+            Err(err) => {
+                debug!("Skipping unreadable manifest {}: {err:#}", path.display());
+                continue;
+            }
+        };
+        for dep in &m.dependencies {
+            let Some(key) = remote_cache_key(dep.source()) else {
+                continue;
+            };
+            let git_ref = match dep.git_ref() {
+                Some(git_ref) => Some(git_ref.to_string()),
+                None => git::parse_remote(dep.source()).git_ref,
+            };
+            referenced.entry(key).or_default().refs.insert(git_ref);
+        }
+    }
+    Ok(referenced)
+}
+
+/// Cache key of a remote `source` (see `referenced_remotes`), or `None` for
+/// local sources.
+fn remote_cache_key(source: &str) -> Option<String> {
+    let key = if archive::is_archive(source) {
+        archive::is_remote_archive(source).then(|| source.to_string())?
+    } else if git::is_git_url(source) {
+        remote_key(&git::parse_remote(source).url)
+    } else {
+        return None;
+    };
+    Some(credentials::redact(&key, git::remote_config()))
+}
+
+/// Comparable form of a remote URL (ignores `.git` suffixes and the like).
+fn remote_key(url: &str) -> String {
+    git::normalize_source(url)
+}
+
+pub fn cmd_cache_clear(cache_root: PathBuf, source: Option<String>) -> Result<()> {
+    debug!("cmd_cache_clear: source={:?}", source);
+    let Some(source) = source else {
+        let entries = cache::list_entries(&cache_root)?;
+        let freed: u64 = entries.iter().map(|e| e.size).sum();
+        for entry in &entries {
+            cache::remove_entry(&entry.dir)?;
+        }
+        println!(
+            "Removed {} cache entry(ies), {} freed.",
+            entries.len(),
+            cache::format_size(freed)
+        );
+        return Ok(());
+    };
+
+    let dir = cache_entry_dir(&cache_root, &source)?;
+    if !dir.is_dir() {
+        println!("No cache entry for {source}.");
+        return Ok(());
+    }
+    cache::remove_entry(&dir)?;
+    println!("Removed cache entry for {source} ({}).", dir.display());
+    Ok(())
+}
+
+pub fn cmd_cache_path(cache_root: PathBuf, source: Option<String>) -> Result<()> {
+    match source {
+        Some(source) => println!("{}", cache_entry_dir(&cache_root, &source)?.display()),
+        None => println!("{}", cache_root.display()),
+    }
+    Ok(())
+}
+
+/// Cache entry directory for a remote `source`.
+fn cache_entry_dir(cache_root: &std::path::Path, source: &str) -> Result<PathBuf> {
+    if archive::is_archive(source) {
+        let key = if archive::is_remote_archive(source) {
+            source.to_string()
+        } else {
+            std::fs::canonicalize(source)
+                .with_context(|| format!("archive not found: {source}"))?
+                .to_string_lossy()
+                .into_owned()
+        };
+        return Ok(cache_root.join(git::hash_url(&key)));
+    }
+    if !git::is_git_url(source) {
+        anyhow::bail!("'{source}' is not a remote source; local sources are not cached");
+    }
+    Ok(cache_root.join(git::hash_url(&git::parse_remote(source).url)))
+}
+
+pub fn cmd_store_gc(data_dir: PathBuf, dry_run: bool) -> Result<()> {
+    debug!("cmd_store_gc: data_dir={}", data_dir.display());
+    let report = store::gc(&data_dir, dry_run)?;
+
+    let prefix = if dry_run { "[dry-run] " } else { "" };
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    for path in &report.removed {
+        println!("{prefix}{verb} {}", path.display());
+    }
+    println!(
+        "{prefix}{verb} {} snapshot(s), {} still in use.",
+        report.removed.len(),
+        report.kept
+    );
+    Ok(())
+}
+
+pub fn cmd_store_path(data_dir: PathBuf) -> Result<()> {
+    println!("{}", store::store_dir(&data_dir).display());
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -623,7 +2218,7 @@ mod tests {
         fs::write(skill_dir.join("SKILL.md"), "test skill")?;
 
         let source = dir.path().to_string_lossy().into_owned();
-        let result = cmd_scan(source);
+        let result = cmd_scan(source, false);
         assert!(result.is_ok());
         Ok(())
     }
@@ -632,14 +2227,17 @@ mod tests {
     fn scan_local_empty() -> Result<()> {
         let dir = TempDir::new()?;
         let source = dir.path().to_string_lossy().into_owned();
-        let result = cmd_scan(source);
+        let result = cmd_scan(source, false);
         assert!(result.is_ok());
         Ok(())
     }
 
     #[test]
     fn scan_local_nonexistent_path() {
-        let result = cmd_scan("/tmp/this-path-definitely-does-not-exist-agentfiles".into());
+        let result = cmd_scan(
+            "/tmp/this-path-definitely-does-not-exist-agentfiles".into(),
+            false,
+        );
         assert!(result.is_err());
     }
 
@@ -676,9 +2274,16 @@ mod tests {
             providers: None,
             strategy: None,
             pick: None,
+            exclude: None,
+            alias: None,
             no_save: false,
             dry_run: false,
+            offline: false,
+            jobs: None,
+            on_conflict: OnConflict::Refuse,
             root: dir.path().to_path_buf(),
+            data_dir: dir.path().join("data"),
+            config_dir: dir.path().join("config"),
         });
         assert!(result.is_err());
     }
@@ -696,14 +2301,255 @@ mod tests {
             providers: None,
             strategy: None,
             pick: None,
+            exclude: None,
+            alias: None,
             no_save: false,
             dry_run: false,
+            offline: false,
+            jobs: None,
+            on_conflict: OnConflict::Refuse,
             root: dir.path().to_path_buf(),
+            data_dir: dir.path().join("data"),
+            config_dir: dir.path().join("config"),
         });
         assert!(result.is_ok());
         Ok(())
     }
 
+    #[test]
+    fn install_names_the_filters_that_left_nothing() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+        fs::create_dir_all(src_dir.path().join("commands"))?;
+        fs::write(src_dir.path().join("commands/deploy.md"), "# Deploy")?;
+
+        let install = |pick: Option<&str>, exclude: Option<&str>| {
+            cmd_install(InstallOptions {
+                source: Some(src_dir.path().to_string_lossy().into_owned()),
+                scope: FileScope::Project,
+                providers: Some(vec![AgentProvider::ClaudeCode]),
+                strategy: None,
+                pick: pick.map(|p| vec![p.to_string()]),
+                exclude: exclude.map(|e| vec![e.to_string()]),
+                alias: None,
+                no_save: true,
+                dry_run: true,
+                offline: false,
+                jobs: None,
+                on_conflict: OnConflict::Refuse,
+                root: dst_dir.path().to_path_buf(),
+                data_dir: src_dir.path().join("data"),
+                config_dir: src_dir.path().join("config"),
+            })
+            .unwrap_err()
+            .to_string()
+        };
+        assert_eq!(
+            install(None, Some("commands/*")),
+            "no items left after --exclude"
+        );
+        assert_eq!(install(Some("lint"), None), "no items left after --pick");
+        assert_eq!(
+            install(Some("deploy"), Some("deploy")),
+            "no items left after --pick/--exclude"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn install_protects_local_changes() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+        fs::create_dir_all(src_dir.path().join("commands"))?;
+        fs::write(src_dir.path().join("commands/deploy.md"), "# Deploy")?;
+        fs::write(src_dir.path().join("commands/lint.md"), "# Lint")?;
+        let install = |on_conflict| {
+            cmd_install(InstallOptions {
+                source: Some(src_dir.path().to_string_lossy().into_owned()),
+                scope: FileScope::Project,
+                providers: Some(vec![AgentProvider::ClaudeCode]),
+                strategy: None,
+                pick: None,
+                exclude: None,
+                alias: None,
+                no_save: true,
+                dry_run: false,
+                offline: false,
+                jobs: None,
+                on_conflict,
+                root: dst_dir.path().to_path_buf(),
+                data_dir: src_dir.path().join("data"),
+                config_dir: src_dir.path().join("config"),
+            })
+        };
+        let commands = dst_dir.path().canonicalize()?.join(".claude/commands");
+        fs::create_dir_all(&commands)?;
+        fs::write(commands.join("lint.md"), "# My own lint")?;
+
+        // Files agentfiles didn't create are never overwritten by default
+        let err = install(OnConflict::Refuse).unwrap_err().to_string();
+        assert!(err.contains("lint.md (not installed by agentfiles)"));
+        assert!(err.contains("+# Lint"));
+        assert!(!commands.join("deploy.md").exists());
+
+        install(OnConflict::Keep)?;
+        assert_eq!(
+            fs::read_to_string(commands.join("lint.md"))?,
+            "# My own lint"
+        );
+        assert!(commands.join("deploy.md").exists());
+
+        // An installed file edited afterwards is a conflict too
+        fs::write(commands.join("deploy.md"), "# Deploy, tweaked")?;
+        let err = install(OnConflict::Refuse).unwrap_err().to_string();
+        assert!(err.contains("deploy.md (modified locally)"));
+        assert!(err.contains("-# Deploy, tweaked"));
+
+        install(OnConflict::Backup)?;
+        assert_eq!(fs::read_to_string(commands.join("deploy.md"))?, "# Deploy");
+        assert_eq!(
+            fs::read_to_string(commands.join("deploy.md.orig"))?,
+            "# Deploy, tweaked"
+        );
+        assert_eq!(
+            fs::read_to_string(commands.join("lint.md.orig"))?,
+            "# My own lint"
+        );
+
+        // Unchanged installs are replaced without asking
+        install(OnConflict::Refuse)?;
+        Ok(())
+    }
+
+    #[test]
+    fn remove_clean_protects_local_changes() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+        fs::create_dir_all(src_dir.path().join("commands"))?;
+        fs::write(src_dir.path().join("commands/deploy.md"), "# Deploy")?;
+        fs::write(src_dir.path().join("commands/lint.md"), "# Lint")?;
+        let source = src_dir.path().to_string_lossy().into_owned();
+        let data_dir = src_dir.path().join("data");
+        cmd_install(InstallOptions {
+            source: Some(source.clone()),
+            scope: FileScope::Project,
+            providers: Some(vec![AgentProvider::ClaudeCode]),
+            strategy: None,
+            pick: None,
+            exclude: None,
+            alias: None,
+            no_save: false,
+            dry_run: false,
+            offline: false,
+            jobs: None,
+            on_conflict: OnConflict::Refuse,
+            root: dst_dir.path().to_path_buf(),
+            data_dir: data_dir.clone(),
+            config_dir: src_dir.path().join("config"),
+        })?;
+        let remove = |on_conflict, data_dir: &Path| {
+            cmd_remove(RemoveOptions {
+                source: source.clone(),
+                clean: true,
+                scope: FileScope::Project,
+                providers: None,
+                on_conflict,
+                offline: false,
+                root: dst_dir.path().to_path_buf(),
+                data_dir: data_dir.to_path_buf(),
+            })
+        };
+        let commands = dst_dir.path().canonicalize()?.join(".claude/commands");
+        fs::write(commands.join("deploy.md"), "# Deploy, tweaked")?;
+
+        // Nothing is deleted, and the dependency stays, while a file is edited
+        let err = remove(OnConflict::Refuse, &data_dir)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("deploy.md (modified locally)"));
+        assert!(commands.join("lint.md").exists());
+        let manifest = manifest::load_manifest(&dst_dir.path().join("agentfiles.json"))?;
+        assert_eq!(manifest.dependencies.len(), 1);
+
+        // Without a ledger, agentfiles can't tell its files from the user's
+        let err = remove(OnConflict::Refuse, &dst_dir.path().join("no-ledger"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("lint.md (not installed by agentfiles)"));
+
+        remove(OnConflict::Keep, &data_dir)?;
+        assert!(!commands.join("lint.md").exists());
+        assert_eq!(
+            fs::read_to_string(commands.join("deploy.md"))?,
+            "# Deploy, tweaked"
+        );
+        let ledger = ledger::Ledger::load(&data_dir, &dst_dir.path().canonicalize()?)?;
+        assert!(ledger.entries.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn install_without_ledger_adopts_earlier_installs() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+        fs::create_dir_all(src_dir.path().join("commands"))?;
+        fs::write(src_dir.path().join("commands/deploy.md"), "# Deploy v2")?;
+        fs::write(src_dir.path().join("commands/lint.md"), "# Lint")?;
+        let source = src_dir.path().to_string_lossy().into_owned();
+
+        // Installed before ledgers existed: lint.md as is, deploy.md from
+        // an older version of the source
+        let commands = dst_dir.path().canonicalize()?.join(".claude/commands");
+        fs::create_dir_all(&commands)?;
+        fs::write(commands.join("lint.md"), "# Lint")?;
+        fs::write(commands.join("deploy.md"), "# Deploy v1")?;
+
+        let data_dir = src_dir.path().join("data");
+        let install = |source: Option<String>| {
+            cmd_install(InstallOptions {
+                source,
+                scope: FileScope::Project,
+                providers: Some(vec![AgentProvider::ClaudeCode]),
+                strategy: None,
+                pick: None,
+                exclude: None,
+                alias: None,
+                no_save: true,
+                dry_run: false,
+                offline: false,
+                jobs: None,
+                on_conflict: OnConflict::Refuse,
+                root: dst_dir.path().to_path_buf(),
+                data_dir: data_dir.clone(),
+                config_dir: src_dir.path().join("config"),
+            })
+        };
+        // A source outside the manifest only adopts identical targets
+        let err = install(Some(source.clone())).unwrap_err().to_string();
+        assert!(err.contains("refusing to overwrite 1 file(s)"));
+        assert!(err.contains("deploy.md (not installed by agentfiles)"));
+
+        // A manifest dependency adopts all of its targets, and records them
+        let mut m = manifest::Manifest::default().with_name("test".to_string());
+        m.dependencies
+            .push(manifest::Dependency::Simple(source.clone()));
+        manifest::save_manifest(&m, dst_dir.path())?;
+        install(None)?;
+        assert_eq!(
+            fs::read_to_string(commands.join("deploy.md"))?,
+            "# Deploy v2"
+        );
+        let ledger = ledger::Ledger::load(&data_dir, dst_dir.path())?;
+        assert_eq!(ledger.entries.len(), 2);
+
+        // Once recorded, a file it didn't install is protected again
+        fs::write(src_dir.path().join("commands/review.md"), "# Review")?;
+        fs::write(commands.join("review.md"), "# My review")?;
+        let err = install(None).unwrap_err().to_string();
+        assert!(err.contains("review.md (not installed by agentfiles)"));
+        Ok(())
+    }
+
     #[test]
     fn install_source_auto_saves_to_manifest() -> Result<()> {
         let src_dir = TempDir::new()?;
@@ -722,9 +2568,16 @@ mod tests {
             providers: None,
             strategy: None,
             pick: None,
+            exclude: None,
+            alias: None,
             no_save: false,
             dry_run: false,
+            offline: false,
+            jobs: None,
+            on_conflict: OnConflict::Refuse,
             root: dst_dir.path().to_path_buf(),
+            data_dir: src_dir.path().join("data"),
+            config_dir: src_dir.path().join("config"),
         })?;
 
         // agentfiles.json should be created in the project root
@@ -754,13 +2607,804 @@ mod tests {
             providers: None,
             strategy: None,
             pick: None,
+            exclude: None,
+            alias: None,
             no_save: true,
             dry_run: false,
+            offline: false,
+            jobs: None,
+            on_conflict: OnConflict::Refuse,
             root: dst_dir.path().to_path_buf(),
+            data_dir: src_dir.path().join("data"),
+            config_dir: src_dir.path().join("config"),
         })?;
 
         // agentfiles.json should NOT be created
         assert!(!dst_dir.path().join("agentfiles.json").exists());
         Ok(())
     }
+
+    #[test]
+    fn install_local_scope_saves_and_excludes() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+
+        let skill_dir = src_dir.path().join("skills").join("review");
+        fs::create_dir_all(&skill_dir)?;
+        fs::write(skill_dir.join("SKILL.md"), "# Review")?;
+
+        let status = std::process::Command::new("git")
+            .args(["init", "-q"])
+            .current_dir(dst_dir.path())
+            .status()?;
+        assert!(status.success());
+
+        cmd_install(InstallOptions {
+            source: Some(src_dir.path().to_string_lossy().into_owned()),
+            scope: FileScope::Local,
+            providers: Some(vec![AgentProvider::ClaudeCode]),
+            strategy: None,
+            pick: None,
+            exclude: None,
+            alias: None,
+            no_save: false,
+            dry_run: false,
+            offline: false,
+            jobs: None,
+            on_conflict: OnConflict::Refuse,
+            root: dst_dir.path().to_path_buf(),
+            data_dir: src_dir.path().join("data"),
+            config_dir: src_dir.path().join("config"),
+        })?;
+
+        // Saved to the personal manifest, not the shared one
+        assert!(!dst_dir.path().join("agentfiles.json").exists());
+        let local = manifest::load_manifest(&dst_dir.path().join("agentfiles.local.json"))?;
+        assert_eq!(local.dependencies.len(), 1);
+
+        let exclude = fs::read_to_string(dst_dir.path().join(".git/info/exclude"))?;
+        let entries = ignore::read_block(&exclude, "local");
+        assert_eq!(
+            entries,
+            vec!["/.claude/skills/review", "/agentfiles.local.json"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn install_maintains_gitignore_block_when_enabled() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+
+        let cmd_dir = src_dir.path().join("commands");
+        fs::create_dir_all(&cmd_dir)?;
+        fs::write(cmd_dir.join("deploy.md"), "# Deploy")?;
+
+        let source = src_dir.path().to_string_lossy().into_owned();
+        let mut m = manifest::Manifest::default().with_name("test".to_string());
+        m.gitignore = true;
+        m.dependencies
+            .push(manifest::Dependency::Simple(source.clone()));
+        manifest::save_manifest(&m, dst_dir.path())?;
+
+        // A hand-authored command next to the installed one
+        let commands = dst_dir.path().join(".claude/commands");
+        fs::create_dir_all(&commands)?;
+        fs::write(commands.join("mine.md"), "# Mine")?;
+        fs::write(dst_dir.path().join(".gitignore"), "target/\n")?;
+
+        let opts = |source: Option<String>| InstallOptions {
+            source,
+            scope: FileScope::Project,
+            providers: Some(vec![AgentProvider::ClaudeCode]),
+            strategy: None,
+            pick: None,
+            exclude: None,
+            alias: None,
+            no_save: false,
+            dry_run: false,
+            offline: false,
+            jobs: None,
+            on_conflict: OnConflict::Refuse,
+            root: dst_dir.path().to_path_buf(),
+            data_dir: src_dir.path().join("data"),
+            config_dir: src_dir.path().join("config"),
+        };
+        cmd_install(opts(None))?;
+
+        let gitignore = fs::read_to_string(dst_dir.path().join(".gitignore"))?;
+        assert!(gitignore.starts_with("target/\n"));
+        assert_eq!(
+            ignore::read_block(&gitignore, GITIGNORE_LABEL),
+            vec!["/.claude/commands/deploy.md"]
+        );
+
+        cmd_remove(RemoveOptions {
+            source,
+            clean: true,
+            scope: FileScope::Project,
+            providers: Some(vec![AgentProvider::ClaudeCode]),
+            on_conflict: OnConflict::Refuse,
+            offline: false,
+            root: dst_dir.path().to_path_buf(),
+            data_dir: src_dir.path().join("data"),
+        })?;
+        let gitignore = fs::read_to_string(dst_dir.path().join(".gitignore"))?;
+        assert_eq!(gitignore, "target/\n");
+        assert!(commands.join("mine.md").exists());
+        Ok(())
+    }
+
+    #[test]
+    fn failed_ignore_update_keeps_ledger_in_step() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+        let deploy = src_dir.path().join("commands/deploy.md");
+        fs::create_dir_all(deploy.parent().unwrap())?;
+        fs::write(&deploy, "# Deploy v1")?;
+
+        let mut m = manifest::Manifest::default().with_name("test".to_string());
+        m.gitignore = true;
+        manifest::save_manifest(&m, dst_dir.path())?;
+        let install = || {
+            cmd_install(InstallOptions {
+                source: Some(src_dir.path().to_string_lossy().into_owned()),
+                scope: FileScope::Project,
+                providers: Some(vec![AgentProvider::ClaudeCode]),
+                strategy: None,
+                pick: None,
+                exclude: None,
+                alias: None,
+                no_save: false,
+                dry_run: false,
+                offline: false,
+                jobs: None,
+                on_conflict: OnConflict::Refuse,
+                root: dst_dir.path().to_path_buf(),
+                data_dir: src_dir.path().join("data"),
+                config_dir: src_dir.path().join("config"),
+            })
+        };
+        install()?;
+
+        // Writing .gitignore fails after the update is in place
+        fs::write(&deploy, "# Deploy v2")?;
+        let gitignore = dst_dir.path().join(".gitignore");
+        fs::remove_file(&gitignore)?;
+        fs::create_dir(&gitignore)?;
+        assert!(install().is_err());
+
+        // The ledger matches what is installed, so nothing looks modified
+        fs::remove_dir(&gitignore)?;
+        install()?;
+        let target = dst_dir.path().join(".claude/commands/deploy.md");
+        assert_eq!(fs::read_to_string(target)?, "# Deploy v2");
+        Ok(())
+    }
+
+    #[test]
+    fn provider_subset_keeps_other_gitignore_entries() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+        fs::create_dir_all(src_dir.path().join("commands"))?;
+        fs::write(src_dir.path().join("commands/deploy.md"), "# Deploy")?;
+        fs::write(src_dir.path().join("commands/lint.md"), "# Lint")?;
+
+        let mut m = manifest::Manifest::default().with_name("test".to_string());
+        m.gitignore = true;
+        m.dependencies.push(manifest::Dependency::Simple(
+            src_dir.path().to_string_lossy().into_owned(),
+        ));
+        manifest::save_manifest(&m, dst_dir.path())?;
+
+        let install = |providers| {
+            cmd_install(InstallOptions {
+                source: None,
+                scope: FileScope::Project,
+                providers: Some(providers),
+                strategy: None,
+                pick: None,
+                exclude: None,
+                alias: None,
+                no_save: false,
+                dry_run: false,
+                offline: false,
+                jobs: None,
+                on_conflict: OnConflict::Refuse,
+                root: dst_dir.path().to_path_buf(),
+                data_dir: src_dir.path().join("data"),
+                config_dir: src_dir.path().join("config"),
+            })
+        };
+        let entries = || -> Result<Vec<String>> {
+            let gitignore = fs::read_to_string(dst_dir.path().join(".gitignore"))?;
+            Ok(ignore::read_block(&gitignore, GITIGNORE_LABEL))
+        };
+        install(vec![AgentProvider::ClaudeCode, AgentProvider::Cursor])?;
+        let both = entries()?;
+        assert_eq!(both.len(), 4);
+
+        // Installing for one provider leaves the other's entries in place
+        install(vec![AgentProvider::Cursor])?;
+        assert_eq!(entries()?, both);
+
+        // Entries for targets removed since are dropped
+        fs::remove_file(dst_dir.path().join(".claude/commands/lint.md"))?;
+        install(vec![AgentProvider::Cursor])?;
+        let remaining = entries()?;
+        assert_eq!(remaining.len(), 3);
+        assert!(!remaining.contains(&"/.claude/commands/lint.md".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn parallel_install_keeps_manifest_order() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+
+        // Every source ships a `deploy` command, so the last one must win
+        let mut m = manifest::Manifest::default().with_name("test".to_string());
+        for i in 0..6 {
+            let cmd_dir = src_dir.path().join(format!("src{i}/commands"));
+            fs::create_dir_all(&cmd_dir)?;
+            fs::write(cmd_dir.join("deploy.md"), format!("# Deploy {i}"))?;
+            m.dependencies.push(manifest::Dependency::Simple(
+                src_dir
+                    .path()
+                    .join(format!("src{i}"))
+                    .to_string_lossy()
+                    .into_owned(),
+            ));
+        }
+        manifest::save_manifest(&m, dst_dir.path())?;
+
+        cmd_install(InstallOptions {
+            source: None,
+            scope: FileScope::Project,
+            providers: Some(vec![AgentProvider::ClaudeCode]),
+            strategy: None,
+            pick: None,
+            exclude: None,
+            alias: None,
+            no_save: false,
+            dry_run: false,
+            offline: false,
+            jobs: NonZeroUsize::new(4),
+            on_conflict: OnConflict::Refuse,
+            root: dst_dir.path().to_path_buf(),
+            data_dir: src_dir.path().join("data"),
+            config_dir: src_dir.path().join("config"),
+        })?;
+
+        let installed = fs::read_to_string(dst_dir.path().join(".claude/commands/deploy.md"))?;
+        assert_eq!(installed, "# Deploy 5");
+        Ok(())
+    }
+
+    #[test]
+    fn install_enforces_audit_policy() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+        let skill = src_dir.path().join("skills/setup");
+        fs::create_dir_all(&skill)?;
+        fs::write(skill.join("SKILL.md"), "# Setup")?;
+        fs::write(skill.join("helper.py"), "print('hi')")?;
+
+        let install = |policy: Option<manifest::Policy>| {
+            let mut m = manifest::Manifest::default().with_name("test".to_string());
+            m.policy = Some(manifest::Policy {
+                checks: [(audit::Check::Script, audit::Action::Block)].into(),
+                allow: vec![],
+            });
+            m.dependencies
+                .push(Dependency::Detailed(manifest::DependencySpec {
+                    source: src_dir.path().to_string_lossy().into_owned(),
+                    git_ref: None,
+                    pick: None,
+                    exclude: None,
+                    strategy: None,
+                    paths: None,
+                    subdir: None,
+                    integrity: None,
+                    verify: None,
+                    policy,
+                    patches: None,
+                    vars: None,
+                    when: None,
+                    rename: None,
+                }));
+            manifest::save_manifest_file(&m, &dst_dir.path().join(manifest::MANIFEST_FILE))?;
+            cmd_install(InstallOptions {
+                source: None,
+                scope: FileScope::Project,
+                providers: Some(vec![AgentProvider::ClaudeCode]),
+                strategy: None,
+                pick: None,
+                exclude: None,
+                alias: None,
+                no_save: false,
+                dry_run: false,
+                offline: false,
+                jobs: None,
+                on_conflict: OnConflict::Refuse,
+                root: dst_dir.path().to_path_buf(),
+                data_dir: src_dir.path().join("data"),
+                config_dir: src_dir.path().join("config"),
+            })
+        };
+
+        let err = install(None).unwrap_err();
+        assert!(err.to_string().contains("blocked by policy"));
+        assert!(err.to_string().contains("skills/setup/helper.py"));
+        assert!(!dst_dir.path().join(".claude/skills/setup").exists());
+
+        // The dependency's own allowlist wins over the manifest-wide block
+        install(Some(manifest::Policy {
+            checks: Default::default(),
+            allow: vec!["skills/setup/helper.py".to_string()],
+        }))?;
+        assert!(
+            dst_dir
+                .path()
+                .join(".claude/skills/setup/helper.py")
+                .is_file()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn resolve_dependencies_reports_in_manifest_order() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let cmd_dir = src_dir.path().join("commands");
+        fs::create_dir_all(&cmd_dir)?;
+        fs::write(cmd_dir.join("deploy.md"), "# Deploy")?;
+
+        let source = src_dir.path().to_string_lossy().into_owned();
+        let deps: Vec<_> = [
+            source.clone(),
+            src_dir
+                .path()
+                .join("missing")
+                .to_string_lossy()
+                .into_owned(),
+            source,
+        ]
+        .into_iter()
+        .map(|s| (manifest::Dependency::Simple(s), FileScope::Project))
+        .collect();
+        let ctx = InstallContext {
+            project_root: src_dir.path(),
+            manifest_dir: src_dir.path(),
+            providers: &[AgentProvider::ClaudeCode],
+            strategy_override: None,
+            data_dir: &src_dir.path().join("data"),
+            config_dir: &src_dir.path().join("config"),
+            dry_run: true,
+            offline: false,
+            jobs: NonZeroUsize::new(3).unwrap(),
+            sources: &trust::SourceRules::default(),
+            on_conflict: OnConflict::Refuse,
+        };
+
+        let resolved = resolve_dependencies(&ctx, &deps, &Verification::default());
+        assert_eq!(resolved.len(), 3);
+        assert_eq!(resolved[0].1.as_ref().unwrap().files.len(), 1);
+        let err = resolved[1].1.as_ref().err().unwrap();
+        assert!(err.to_string().contains("source path not found"));
+        assert_eq!(resolved[2].1.as_ref().unwrap().files.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn install_enforces_integrity() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+        let skill = src_dir.path().join("skills/review");
+        fs::create_dir_all(&skill)?;
+        fs::write(skill.join("SKILL.md"), "# Review")?;
+        let reviewed = integrity::format(&store::hash_tree(&skill)?);
+
+        let dep = |integrity| {
+            Dependency::Detailed(manifest::DependencySpec {
+                source: src_dir.path().to_string_lossy().into_owned(),
+                git_ref: None,
+                pick: None,
+                exclude: None,
+                strategy: None,
+                paths: None,
+                subdir: None,
+                integrity: Some(integrity),
+                verify: None,
+                policy: None,
+                patches: None,
+                vars: None,
+                when: None,
+                rename: None,
+            })
+        };
+        let pinned = |hash: &str| {
+            manifest::Integrity::Items([("skills/review".to_string(), hash.to_string())].into())
+        };
+        let ctx = InstallContext {
+            project_root: dst_dir.path(),
+            manifest_dir: dst_dir.path(),
+            providers: &[AgentProvider::ClaudeCode],
+            strategy_override: None,
+            data_dir: &src_dir.path().join("data"),
+            config_dir: &src_dir.path().join("config"),
+            dry_run: false,
+            offline: false,
+            jobs: NonZeroUsize::MIN,
+            sources: &trust::SourceRules::default(),
+            on_conflict: OnConflict::Refuse,
+        };
+
+        assert!(
+            resolve_dependency(
+                &ctx,
+                &dep(pinned(&reviewed)),
+                &Verification::default(),
+                &mut Progress::Print
+            )
+            .is_ok()
+        );
+
+        // Content changed after review
+        fs::write(skill.join("SKILL.md"), "# Review, now with surprises")?;
+        let err = resolve_dependency(
+            &ctx,
+            &dep(pinned(&reviewed)),
+            &Verification::default(),
+            &mut Progress::Print,
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("integrity mismatch"));
+
+        let whole = manifest::Integrity::Dependency(reviewed);
+        assert!(
+            resolve_dependency(
+                &ctx,
+                &dep(whole),
+                &Verification::default(),
+                &mut Progress::Print
+            )
+            .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn verification_refuses_unsigned_sources() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+        fs::create_dir_all(src_dir.path().join("commands"))?;
+        fs::write(src_dir.path().join("commands/deploy.md"), "# Deploy")?;
+
+        let dep = |verify| {
+            Dependency::Detailed(manifest::DependencySpec {
+                source: src_dir.path().to_string_lossy().into_owned(),
+                git_ref: None,
+                pick: None,
+                exclude: None,
+                strategy: None,
+                paths: None,
+                subdir: None,
+                integrity: None,
+                verify,
+                policy: None,
+                patches: None,
+                vars: None,
+                when: None,
+                rename: None,
+            })
+        };
+        let ctx = InstallContext {
+            project_root: dst_dir.path(),
+            manifest_dir: dst_dir.path(),
+            providers: &[AgentProvider::ClaudeCode],
+            strategy_override: None,
+            data_dir: &src_dir.path().join("data"),
+            config_dir: &src_dir.path().join("config"),
+            dry_run: false,
+            offline: false,
+            jobs: NonZeroUsize::MIN,
+            sources: &trust::SourceRules::default(),
+            on_conflict: OnConflict::Refuse,
+        };
+        let required = Verification {
+            default: true,
+            policy: git::SignaturePolicy::default(),
+        };
+
+        // A local directory carries no signature to check
+        let err = resolve_dependency(&ctx, &dep(None), &required, &mut Progress::Print)
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string()
+                .contains("signature verification is required")
+        );
+        // ...unless the dependency opts out
+        assert!(
+            resolve_dependency(&ctx, &dep(Some(false)), &required, &mut Progress::Print).is_ok()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn install_dependency_from_subdir() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+
+        // Only the subdirectory's skills should be picked up
+        let nested = src_dir.path().join("tools/agents/skills/review");
+        fs::create_dir_all(&nested)?;
+        fs::write(nested.join("SKILL.md"), "# Review")?;
+        let top = src_dir.path().join("skills/other");
+        fs::create_dir_all(&top)?;
+        fs::write(top.join("SKILL.md"), "# Other")?;
+
+        let dep = Dependency::Detailed(manifest::DependencySpec {
+            source: src_dir.path().to_string_lossy().into_owned(),
+            git_ref: None,
+            pick: None,
+            exclude: None,
+            strategy: None,
+            paths: None,
+            subdir: Some("tools/agents".to_string()),
+            integrity: None,
+            verify: None,
+            policy: None,
+            patches: None,
+            vars: None,
+            when: None,
+            rename: None,
+        });
+        let ctx = InstallContext {
+            project_root: dst_dir.path(),
+            manifest_dir: dst_dir.path(),
+            providers: &[AgentProvider::ClaudeCode],
+            strategy_override: None,
+            data_dir: &src_dir.path().join("data"),
+            config_dir: &src_dir.path().join("config"),
+            dry_run: false,
+            offline: false,
+            jobs: NonZeroUsize::MIN,
+            sources: &trust::SourceRules::default(),
+            on_conflict: OnConflict::Refuse,
+        };
+        let resolved =
+            resolve_dependency(&ctx, &dep, &Verification::default(), &mut Progress::Print)?;
+        let mut transaction = installer::Transaction::new(false);
+        let results = install_resolved(
+            &ctx,
+            &mut transaction,
+            &ledger::Ledger::default(),
+            dep.source(),
+            resolved,
+            &FileScope::Project,
+            &template::Vars::default(),
+        )?;
+        transaction.commit();
+
+        assert_eq!(results.len(), 1);
+        assert!(
+            dst_dir
+                .path()
+                .join(".claude/skills/review/SKILL.md")
+                .exists()
+        );
+        assert!(!dst_dir.path().join(".claude/skills/other").exists());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn link_install_points_into_store_and_gc_collects_after_remove() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+        let data_dir = TempDir::new()?;
+
+        let skill_dir = src_dir.path().join("skills/review");
+        fs::create_dir_all(&skill_dir)?;
+        fs::write(skill_dir.join("SKILL.md"), "# Review v1")?;
+        let source = src_dir.path().to_string_lossy().into_owned();
+
+        cmd_install(InstallOptions {
+            source: Some(source.clone()),
+            scope: FileScope::Project,
+            providers: Some(vec![AgentProvider::ClaudeCode]),
+            strategy: Some(FileStrategy::Link),
+            pick: None,
+            exclude: None,
+            alias: None,
+            no_save: false,
+            dry_run: false,
+            offline: false,
+            jobs: None,
+            on_conflict: OnConflict::Refuse,
+            root: dst_dir.path().to_path_buf(),
+            data_dir: data_dir.path().to_path_buf(),
+            config_dir: data_dir.path().join("config"),
+        })?;
+
+        let target = dst_dir.path().join(".claude/skills/review");
+        let store = store::store_dir(data_dir.path());
+        assert!(fs::read_link(&target)?.starts_with(&store));
+
+        // Changing the source doesn't leak into the installed link
+        fs::write(skill_dir.join("SKILL.md"), "# Review v2")?;
+        assert_eq!(fs::read_to_string(target.join("SKILL.md"))?, "# Review v1");
+
+        let project_root = dst_dir.path().canonicalize()?;
+        let ledger = ledger::Ledger::load(data_dir.path(), &project_root)?;
+        assert_eq!(ledger.entries.len(), 1);
+        assert!(ledger.entries[0].snapshot.is_some());
+
+        // Still linked: nothing to collect
+        cmd_store_gc(data_dir.path().to_path_buf(), false)?;
+        assert_eq!(fs::read_dir(&store)?.count(), 1);
+
+        cmd_remove(RemoveOptions {
+            source,
+            clean: true,
+            scope: FileScope::Project,
+            providers: Some(vec![AgentProvider::ClaudeCode]),
+            on_conflict: OnConflict::Refuse,
+            offline: false,
+            root: dst_dir.path().to_path_buf(),
+            data_dir: data_dir.path().to_path_buf(),
+        })?;
+        assert!(
+            ledger::Ledger::load(data_dir.path(), &project_root)?
+                .entries
+                .is_empty()
+        );
+        cmd_store_gc(data_dir.path().to_path_buf(), false)?;
+        assert_eq!(fs::read_dir(&store)?.count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn cache_prune_keeps_referenced_sources() -> Result<()> {
+        let cache_root = TempDir::new()?;
+        let data_dir = TempDir::new()?;
+        let project = TempDir::new()?;
+        let project_root = project.path().canonicalize()?;
+
+        let mut m = manifest::Manifest::default().with_name("test".to_string());
+        m.dependencies
+            .push(Dependency::Simple("github.com/org/used@v1".to_string()));
+        manifest::save_manifest(&m, &project_root)?;
+        let mut ledger = ledger::Ledger::load(data_dir.path(), &project_root)?;
+        ledger.record(vec![ledger::LedgerEntry {
+            target: project_root.join(".claude/skills/review"),
+            source: "github.com/org/used".to_string(),
+            item: "skills/review".to_string(),
+            provider: AgentProvider::ClaudeCode,
+            kind: FileKind::Skill,
+            strategy: FileStrategy::Copy,
+            snapshot: None,
+            hash: None,
+            commit: None,
+        }]);
+        ledger.save(data_dir.path())?;
+
+        let entry = |url: &str, fetched_at: u64| -> Result<PathBuf> {
+            let dir = cache_entry_dir(cache_root.path(), url)?;
+            fs::create_dir_all(&dir)?;
+            let mut meta = cache::CacheMeta::fetched(url, None, "abc");
+            meta.fetched_at = fetched_at;
+            cache::write_meta(&dir, &meta)?;
+            Ok(dir)
+        };
+        let used = entry("https://github.com/org/used.git", 0)?;
+        let unused = entry("https://github.com/org/unused", cache::now())?;
+
+        cmd_cache_prune(
+            cache_root.path().to_path_buf(),
+            data_dir.path().to_path_buf(),
+            None,
+            true,
+        )?;
+        assert!(unused.exists());
+
+        cmd_cache_prune(
+            cache_root.path().to_path_buf(),
+            data_dir.path().to_path_buf(),
+            None,
+            false,
+        )?;
+        assert!(used.exists());
+        assert!(!unused.exists());
+
+        // Referenced, but not fetched for longer than the cutoff
+        cmd_cache_prune(
+            cache_root.path().to_path_buf(),
+            data_dir.path().to_path_buf(),
+            Some(30),
+            false,
+        )?;
+        assert!(!used.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn cache_prune_removes_unreferenced_checkouts() -> Result<()> {
+        let cache_root = TempDir::new()?;
+        let data_dir = TempDir::new()?;
+        let project = TempDir::new()?;
+        let project_root = project.path().canonicalize()?;
+        let (v1, v2) = ("1".repeat(40), "2".repeat(40));
+
+        let mut m = manifest::Manifest::default().with_name("test".to_string());
+        m.dependencies
+            .push(Dependency::Simple("github.com/org/repo@v1".to_string()));
+        manifest::save_manifest(&m, &project_root)?;
+        let mut ledger = ledger::Ledger::load(data_dir.path(), &project_root)?;
+        ledger.record(vec![ledger::LedgerEntry {
+            target: project_root.join(".claude/skills/review"),
+            source: "github.com/org/repo".to_string(),
+            item: "skills/review".to_string(),
+            provider: AgentProvider::ClaudeCode,
+            kind: FileKind::Skill,
+            strategy: FileStrategy::Copy,
+            snapshot: None,
+            hash: None,
+            commit: Some(v1.clone()),
+        }]);
+        ledger.save(data_dir.path())?;
+
+        // Both refs were resolved at some point, but only v1 is still used
+        let url = "https://github.com/org/repo";
+        let dir = cache_entry_dir(cache_root.path(), url)?;
+        let mut meta = cache::CacheMeta::fetched(url, Some("v2"), &v2);
+        meta.refs.insert("v1".to_string(), v1.clone());
+        fs::create_dir_all(&dir)?;
+        cache::write_meta(&dir, &meta)?;
+        for commit in [&v1, &v2] {
+            let worktree = git::worktree_path(&dir, commit);
+            fs::create_dir_all(worktree.join("skills/review"))?;
+            fs::write(worktree.join("skills/review/SKILL.md"), "# Review")?;
+        }
+
+        cmd_cache_prune(
+            cache_root.path().to_path_buf(),
+            data_dir.path().to_path_buf(),
+            None,
+            true,
+        )?;
+        assert_eq!(git::cached_worktrees(&dir), vec![v1.clone(), v2.clone()]);
+
+        cmd_cache_prune(
+            cache_root.path().to_path_buf(),
+            data_dir.path().to_path_buf(),
+            None,
+            false,
+        )?;
+        assert_eq!(git::cached_worktrees(&dir), vec![v1]);
+        Ok(())
+    }
+
+    #[test]
+    fn cache_clear_single_source() -> Result<()> {
+        let cache_root = TempDir::new()?;
+        let a = cache_entry_dir(cache_root.path(), "github.com/org/a")?;
+        let b = cache_entry_dir(cache_root.path(), "github.com/org/b")?;
+        fs::create_dir_all(&a)?;
+        fs::create_dir_all(&b)?;
+
+        cmd_cache_clear(
+            cache_root.path().to_path_buf(),
+            Some("https://github.com/org/a@main".to_string()),
+        )?;
+        assert!(!a.exists());
+        assert!(b.exists());
+
+        cmd_cache_clear(cache_root.path().to_path_buf(), None)?;
+        assert!(!b.exists());
+        assert!(cache_entry_dir(cache_root.path(), "./local").is_err());
+        Ok(())
+    }
 }
@@ -0,0 +1,277 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result, bail};
+use serde_json::{Map, Value};
+
+use crate::manifest::{Condition, FileMapping};
+use crate::scanner;
+use crate::types::AgentProvider;
+
+/// The condition `item` is installed under: the dependency's `when` entry
+/// for it, or else a `when` in the frontmatter of its markdown file
+/// (`SKILL.md` for skills), read from `source_dir`.
+pub(crate) fn for_item(
+    item: &FileMapping,
+    source_dir: &Path,
+    when: &BTreeMap<String, Condition>,
+) -> Result<Option<Condition>> {
+    if let Some(condition) = when.get(&scanner::item_key(item)) {
+        return Ok(Some(condition.clone()));
+    }
+    let path = source_dir.join(&item.path);
+    let markdown = if path.is_dir() {
+        path.join("SKILL.md")
+    } else {
+        path
+    };
+    let Ok(text) = fs::read_to_string(&markdown) else {
+        return Ok(None);
+    };
+    frontmatter_condition(&text).with_context(|| {
+        format!(
+            "invalid 'when' in the frontmatter of {}",
+            item.path.display()
+        )
+    })
+}
+
+/// Which of `providers` an item with `condition` is installed to in the
+/// project at `root`, and why the others are left out.
+///
+/// An unmet `files_exist` or `os` leaves out every provider.
+pub(crate) fn allowed(
+    condition: &Condition,
+    providers: &[AgentProvider],
+    root: &Path,
+) -> Result<(Vec<AgentProvider>, Vec<String>)> {
+    let mut reasons = Vec::new();
+    if !condition.os.is_empty() && !condition.os.iter().any(|os| os == std::env::consts::OS) {
+        reasons.push(format!("only on {}", condition.os.join(", ")));
+    }
+    let missing: Vec<&str> = condition
+        .files_exist
+        .iter()
+        .filter(|path| !root.join(path).exists())
+        .map(String::as_str)
+        .collect();
+    if !missing.is_empty() {
+        reasons.push(format!("needs {}", missing.join(", ")));
+    }
+    if !reasons.is_empty() {
+        return Ok((vec![], reasons));
+    }
+
+    if condition.providers.is_empty() {
+        return Ok((providers.to_vec(), reasons));
+    }
+    let wanted = condition
+        .providers
+        .iter()
+        .map(|p| p.parse())
+        .collect::<Result<Vec<AgentProvider>>>()?;
+    let allowed: Vec<AgentProvider> = providers
+        .iter()
+        .copied()
+        .filter(|p| wanted.contains(p))
+        .collect();
+    if allowed.len() < providers.len() {
+        let ids: Vec<&str> = wanted.iter().map(AgentProvider::id).collect();
+        reasons.push(format!("only for {}", ids.join(", ")));
+    }
+    Ok((allowed, reasons))
+}
+
+/// The `when` field of a markdown file's YAML frontmatter, written as a
+/// flow mapping (`when: { os: linux, providers: [claude-code] }`) or as a
+/// block of indented `key: value` lines whose values are scalars, flow
+/// lists or `- item` lines.
+fn frontmatter_condition(text: &str) -> Result<Option<Condition>> {
+    let mut lines = text.lines();
+    if lines.next().map(str::trim) != Some("---") {
+        return Ok(None);
+    }
+    let mut lines = lines.take_while(|line| line.trim() != "---").peekable();
+    while let Some(line) = lines.next() {
+        let Some(rest) = line.strip_prefix("when:") else {
+            continue;
+        };
+        let value = if rest.trim().is_empty() {
+            let mut block = Vec::new();
+            while let Some(line) = lines.next_if(|l| l.starts_with([' ', '\t']) || l.is_empty()) {
+                block.push(line);
+            }
+            block_map(&block)?
+        } else {
+            flow_value(rest)?
+        };
+        return Ok(Some(serde_json::from_value(value)?));
+    }
+    Ok(None)
+}
+
+/// Parse indented `key: value` lines, with `- item` lines under a key that
+/// has no value of its own.
+fn block_map(lines: &[&str]) -> Result<Value> {
+    let mut map = Map::new();
+    let mut current: Option<String> = None;
+    for line in lines.iter().map(|l| l.trim()) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(item) = line.strip_prefix("- ") {
+            let Some(Value::Array(items)) = current.as_ref().and_then(|k| map.get_mut(k)) else {
+                bail!("unexpected list item '{item}'");
+            };
+            items.push(flow_value(item)?);
+        } else if let Some((key, value)) = line.split_once(':') {
+            let key = key.trim().to_string();
+            if value.trim().is_empty() {
+                map.insert(key.clone(), Value::Array(vec![]));
+            } else {
+                map.insert(key.clone(), flow_value(value)?);
+            }
+            current = Some(key);
+        } else {
+            bail!("expected 'key: value', found '{line}'");
+        }
+    }
+    Ok(Value::Object(map))
+}
+
+/// Parse a YAML flow value: `{ ... }`, `[ ... ]`, a quoted or a plain
+/// scalar.
+fn flow_value(text: &str) -> Result<Value> {
+    let mut flow = Flow { rest: text };
+    let value = flow.value()?;
+    flow.skip_space();
+    if !flow.rest.is_empty() {
+        bail!("unexpected '{}'", flow.rest);
+    }
+    Ok(value)
+}
+
+struct Flow<'a> {
+    rest: &'a str,
+}
+
+impl Flow<'_> {
+    fn skip_space(&mut self) {
+        self.rest = self.rest.trim_start();
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_space();
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        self.skip_space();
+        if self.eat('{') {
+            let mut map = Map::new();
+            while !self.eat('}') {
+                let key = self.scalar(&[':'])?;
+                if !self.eat(':') {
+                    bail!("expected ':' after '{key}'");
+                }
+                map.insert(key, self.value()?);
+                if !self.eat(',') && !self.rest.starts_with('}') {
+                    bail!("expected ',' or '}}'");
+                }
+            }
+            Ok(Value::Object(map))
+        } else if self.eat('[') {
+            let mut items = Vec::new();
+            while !self.eat(']') {
+                items.push(self.value()?);
+                if !self.eat(',') && !self.rest.starts_with(']') {
+                    bail!("expected ',' or ']'");
+                }
+            }
+            Ok(Value::Array(items))
+        } else {
+            Ok(Value::String(self.scalar(&[',', ']', '}'])?))
+        }
+    }
+
+    /// A quoted string, or plain text up to one of `stops`.
+    fn scalar(&mut self, stops: &[char]) -> Result<String> {
+        self.skip_space();
+        if let Some(quote) = self.rest.chars().next().filter(|c| matches!(c, '"' | '\'')) {
+            let end = self.rest[1..]
+                .find(quote)
+                .with_context(|| format!("unterminated string {}", self.rest))?;
+            let value = self.rest[1..=end].to_string();
+            self.rest = &self.rest[end + 2..];
+            return Ok(value);
+        }
+        let end = self.rest.find(stops).unwrap_or(self.rest.len());
+        let value = self.rest[..end].trim();
+        if value.is_empty() {
+            bail!("expected a value");
+        }
+        self.rest = &self.rest[end..];
+        Ok(value.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn reads_frontmatter_conditions() -> Result<()> {
+        let flow = "---\nname: rust-review\nwhen: { providers: [claude-code], \
+                    files_exist: [\"Cargo.toml\"], os: linux }\n---\n# Review\n";
+        let block = "---\nname: rust-review\nwhen:\n  providers: [claude-code]\n  \
+                     files_exist:\n    - 'Cargo.toml'\n  os: linux\nmodel: opus\n---\n";
+        let expected = Condition {
+            providers: vec!["claude-code".to_string()],
+            files_exist: vec!["Cargo.toml".to_string()],
+            os: vec!["linux".to_string()],
+        };
+        assert_eq!(frontmatter_condition(flow)?, Some(expected.clone()));
+        assert_eq!(frontmatter_condition(block)?, Some(expected));
+        assert_eq!(frontmatter_condition("---\nname: x\n---\nwhen: {}")?, None);
+        assert!(frontmatter_condition("---\nwhen: { provider: codex }\n---\n").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn checks_project_and_providers() -> Result<()> {
+        let root = TempDir::new()?;
+        let providers = [AgentProvider::ClaudeCode, AgentProvider::Cursor];
+        let condition = Condition {
+            providers: vec!["claude".to_string()],
+            files_exist: vec!["Cargo.toml".to_string(), "rust-toolchain.toml".to_string()],
+            os: vec![],
+        };
+
+        let (allowed_to, reasons) = allowed(&condition, &providers, root.path())?;
+        assert!(allowed_to.is_empty());
+        assert_eq!(reasons, ["needs Cargo.toml, rust-toolchain.toml"]);
+
+        fs::write(root.path().join("Cargo.toml"), "")?;
+        fs::write(root.path().join("rust-toolchain.toml"), "")?;
+        let (allowed_to, reasons) = allowed(&condition, &providers, root.path())?;
+        assert_eq!(allowed_to, [AgentProvider::ClaudeCode]);
+        assert_eq!(reasons, ["only for claude-code"]);
+
+        let elsewhere = Condition {
+            os: vec!["plan9".to_string()],
+            ..Condition::default()
+        };
+        let (allowed_to, reasons) = allowed(&elsewhere, &providers, root.path())?;
+        assert!(allowed_to.is_empty());
+        assert_eq!(reasons, ["only on plan9"]);
+        Ok(())
+    }
+}
@@ -1,58 +1,170 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync::OnceLock;
 
 use anyhow::{Context, Result, bail};
 use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::{cache, credentials};
 
 /// Result of resolving a remote git source.
 ///
 /// Contains the local path to the cloned/cached repository and the
 /// original URL used for display purposes.
 pub struct GitSource {
-    /// Local path to the cloned repository content.
+    /// Local path to the cloned repository content. When the remote names a
+    /// subdirectory, this points at that subdirectory inside the clone.
     pub local_path: PathBuf,
     /// The original URL (without ref) for display.
     pub url: String,
     /// The git ref that was checked out, if any.
     pub git_ref: Option<String>,
+    /// The full commit hash `git_ref` resolved to.
+    pub commit: String,
+}
+
+/// User configuration for recognizing and rewriting remote sources.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct RemoteConfig {
+    /// Extra hosts accepted in shorthand form (`git.corp.example/team/repo`),
+    /// in addition to the built-in ones.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+
+    /// `insteadOf`-style rewrites: a source starting with a key has that
+    /// prefix replaced by the value. Keys match the source as written or its
+    /// full URL (`https://github.com/`); the longest matching key wins.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rewrites: BTreeMap<String, String>,
+
+    /// Environment variable holding the access token for a host, for hosts
+    /// whose token isn't in `AGENTFILES_TOKEN_<HOST>` (see `credentials`).
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tokens: BTreeMap<String, String>,
+}
+
+/// Remote configuration in effect for this process (see `configure_remotes`).
+static REMOTE_CONFIG: OnceLock<RemoteConfig> = OnceLock::new();
+
+/// Install the user's remote configuration for the rest of the process.
+///
+/// Must run before any source is parsed, so that every URL, deduplication
+/// key and cache directory is derived the same way. Later calls are ignored.
+pub fn configure_remotes(config: RemoteConfig) {
+    debug!("Remote config: {:?}", config);
+    let _ = REMOTE_CONFIG.set(config);
+}
+
+pub(crate) fn remote_config() -> &'static RemoteConfig {
+    REMOTE_CONFIG.get_or_init(RemoteConfig::default)
+}
+
+/// Hosts recognized in shorthand form without any configuration.
+pub(crate) const KNOWN_HOSTS: &[&str] = &[
+    "github.com",
+    "gitlab.com",
+    "bitbucket.org",
+    "codeberg.org",
+    "sr.ht",
+];
+
+/// Keys trusted to sign the refs of sources that must be verified.
+///
+/// Unset fields fall back to the user's own git and GnuPG configuration.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SignaturePolicy {
+    /// SSH allowed-signers file (git's `gpg.ssh.allowedSignersFile`).
+    pub allowed_signers: Option<PathBuf>,
+    /// GnuPG home directory holding the trusted public keys (`GNUPGHOME`).
+    pub gpg_home: Option<PathBuf>,
 }
 
-/// Parsed git remote input — a URL and an optional ref.
+/// Parsed git remote input — a URL, an optional subdirectory and an optional ref.
 #[derive(Debug, PartialEq)]
 pub struct ParsedRemote {
     /// The git-cloneable URL (with scheme).
     pub url: String,
     /// Optional ref (branch, tag, commit) to check out.
     pub git_ref: Option<String>,
+    /// Optional subdirectory within the repository to use as the source root.
+    pub subdir: Option<String>,
+}
+
+impl fmt::Display for ParsedRemote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", credentials::redact(&self.url, remote_config()))?;
+        if let Some(subdir) = &self.subdir {
+            write!(f, "//{subdir}")?;
+        }
+        if let Some(git_ref) = &self.git_ref {
+            write!(f, " @ {git_ref}")?;
+        }
+        Ok(())
+    }
 }
 
 /// Normalize a source string for deduplication.
 ///
 /// Strips `@ref` suffixes, normalizes URL schemes, and removes trailing `.git`
 /// so that `github.com/org/repo`, `https://github.com/org/repo`, and
-/// `https://github.com/org/repo.git` all compare as equal.
+/// `https://github.com/org/repo.git` all compare as equal. A `//subdir`
+/// suffix is kept, so different subdirectories of one repository stay
+/// distinct.
 pub fn normalize_source(input: &str) -> String {
+    source_key(input, None)
+}
+
+/// Normalize a source string plus an explicit subdirectory for deduplication.
+///
+/// Like `normalize_source`, but `subdir` (e.g. from a manifest `subdir`
+/// field) takes precedence over any inline `//subdir` in `input`.
+pub fn source_key(input: &str, subdir: Option<&str>) -> String {
     let (base, _ref) = strip_ref(input);
+    let (base, inline_subdir) = split_subdir(base);
     let url = normalize_url(base);
-    url.trim_end_matches(".git").to_string()
+    let url = url.trim_end_matches(".git");
+    match subdir
+        .map(|s| s.trim_matches('/').to_string())
+        .or(inline_subdir)
+    {
+        Some(subdir) if !subdir.is_empty() => format!("{url}//{subdir}"),
+        _ => url.to_string(),
+    }
 }
 
 /// Check whether a string looks like a git remote URL rather than a local path.
 ///
 /// Recognizes:
-/// - Explicit schemes: `https://`, `http://`, `git://`, `ssh://`
+/// - Explicit schemes: `https://`, `http://`, `git://`, `ssh://`, `file://`
 /// - SCP-style: `git@host:org/repo`
-/// - Shorthand: `github.com/org/repo`, `gitlab.com/org/repo`, etc.
+/// - Shorthand: `github.com/org/repo`, `gitlab.com/org/repo`, etc., plus
+///   any host added in the user's `RemoteConfig`
+/// - Anything matching a configured rewrite
 pub fn is_git_url(input: &str) -> bool {
+    is_git_url_with(input, remote_config())
+}
+
+fn is_git_url_with(input: &str, config: &RemoteConfig) -> bool {
     // Strip any @ref suffix before checking
     let base = strip_ref(input).0;
 
+    if config
+        .rewrites
+        .keys()
+        .any(|from| base.starts_with(from.as_str()))
+    {
+        return true;
+    }
+
     // Explicit schemes
     if base.starts_with("https://")
         || base.starts_with("http://")
         || base.starts_with("git://")
         || base.starts_with("ssh://")
+        || base.starts_with("file://")
     {
         return true;
     }
@@ -63,27 +175,22 @@ pub fn is_git_url(input: &str) -> bool {
     }
 
     // Shorthand: github.com/org/repo, gitlab.com/org/repo, etc.
-    let known_hosts = [
-        "github.com/",
-        "gitlab.com/",
-        "bitbucket.org/",
-        "codeberg.org/",
-        "sr.ht/",
-    ];
-    for host in &known_hosts {
-        if base.starts_with(host) {
-            return true;
-        }
-    }
-
-    false
+    KNOWN_HOSTS
+        .iter()
+        .copied()
+        .chain(config.hosts.iter().map(String::as_str))
+        .any(|host| {
+            base.strip_prefix(host.trim_end_matches('/'))
+                .is_some_and(|rest| rest.starts_with('/'))
+        })
 }
 
-/// Parse a remote input string into a URL and optional ref.
+/// Parse a remote input string into a URL, optional subdirectory and optional ref.
 ///
 /// The ref is specified with `@ref` syntax at the end of the URL.
 /// We're careful not to confuse `git@github.com` (part of SCP syntax)
-/// with a ref delimiter.
+/// with a ref delimiter. A subdirectory within the repository is given
+/// with `//`, before the ref: `github.com/org/mono//tools/agents@v3`.
 ///
 /// # Examples
 ///
@@ -97,62 +204,312 @@ pub fn is_git_url(input: &str) -> bool {
 /// let p = parse_remote("git@github.com:org/repo.git");
 /// assert_eq!(p.url, "git@github.com:org/repo.git");
 /// assert_eq!(p.git_ref, None);
+///
+/// let p = parse_remote("github.com/org/mono//tools/agents@v3");
+/// assert_eq!(p.url, "https://github.com/org/mono");
+/// assert_eq!(p.subdir, Some("tools/agents".to_string()));
+/// assert_eq!(p.git_ref, Some("v3".to_string()));
 /// ```
 pub fn parse_remote(input: &str) -> ParsedRemote {
     let (base, git_ref) = strip_ref(input);
+    let (base, subdir) = split_subdir(base);
     let url = normalize_url(base);
-    ParsedRemote { url, git_ref }
+    ParsedRemote {
+        url,
+        git_ref,
+        subdir,
+    }
+}
+
+/// The URL of `input` as written: shorthand expanded to `https://`, but
+/// without any configured rewrite, ref or subdirectory. This is what
+/// manifests record, so they stay portable across machines with different
+/// rewrites.
+pub fn written_url(input: &str) -> String {
+    let (base, _ref) = strip_ref(input);
+    expand_url(split_subdir(base).0)
 }
 
 /// Clone or update a remote git repository and return the local path.
 ///
 /// Uses a cache directory at `~/.cache/agentfiles/<hash>/` (or the platform
-/// equivalent via `dirs::cache_dir()`). If the cache already exists, it fetches
-/// updates instead of re-cloning.
+/// equivalent via `dirs::cache_dir()`) holding a `meta.json` that maps the
+/// hash back to the URL, one bare mirror per URL
+/// (`mirror.git`) and one worktree per resolved commit
+/// (`worktrees/<commit>`). Different refs of the same repository therefore
+/// never share a working tree. The metadata records the commit each ref was
+/// last resolved to, and worktrees of commits no ref resolves to anymore
+/// are removed.
+///
+/// The mirror is a blobless partial clone: only the requested commit is
+/// fetched (`--depth 1`), and file contents are downloaded lazily as they
+/// are checked out. A ref missing from the shallow history (e.g. an
+/// abbreviated commit hash) deepens the mirror on demand.
+///
+/// `checkout_paths` restricts a worktree to those repository-relative paths
+/// via sparse-checkout (an empty slice checks out everything). Paths are
+/// added to whatever an earlier resolution of the same commit checked out,
+/// so a worktree only ever widens.
 ///
-/// If a `git_ref` is provided, checks out that ref after clone/fetch.
-pub fn resolve_remote(remote: &ParsedRemote) -> Result<GitSource> {
+/// If a `git_ref` is provided, checks out that ref; otherwise the remote's
+/// default branch. Commit hashes and tags that are already cached are used
+/// without contacting the remote. With `offline`, git is never allowed to
+/// use the network and anything missing from the cache is an error.
+///
+/// With a `verify` policy, the resolved tag or commit must carry a valid
+/// signature from a key the policy trusts; nothing is checked out otherwise.
+pub fn resolve_remote(
+    remote: &ParsedRemote,
+    checkout_paths: &[String],
+    offline: bool,
+    verify: Option<&SignaturePolicy>,
+) -> Result<GitSource> {
     debug!(
-        "Resolving remote: url={}, ref={:?}",
-        remote.url, remote.git_ref
+        "Resolving remote: url={}, ref={:?}, paths={:?}, offline={}, verify={:?}",
+        credentials::redact(&remote.url, remote_config()),
+        remote.git_ref,
+        checkout_paths,
+        offline,
+        verify
     );
     ensure_git_available()?;
 
     let cache_dir = get_cache_dir(&remote.url)?;
+    let mirror = cache_dir.join(MIRROR_DIR);
 
-    if cache_dir.exists() {
-        debug!("Cache hit, fetching updates: {}", cache_dir.display());
-        // Update existing clone
-        fetch_repo(&cache_dir)?;
+    if mirror.join("HEAD").is_file() {
+        debug!("Cache hit: {}", mirror.display());
+    } else if offline {
+        bail!("{remote} is not cached (offline mode)");
     } else {
-        debug!("Cache miss, cloning to: {}", cache_dir.display());
-        // Fresh clone
-        clone_repo(&remote.url, &cache_dir)?;
+        debug!("Cache miss, initializing: {}", mirror.display());
+        init_mirror(&remote.url, &mirror)?;
     }
 
-    // Check out the requested ref, or reset to the default branch HEAD
-    if let Some(ref git_ref) = remote.git_ref {
-        debug!("Checking out ref: {}", git_ref);
-        checkout_ref(&cache_dir, git_ref)?;
-    } else {
-        debug!("Resetting to default branch");
-        reset_to_default_branch(&cache_dir)?;
+    let (commit, fetched) =
+        fetch_ref(&mirror, remote.git_ref.as_deref(), offline).map_err(|err| {
+            if credentials::is_auth_failure(&format!("{err:#}")) {
+                credentials::auth_error(&remote.url, err, remote_config())
+            } else {
+                err
+            }
+        })?;
+    let mut meta = cache::CacheMeta::fetched(&remote.url, remote.git_ref.as_deref(), &commit);
+    if let Some(previous) = cache::read_meta(&cache_dir) {
+        if !fetched {
+            meta.fetched_at = previous.fetched_at;
+        }
+        // Other refs of the repository keep their commits
+        meta.refs = previous.refs.into_iter().chain(meta.refs).collect();
+    }
+    cache::write_meta(&cache_dir, &meta)?;
+
+    if let Some(policy) = verify {
+        verify_signature(&mirror, remote.git_ref.as_deref(), &commit, policy)
+            .with_context(|| format!("refusing to install {remote}"))?;
+    }
+
+    let worktree = worktree_path(&cache_dir, &commit);
+    ensure_worktree(&mirror, &worktree, &commit, checkout_paths, offline).with_context(|| {
+        if offline {
+            format!("could not check out {commit} from the cache (offline mode)")
+        } else {
+            format!("could not check out {commit}")
+        }
+    })?;
+
+    let local_path = match &remote.subdir {
+        Some(subdir) => {
+            validate_subdir(subdir)?;
+            // Check the commit's tree: with a sparse checkout the directory
+            // may legitimately be absent from the working tree.
+            let spec = format!("{commit}:{}^{{tree}}", subdir.trim_matches('/'));
+            if run_git(&mirror, &["rev-parse", "--verify", "--quiet", &spec]).is_err() {
+                bail!(
+                    "subdirectory '{subdir}' not found in {}",
+                    credentials::redact(&remote.url, remote_config())
+                );
+            }
+            let path = worktree.join(subdir);
+            std::fs::create_dir_all(&path)
+                .with_context(|| format!("failed to create directory: {}", path.display()))?;
+            path
+        }
+        None => worktree,
+    };
+
+    // Installs copy or snapshot what they need, so the checkouts of commits
+    // that no ref resolves to anymore (e.g. a branch that moved) are garbage
+    let in_use: HashSet<&str> = meta.refs.values().map(String::as_str).collect();
+    if let Err(err) = remove_unused_worktrees(&cache_dir, &in_use) {
+        debug!("Could not remove unused worktrees: {err:#}");
     }
 
     Ok(GitSource {
-        local_path: cache_dir,
+        local_path,
         url: remote.url.clone(),
         git_ref: remote.git_ref.clone(),
+        commit,
     })
 }
 
+/// Bare mirror inside a URL's cache directory.
+const MIRROR_DIR: &str = "mirror.git";
+
+/// Per-commit worktrees inside a URL's cache directory.
+const WORKTREES_DIR: &str = "worktrees";
+
+/// Commits that have a worktree in the cache entry at `cache_dir`.
+pub(crate) fn cached_worktrees(cache_dir: &Path) -> Vec<String> {
+    let Ok(entries) = std::fs::read_dir(cache_dir.join(WORKTREES_DIR)) else {
+        return Vec::new();
+    };
+    let mut commits: Vec<String> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+    commits.sort();
+    commits
+}
+
+/// Path of the worktree of `commit` in the cache entry at `cache_dir`.
+pub(crate) fn worktree_path(cache_dir: &Path, commit: &str) -> PathBuf {
+    cache_dir.join(WORKTREES_DIR).join(commit)
+}
+
+/// Remove the worktree of `commit` from the cache entry at `cache_dir`.
+pub(crate) fn remove_worktree(cache_dir: &Path, commit: &str) -> Result<()> {
+    let worktree = worktree_path(cache_dir, commit);
+    debug!("Removing worktree {}", worktree.display());
+    std::fs::remove_dir_all(&worktree)
+        .with_context(|| format!("failed to remove {}", worktree.display()))?;
+    let mirror = cache_dir.join(MIRROR_DIR);
+    if mirror.join("HEAD").is_file() {
+        run_git(&mirror, &["worktree", "prune"])?;
+    }
+    Ok(())
+}
+
+/// Remove every worktree in the cache entry at `cache_dir` whose commit
+/// isn't in `in_use`.
+fn remove_unused_worktrees(cache_dir: &Path, in_use: &HashSet<&str>) -> Result<()> {
+    for commit in cached_worktrees(cache_dir) {
+        if !in_use.contains(commit.as_str()) {
+            remove_worktree(cache_dir, &commit)?;
+        }
+    }
+    Ok(())
+}
+
+/// Commit `git_ref` (or the default branch) resolves to in the cache entry
+/// at `cache_dir`, without contacting the remote: the commit the ref was
+/// last resolved to, or else whatever the mirror has for it.
+pub(crate) fn cached_commit(cache_dir: &Path, git_ref: Option<&str>) -> Option<String> {
+    if let Some(commit) =
+        cache::read_meta(cache_dir).and_then(|meta| meta.refs.get(cache::ref_key(git_ref)).cloned())
+    {
+        return Some(commit);
+    }
+    let mirror = cache_dir.join(MIRROR_DIR);
+    if !mirror.join("HEAD").is_file() {
+        return None;
+    }
+    git_ref
+        .and_then(|git_ref| cached_immutable_ref(&mirror, git_ref))
+        .or_else(|| resolve_cached_ref(&mirror, git_ref))
+}
+
+/// Root of the source cache: `agentfiles/` under the platform cache
+/// directory (via `dirs::cache_dir()`).
+pub fn cache_root() -> Result<PathBuf> {
+    let base = dirs::cache_dir().context("could not determine cache directory")?;
+    Ok(base.join("agentfiles"))
+}
+
 /// Return the cache directory path for a given URL without performing any git operations.
 ///
 /// Useful for checking cache status or cleaning up.
 pub fn get_cache_dir(url: &str) -> Result<PathBuf> {
-    let base = dirs::cache_dir().context("could not determine cache directory")?;
-    let hash = hash_url(url);
-    Ok(base.join("agentfiles").join(hash))
+    Ok(cache_root()?.join(hash_url(url)))
+}
+
+/// Unified diff from `old` to `new` (files or directories), or `None` when
+/// git is unavailable. Uses `git diff --no-index`, so neither path needs to
+/// be inside a repository.
+pub(crate) fn diff_paths(old: &Path, new: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .args(["diff", "--no-index", "--no-color", "--"])
+        .arg(old)
+        .arg(new)
+        .output()
+        .ok()?;
+    // Exit code 1 just means the paths differ
+    match output.status.code() {
+        Some(0 | 1) => Some(String::from_utf8_lossy(&output.stdout).into_owned()),
+        _ => None,
+    }
+}
+
+/// Apply the unified diff at `patch` to the files in `dir`.
+///
+/// `dir` doesn't need to be (and is never treated as) part of a repository.
+pub(crate) fn apply_patch(dir: &Path, patch: &Path) -> Result<()> {
+    ensure_git_available()?;
+    debug!("Applying {} in {}", patch.display(), dir.display());
+    let output = Command::new("git")
+        .args(["apply", "--whitespace=nowarn", "--"])
+        .arg(patch)
+        .current_dir(dir)
+        .env("GIT_CEILING_DIRECTORIES", dir.parent().unwrap_or(dir))
+        .output()
+        .context("failed to run 'git apply'")?;
+    if !output.status.success() {
+        bail!(
+            "git apply failed:\n{}",
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        );
+    }
+    Ok(())
+}
+
+/// Return the top-level directory of the git work tree containing `dir`.
+///
+/// Returns `None` when `dir` is not inside a git repository or git is
+/// unavailable.
+pub fn repo_toplevel(dir: &Path) -> Option<PathBuf> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .current_dir(dir)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Some(PathBuf::from(path))
+}
+
+/// Locate the `info/exclude` file of the git repository containing `dir`.
+///
+/// Resolved through `git rev-parse --git-path` so that linked worktrees and
+/// submodules (where `.git` is a file) are handled. Returns `None` when
+/// `dir` is not inside a git repository or git is unavailable.
+pub fn info_exclude_path(dir: &Path) -> Option<PathBuf> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--git-path", "info/exclude"])
+        .current_dir(dir)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let path = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    Some(if path.is_absolute() {
+        path
+    } else {
+        dir.join(path)
+    })
 }
 
 /// Split a ref suffix from the input.
@@ -176,16 +533,78 @@ fn strip_ref(input: &str) -> (&str, Option<String>) {
     (input, None)
 }
 
+/// Split a `//subdir` suffix from a URL (with any `@ref` already stripped).
+///
+/// The `//` of a URL scheme (`https://`) is not a delimiter, so
+/// `https://github.com/org/mono//tools/agents` yields
+/// ("https://github.com/org/mono", Some("tools/agents")).
+fn split_subdir(url: &str) -> (&str, Option<String>) {
+    let search_from = url.find("://").map(|i| i + 3).unwrap_or(0);
+    if let Some(pos) = url[search_from..].find("//") {
+        let split = search_from + pos;
+        let subdir = url[split + 2..].trim_matches('/');
+        if !subdir.is_empty() {
+            return (&url[..split], Some(subdir.to_string()));
+        }
+        return (&url[..split], None);
+    }
+    (url, None)
+}
+
+/// Validate a repository subdirectory so it can't escape the clone.
+pub fn validate_subdir(subdir: &str) -> Result<()> {
+    let path = Path::new(subdir);
+    if subdir.is_empty() || path.is_absolute() {
+        bail!("subdirectory '{subdir}' must be a relative path");
+    }
+    if path
+        .components()
+        .any(|c| !matches!(c, std::path::Component::Normal(_)))
+    {
+        bail!("subdirectory '{subdir}' contains '..' or other special components");
+    }
+    Ok(())
+}
+
 /// Normalize a URL so it's git-cloneable.
 ///
 /// - Already has a scheme -> use as-is
 /// - SCP-style (`git@...`) -> use as-is
 /// - Shorthand (`github.com/org/repo`) -> prepend `https://`
+///
+/// Configured rewrites are applied to the URL as written and, failing
+/// that, to the normalized URL.
 fn normalize_url(url: &str) -> String {
+    normalize_url_with(url, remote_config())
+}
+
+fn normalize_url_with(url: &str, config: &RemoteConfig) -> String {
+    if let Some(rewritten) = rewrite_url(url, config) {
+        return expand_url(&rewritten);
+    }
+    let expanded = expand_url(url);
+    rewrite_url(&expanded, config).unwrap_or(expanded)
+}
+
+/// Apply the longest rewrite whose key prefixes `url`, if any.
+fn rewrite_url(url: &str, config: &RemoteConfig) -> Option<String> {
+    let (from, to) = config
+        .rewrites
+        .iter()
+        .filter(|(from, _)| url.starts_with(from.as_str()))
+        .max_by_key(|(from, _)| from.len())?;
+    let rewritten = format!("{to}{}", &url[from.len()..]);
+    debug!("Rewrote {} to {}", url, rewritten);
+    Some(rewritten)
+}
+
+/// Prepend `https://` to shorthand URLs.
+fn expand_url(url: &str) -> String {
     if url.starts_with("https://")
         || url.starts_with("http://")
         || url.starts_with("git://")
         || url.starts_with("ssh://")
+        || url.starts_with("file://")
         || url.starts_with("git@")
     {
         url.to_string()
@@ -194,11 +613,31 @@ fn normalize_url(url: &str) -> String {
     }
 }
 
+/// Split a normalized URL (see `normalize_url`) into its scheme, host and
+/// path. SCP-style URLs (`git@host:org/repo`) use the `ssh` scheme; user
+/// info and ports are dropped from the host.
+pub(crate) fn url_parts(url: &str) -> (&str, &str, &str) {
+    let (scheme, rest, separator) = match url.split_once("://") {
+        Some((scheme, rest)) => (scheme, rest, '/'),
+        None => ("ssh", url, ':'),
+    };
+    let (authority, path) = rest.split_once(separator).unwrap_or((rest, ""));
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = if separator == '/' {
+        host.split(':').next().unwrap_or(host)
+    } else {
+        host
+    };
+    (scheme, host, path.trim_start_matches('/'))
+}
+
 /// Hash a URL string to a 16-character hex string using FNV-1a.
 ///
 /// Uses a deterministic hash algorithm (FNV-1a 64-bit) so that cache
 /// directory names remain stable across Rust toolchain upgrades.
-fn hash_url(url: &str) -> String {
+pub(crate) fn hash_url(url: &str) -> String {
     const FNV_OFFSET: u64 = 0xcbf29ce484222325;
     const FNV_PRIME: u64 = 0x00000100000001B3;
 
@@ -235,148 +674,385 @@ fn ensure_git_available() -> Result<()> {
         .map_err(|e| anyhow::anyhow!("{e}"))
 }
 
-/// Clone a repository into the cache directory.
-fn clone_repo(url: &str, target: &Path) -> Result<()> {
-    debug!("Cloning {} into {}", url, target.display());
-    // Ensure parent directory exists
-    if let Some(parent) = target.parent() {
-        std::fs::create_dir_all(parent)
-            .with_context(|| format!("failed to create cache directory: {}", parent.display()))?;
-    }
-
+/// Run a git command in `dir` and return its trimmed stdout.
+///
+/// Fails with git's stderr when the command exits unsuccessfully.
+///
+/// Access tokens are passed through the environment (see
+/// `credentials::git_env`) and git never prompts for credentials, so an
+/// unauthenticated fetch fails instead of hanging. Arguments and stderr are
+/// redacted before being logged or returned.
+fn run_git(dir: &Path, args: &[&str]) -> Result<String> {
+    let config = remote_config();
+    let command = credentials::redact(&args.join(" "), config);
+    debug!("git {} (in {})", command, dir.display());
     let output = Command::new("git")
-        .args(["clone", url])
-        .arg(target)
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .envs(credentials::git_env(config))
         .output()
-        .with_context(|| format!("failed to run 'git clone {url}'"))?;
+        .with_context(|| format!("failed to run 'git {command}'"))?;
 
     if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!("git clone failed:\n{stderr}");
+        let stderr = credentials::redact(&String::from_utf8_lossy(&output.stderr), config);
+        bail!("git {} failed:\n{stderr}", args[0]);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Prefix `args` with config that forbids every git transport when
+/// `offline`, so not even a lazy blob fetch can reach the network.
+fn offline_args<'a>(offline: bool, args: &[&'a str]) -> Vec<&'a str> {
+    let mut full = Vec::with_capacity(args.len() + 2);
+    if offline {
+        full.extend(["-c", "protocol.allow=never"]);
     }
+    full.extend_from_slice(args);
+    full
+}
 
+/// Initialize an empty bare, blobless partial clone of `url` at `target`.
+///
+/// Nothing is fetched here; `fetch_ref` downloads only the commit needed.
+fn init_mirror(url: &str, target: &Path) -> Result<()> {
+    debug!(
+        "Initializing partial mirror of {} in {}",
+        credentials::redact(url, remote_config()),
+        target.display()
+    );
+    std::fs::create_dir_all(target)
+        .with_context(|| format!("failed to create cache directory: {}", target.display()))?;
+
+    run_git(target, &["init", "--quiet", "--bare"])?;
+    run_git(target, &["remote", "add", "origin", url])?;
+    run_git(target, &["config", "remote.origin.promisor", "true"])?;
+    run_git(
+        target,
+        &["config", "remote.origin.partialclonefilter", "blob:none"],
+    )?;
     Ok(())
 }
 
-/// Fetch updates in an existing clone.
-fn fetch_repo(repo_dir: &Path) -> Result<()> {
-    debug!("Fetching updates in {}", repo_dir.display());
-    let output = Command::new("git")
-        .args(["fetch", "--all", "--prune"])
-        .current_dir(repo_dir)
-        .output()
-        .context("failed to run 'git fetch'")?;
+/// Make sure `worktree` holds a checkout of `commit` covering `paths`.
+///
+/// A new worktree is created from `mirror` with only `paths` checked out
+/// (everything when `paths` is empty). An existing one is widened to
+/// include `paths`; commits are immutable, so its content is otherwise
+/// left alone.
+fn ensure_worktree(
+    mirror: &Path,
+    worktree: &Path,
+    commit: &str,
+    paths: &[String],
+    offline: bool,
+) -> Result<()> {
+    if worktree.join(".git").exists() {
+        debug!("Reusing worktree: {}", worktree.display());
+        return widen_sparse_checkout(worktree, paths, offline);
+    }
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        bail!("git fetch failed:\n{stderr}");
+    debug!("Creating worktree for {} at {}", commit, worktree.display());
+    if worktree.exists() {
+        // Leftover from an interrupted run
+        std::fs::remove_dir_all(worktree)
+            .with_context(|| format!("failed to remove {}", worktree.display()))?;
+        run_git(mirror, &["worktree", "prune"])?;
+    }
+
+    let path = worktree.to_string_lossy();
+    if let Err(err) = run_git(
+        mirror,
+        &[
+            "worktree",
+            "add",
+            "--no-checkout",
+            "--detach",
+            &path,
+            commit,
+        ],
+    ) {
+        // Another install may have created it in the meantime
+        if worktree.join(".git").exists() {
+            return widen_sparse_checkout(worktree, paths, offline);
+        }
+        return Err(err);
     }
 
+    if !paths.is_empty() {
+        let patterns = sparse_patterns(Vec::new(), paths);
+        let mut args = vec!["sparse-checkout", "set", "--no-cone"];
+        args.extend(patterns.iter().map(String::as_str));
+        run_git(worktree, &offline_args(offline, &args))?;
+    }
+    run_git(
+        worktree,
+        &offline_args(offline, &["reset", "--quiet", "--hard", commit]),
+    )?;
     Ok(())
 }
 
-/// Validate a git ref to prevent flag injection.
+/// Widen a worktree's sparse-checkout to include `paths`.
 ///
-/// Rejects refs that could be interpreted as git command-line flags
-/// or that contain path traversal sequences.
-fn validate_git_ref(git_ref: &str) -> Result<()> {
-    if git_ref.is_empty() {
-        bail!("git ref cannot be empty");
-    }
-    if git_ref.starts_with('-') {
-        bail!("git ref '{git_ref}' looks like a command-line flag — refusing for safety");
+/// Uses non-cone sparse-checkout patterns anchored at the repository root.
+/// An empty `paths` disables sparse-checkout. A worktree without
+/// sparse-checkout already has everything and is left alone.
+fn widen_sparse_checkout(worktree: &Path, paths: &[String], offline: bool) -> Result<()> {
+    let enabled = run_git(worktree, &["config", "--bool", "core.sparseCheckout"])
+        .map(|v| v == "true")
+        .unwrap_or(false);
+    if !enabled {
+        return Ok(());
     }
-    if git_ref.contains("..") {
-        bail!("git ref '{git_ref}' contains '..' — refusing for safety");
+
+    if paths.is_empty() {
+        debug!("Disabling sparse-checkout in {}", worktree.display());
+        run_git(
+            worktree,
+            &offline_args(offline, &["sparse-checkout", "disable"]),
+        )?;
+        return Ok(());
     }
-    if git_ref.bytes().any(|b| b.is_ascii_control() || b == b' ') {
-        bail!("git ref '{git_ref}' contains whitespace or control characters");
+
+    let existing: Vec<String> = run_git(worktree, &["sparse-checkout", "list"])?
+        .lines()
+        .map(str::to_string)
+        .collect();
+    let before = existing.len();
+    let patterns = sparse_patterns(existing, paths);
+    if patterns.len() == before {
+        return Ok(());
     }
+
+    debug!("Sparse-checkout patterns: {:?}", patterns);
+    let mut args = vec!["sparse-checkout", "set", "--no-cone"];
+    args.extend(patterns.iter().map(String::as_str));
+    run_git(worktree, &offline_args(offline, &args))?;
     Ok(())
 }
 
-/// Check out a specific ref (branch, tag, or commit hash).
-fn checkout_ref(repo_dir: &Path, git_ref: &str) -> Result<()> {
-    debug!("Checking out ref '{}' in {}", git_ref, repo_dir.display());
-    validate_git_ref(git_ref)?;
+/// Append root-anchored patterns for `paths` to `patterns`, skipping duplicates.
+fn sparse_patterns(mut patterns: Vec<String>, paths: &[String]) -> Vec<String> {
+    for path in paths {
+        let pattern = format!("/{}", path.trim_matches('/'));
+        if !patterns.contains(&pattern) {
+            patterns.push(pattern);
+        }
+    }
+    patterns
+}
 
-    // First, try a detached checkout (works for tags and commit hashes)
-    let output = Command::new("git")
-        .args(["checkout", git_ref])
-        .current_dir(repo_dir)
-        .output()
-        .with_context(|| format!("failed to run 'git checkout {git_ref}'"))?;
+/// Fetch `git_ref` (or the remote's default branch) and return its commit
+/// hash, along with whether the remote was actually contacted.
+///
+/// Commit hashes and tags already in the mirror never change, so they are
+/// used as-is without a fetch. Otherwise tries a shallow, blobless fetch of
+/// just that ref. If the remote can't serve it directly (abbreviated commit
+/// hashes, servers that refuse fetching arbitrary commits), deepens the
+/// clone to full history and resolves the ref locally.
+///
+/// With `offline`, only refs already in the mirror (including branches and
+/// the default branch as of the last fetch) can be resolved.
+fn fetch_ref(repo_dir: &Path, git_ref: Option<&str>, offline: bool) -> Result<(String, bool)> {
+    if let Some(git_ref) = git_ref {
+        validate_git_ref(git_ref)?;
+        if let Some(commit) = cached_immutable_ref(repo_dir, git_ref) {
+            debug!("'{}' is cached as {}, skipping fetch", git_ref, commit);
+            return Ok((commit, false));
+        }
+    }
+    let target = git_ref.unwrap_or("HEAD");
 
-    if output.status.success() {
-        return Ok(());
+    if offline {
+        let commit = resolve_cached_ref(repo_dir, git_ref)
+            .with_context(|| format!("git ref '{target}' is not in the cache (offline mode)"))?;
+        return Ok((commit, false));
     }
 
-    // If that failed, try as a remote tracking branch
-    // First clean up any partial state
-    let output2 = Command::new("git")
-        .args(["checkout", "-B", git_ref, &format!("origin/{git_ref}")])
-        .current_dir(repo_dir)
-        .output()
-        .with_context(|| format!("failed to checkout remote branch '{git_ref}'"))?;
+    debug!("Fetching '{}' in {}", target, repo_dir.display());
+    let shallow = run_git(
+        repo_dir,
+        &[
+            "fetch",
+            "--quiet",
+            "--depth",
+            "1",
+            "--filter=blob:none",
+            "origin",
+            target,
+        ],
+    );
+    let err = match shallow {
+        Ok(_) => {
+            let commit = run_git(repo_dir, &["rev-parse", "FETCH_HEAD^{commit}"])?;
+            remember_fetched_ref(repo_dir, git_ref)?;
+            return Ok((commit, true));
+        }
+        Err(err) => err,
+    };
+    let Some(git_ref) = git_ref else {
+        return Err(err);
+    };
 
-    if !output2.status.success() {
-        let stderr = String::from_utf8_lossy(&output2.stderr);
-        bail!("git checkout '{git_ref}' failed:\n{stderr}");
+    debug!(
+        "Shallow fetch of '{}' failed, deepening: {:#}",
+        git_ref, err
+    );
+    deepen_repo(repo_dir)?;
+    match resolve_cached_ref(repo_dir, Some(git_ref)) {
+        Some(commit) => Ok((commit, true)),
+        None => bail!("git ref '{git_ref}' not found in remote:\n{err:#}"),
     }
+}
 
-    Ok(())
+/// Resolve `git_ref` when it names something that can't move: a commit hash
+/// (full or abbreviated) or a tag already present in the mirror.
+fn cached_immutable_ref(repo_dir: &Path, git_ref: &str) -> Option<String> {
+    let is_hash =
+        (7..=64).contains(&git_ref.len()) && git_ref.bytes().all(|b| b.is_ascii_hexdigit());
+    let spec = if is_hash {
+        format!("{git_ref}^{{commit}}")
+    } else {
+        format!("refs/tags/{git_ref}^{{commit}}")
+    };
+    let commit = rev_parse_cached(repo_dir, &spec)?;
+    // Guard against a branch or tag that merely looks like a hash
+    if is_hash && !commit.starts_with(&git_ref.to_ascii_lowercase()) {
+        return None;
+    }
+    Some(commit)
 }
 
-/// Reset the working tree to the latest commit on the default branch.
-fn reset_to_default_branch(repo_dir: &Path) -> Result<()> {
-    debug!("Resetting to default branch in {}", repo_dir.display());
-    // Get the default branch name from the remote HEAD
-    let output = Command::new("git")
-        .args(["symbolic-ref", "refs/remotes/origin/HEAD", "--short"])
-        .current_dir(repo_dir)
-        .output()
-        .context("failed to determine default branch")?;
+/// Resolve `git_ref` (or the default branch) using only what the mirror
+/// already has.
+fn resolve_cached_ref(repo_dir: &Path, git_ref: Option<&str>) -> Option<String> {
+    let candidates = match git_ref {
+        Some(git_ref) => vec![git_ref.to_string(), format!("origin/{git_ref}")],
+        None => vec!["refs/remotes/origin/HEAD".to_string()],
+    };
+    candidates
+        .into_iter()
+        .find_map(|candidate| rev_parse_cached(repo_dir, &format!("{candidate}^{{commit}}")))
+}
 
-    let mut branch = if output.status.success() {
-        let full = String::from_utf8_lossy(&output.stdout).trim().to_string();
-        full.strip_prefix("origin/").unwrap_or(&full).to_string()
-    } else {
-        "main".to_string()
+/// Resolve `spec` to an object id without letting the partial clone
+/// lazily fetch a missing object from the remote.
+fn rev_parse_cached(repo_dir: &Path, spec: &str) -> Option<String> {
+    let args = offline_args(true, &["rev-parse", "--verify", "--quiet", spec]);
+    run_git(repo_dir, &args).ok()
+}
+
+/// Record the ref just fetched into `FETCH_HEAD` under a local name, so
+/// later runs can find it without the network: tags as `refs/tags/<name>`,
+/// branches as `refs/remotes/origin/<name>`, and the default branch as
+/// `refs/remotes/origin/HEAD`. Commit hashes need no name.
+fn remember_fetched_ref(repo_dir: &Path, git_ref: Option<&str>) -> Result<()> {
+    let fetch_head = std::fs::read_to_string(repo_dir.join("FETCH_HEAD"))
+        .context("failed to read FETCH_HEAD")?;
+    let Some(line) = fetch_head.lines().next() else {
+        return Ok(());
     };
-    debug!("Default branch: {}", branch);
+    // Format: <object>\t<not-for-merge>\t<description>
+    let mut fields = line.splitn(3, '\t');
+    let object = fields.next().unwrap_or_default();
+    let description = fields.nth(1).unwrap_or_default();
+
+    let local_ref = match git_ref {
+        None => "refs/remotes/origin/HEAD".to_string(),
+        Some(name) if description.starts_with(&format!("tag '{name}'")) => {
+            format!("refs/tags/{name}")
+        }
+        Some(name) if description.starts_with(&format!("branch '{name}'")) => {
+            format!("refs/remotes/origin/{name}")
+        }
+        Some(_) => return Ok(()),
+    };
+    debug!("Recording {} as {}", object, local_ref);
+    run_git(repo_dir, &["update-ref", &local_ref, object])?;
+    Ok(())
+}
 
-    let output = Command::new("git")
-        .args(["checkout", &branch])
+/// Check that `commit`, or the annotated tag `git_ref` names, is signed by
+/// a key `policy` trusts.
+///
+/// An annotated tag carries its own signature, so it is verified instead of
+/// the commit it points to; anything else must be a signed commit.
+fn verify_signature(
+    repo_dir: &Path,
+    git_ref: Option<&str>,
+    commit: &str,
+    policy: &SignaturePolicy,
+) -> Result<()> {
+    let tag = git_ref
+        .map(|name| format!("refs/tags/{name}"))
+        .filter(|tag| {
+            let args = offline_args(true, &["cat-file", "-t", tag]);
+            run_git(repo_dir, &args).is_ok_and(|kind| kind == "tag")
+        });
+    let (what, args) = match &tag {
+        Some(tag) => (
+            format!("tag '{}'", git_ref.unwrap_or_default()),
+            ["verify-tag", tag.as_str()],
+        ),
+        None => (format!("commit {commit}"), ["verify-commit", commit]),
+    };
+    debug!("Verifying signature of {} in {}", what, repo_dir.display());
+
+    let mut cmd = Command::new("git");
+    cmd.args(["-c", "protocol.allow=never"]);
+    if let Some(file) = &policy.allowed_signers {
+        cmd.arg("-c")
+            .arg(format!("gpg.ssh.allowedSignersFile={}", file.display()));
+    }
+    if let Some(home) = &policy.gpg_home {
+        cmd.env("GNUPGHOME", home);
+    }
+    let output = cmd
+        .args(args)
         .current_dir(repo_dir)
         .output()
-        .with_context(|| format!("failed to checkout '{branch}'"))?;
+        .with_context(|| format!("failed to run 'git {}'", args[0]))?;
 
     if !output.status.success() {
-        // Try "master" as a fallback
-        let output2 = Command::new("git")
-            .args(["checkout", "master"])
-            .current_dir(repo_dir)
-            .output()
-            .context("failed to checkout 'master'")?;
-
-        if !output2.status.success() {
-            bail!("could not determine or checkout the default branch");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let stderr = stderr.trim();
+        if stderr.is_empty() {
+            bail!("{what} is not signed");
         }
-        branch = "master".to_string();
+        bail!("{what} is not signed by a trusted key:\n{stderr}");
     }
+    Ok(())
+}
 
-    // Reset to match remote and pull latest
-    let reset_output = Command::new("git")
-        .args(["reset", "--hard", &format!("origin/{branch}")])
-        .current_dir(repo_dir)
-        .output()
-        .context("failed to run 'git reset'")?;
-
-    if !reset_output.status.success() {
-        let stderr = String::from_utf8_lossy(&reset_output.stderr);
-        eprintln!("warning: git reset --hard failed: {stderr}");
+/// Fetch full history (still blobless) for all branches and tags.
+fn deepen_repo(repo_dir: &Path) -> Result<()> {
+    let shallow = run_git(repo_dir, &["rev-parse", "--is-shallow-repository"])? == "true";
+    let mut args = vec!["fetch", "--quiet", "--tags", "--filter=blob:none"];
+    if shallow {
+        args.push("--unshallow");
     }
+    args.push("origin");
+    run_git(repo_dir, &args)?;
+    Ok(())
+}
 
+/// Validate a git ref to prevent flag injection.
+///
+/// Rejects refs that could be interpreted as git command-line flags
+/// or that contain path traversal sequences.
+fn validate_git_ref(git_ref: &str) -> Result<()> {
+    if git_ref.is_empty() {
+        bail!("git ref cannot be empty");
+    }
+    if git_ref.starts_with('-') {
+        bail!("git ref '{git_ref}' looks like a command-line flag — refusing for safety");
+    }
+    if git_ref.contains("..") {
+        bail!("git ref '{git_ref}' contains '..' — refusing for safety");
+    }
+    if git_ref.bytes().any(|b| b.is_ascii_control() || b == b' ') {
+        bail!("git ref '{git_ref}' contains whitespace or control characters");
+    }
     Ok(())
 }
 
@@ -387,6 +1063,15 @@ mod tests {
     mod is_git_url_tests {
         use super::*;
 
+        #[test]
+        fn file_url() {
+            assert!(is_git_url("file:///srv/git/repo.git"));
+            assert_eq!(
+                normalize_url("file:///srv/git/repo"),
+                "file:///srv/git/repo"
+            );
+        }
+
         #[test]
         fn https_url() {
             assert!(is_git_url("https://github.com/org/repo.git"));
@@ -491,6 +1176,91 @@ mod tests {
         }
     }
 
+    mod subdir_tests {
+        use super::*;
+
+        #[test]
+        fn shorthand_with_subdir_and_ref() {
+            let p = parse_remote("github.com/org/mono//tools/agents@v3");
+            assert_eq!(p.url, "https://github.com/org/mono");
+            assert_eq!(p.subdir, Some("tools/agents".to_string()));
+            assert_eq!(p.git_ref, Some("v3".to_string()));
+        }
+
+        #[test]
+        fn https_with_subdir() {
+            let p = parse_remote("https://github.com/org/mono.git//tools/agents/");
+            assert_eq!(p.url, "https://github.com/org/mono.git");
+            assert_eq!(p.subdir, Some("tools/agents".to_string()));
+            assert_eq!(p.git_ref, None);
+        }
+
+        #[test]
+        fn scp_style_with_subdir() {
+            let p = parse_remote("git@github.com:org/mono//agents@main");
+            assert_eq!(p.url, "git@github.com:org/mono");
+            assert_eq!(p.subdir, Some("agents".to_string()));
+            assert_eq!(p.git_ref, Some("main".to_string()));
+        }
+
+        #[test]
+        fn scheme_separator_is_not_a_subdir() {
+            let p = parse_remote("https://github.com/org/repo");
+            assert_eq!(p.subdir, None);
+        }
+
+        #[test]
+        fn normalize_keeps_subdirs_distinct() {
+            assert_eq!(
+                normalize_source("github.com/org/mono//tools/agents@v3"),
+                "https://github.com/org/mono//tools/agents"
+            );
+            assert_ne!(
+                normalize_source("github.com/org/mono//a"),
+                normalize_source("github.com/org/mono//b")
+            );
+            assert_ne!(
+                normalize_source("github.com/org/mono//a"),
+                normalize_source("github.com/org/mono")
+            );
+            assert_eq!(
+                normalize_source("https://github.com/org/mono.git//a/"),
+                normalize_source("github.com/org/mono//a")
+            );
+        }
+
+        #[test]
+        fn explicit_subdir_overrides_inline() {
+            assert_eq!(
+                source_key("github.com/org/mono", Some("tools/agents")),
+                "https://github.com/org/mono//tools/agents"
+            );
+            assert_eq!(
+                source_key("github.com/org/mono//a", Some("b")),
+                "https://github.com/org/mono//b"
+            );
+        }
+
+        #[test]
+        fn subdirs_share_one_cache() {
+            let a = parse_remote("github.com/org/mono//a");
+            let b = parse_remote("github.com/org/mono//b@v1");
+            assert_eq!(
+                get_cache_dir(&a.url).unwrap(),
+                get_cache_dir(&b.url).unwrap()
+            );
+        }
+
+        #[test]
+        fn validate_subdir_rejects_escapes() {
+            assert!(validate_subdir("tools/agents").is_ok());
+            assert!(validate_subdir("../outside").is_err());
+            assert!(validate_subdir("tools/../../x").is_err());
+            assert!(validate_subdir("/etc").is_err());
+            assert!(validate_subdir("").is_err());
+        }
+    }
+
     mod cache_dir_tests {
         use super::*;
 
@@ -553,6 +1323,70 @@ mod tests {
         }
     }
 
+    mod remote_config_tests {
+        use super::*;
+
+        fn config() -> RemoteConfig {
+            RemoteConfig {
+                hosts: vec!["git.corp.example".to_string()],
+                rewrites: BTreeMap::from([
+                    (
+                        "https://github.com/".to_string(),
+                        "https://mirror.corp.example/github/".to_string(),
+                    ),
+                    (
+                        "https://github.com/acme/".to_string(),
+                        "git@git.corp.example:acme/".to_string(),
+                    ),
+                    ("corp:".to_string(), "git.corp.example/".to_string()),
+                ]),
+                ..Default::default()
+            }
+        }
+
+        #[test]
+        fn extra_hosts_are_shorthand() {
+            let config = config();
+            assert!(is_git_url_with("git.corp.example/team/agents", &config));
+            assert!(is_git_url_with("corp:team/agents", &config));
+            assert!(!is_git_url_with("git.corp.example.local/x", &config));
+            assert!(!is_git_url("git.corp.example/team/agents"));
+            assert_eq!(
+                normalize_url_with("git.corp.example/team/agents", &config),
+                "https://git.corp.example/team/agents"
+            );
+        }
+
+        #[test]
+        fn written_url_skips_rewrites() {
+            assert_eq!(
+                written_url("github.com/org/mono//tools@v1"),
+                "https://github.com/org/mono"
+            );
+        }
+
+        #[test]
+        fn rewrites_use_longest_prefix() {
+            let config = config();
+            assert_eq!(
+                normalize_url_with("github.com/org/repo", &config),
+                "https://mirror.corp.example/github/org/repo"
+            );
+            assert_eq!(
+                normalize_url_with("https://github.com/acme/skills", &config),
+                "git@git.corp.example:acme/skills"
+            );
+            assert_eq!(
+                normalize_url_with("corp:team/agents", &config),
+                "https://git.corp.example/team/agents"
+            );
+            assert_eq!(
+                normalize_url_with("gitlab.com/org/repo", &config),
+                "https://gitlab.com/org/repo"
+            );
+        }
+    }
+
     mod normalize_url_tests {
         use super::*;
 
@@ -580,4 +1414,330 @@ mod tests {
             );
         }
     }
+
+    mod url_parts_tests {
+        use super::*;
+
+        #[test]
+        fn splits_urls() {
+            assert_eq!(
+                url_parts("https://github.com/org/repo"),
+                ("https", "github.com", "org/repo")
+            );
+            assert_eq!(
+                url_parts("ssh://git@host.example:2222/team/repo.git"),
+                ("ssh", "host.example", "team/repo.git")
+            );
+            assert_eq!(
+                url_parts("git@github.com:org/repo.git"),
+                ("ssh", "github.com", "org/repo.git")
+            );
+            assert_eq!(url_parts("file:///tmp/repo"), ("file", "", "tmp/repo"));
+        }
+    }
+
+    mod partial_clone_tests {
+        use super::*;
+        use std::fs;
+        use tempfile::TempDir;
+
+        /// Create an upstream repository with two commits and return
+        /// `(dir, file:// url, first commit, second commit)`.
+        fn setup_upstream() -> (TempDir, String, String, String) {
+            let dir = TempDir::new().unwrap();
+            let root = dir.path();
+            let commit = |msg: &str| {
+                run_git(root, &["add", "-A"]).unwrap();
+                run_git(
+                    root,
+                    &[
+                        "-c",
+                        "user.name=test",
+                        "-c",
+                        "user.email=test@example.com",
+                        "commit",
+                        "--quiet",
+                        "-m",
+                        msg,
+                    ],
+                )
+                .unwrap();
+                run_git(root, &["rev-parse", "HEAD"]).unwrap()
+            };
+
+            run_git(root, &["init", "--quiet"]).unwrap();
+            // Serve blobless clones like a real host would
+            run_git(root, &["config", "uploadpack.allowFilter", "true"]).unwrap();
+            fs::create_dir_all(root.join("skills/review")).unwrap();
+            fs::write(root.join("skills/review/SKILL.md"), "# Review v1").unwrap();
+            fs::create_dir_all(root.join("docs")).unwrap();
+            fs::write(root.join("docs/big.md"), "lots of docs").unwrap();
+            let first = commit("first");
+
+            fs::write(root.join("skills/review/SKILL.md"), "# Review v2").unwrap();
+            fs::create_dir_all(root.join("commands")).unwrap();
+            fs::write(root.join("commands/deploy.md"), "# Deploy").unwrap();
+            let second = commit("second");
+
+            let url = format!("file://{}", root.display());
+            (dir, url, first, second)
+        }
+
+        /// Initialize a mirror in a fresh cache dir and return `(cache, mirror)`.
+        fn setup_mirror(url: &str) -> (TempDir, PathBuf) {
+            let cache = TempDir::new().unwrap();
+            let mirror = cache.path().join(MIRROR_DIR);
+            init_mirror(url, &mirror).unwrap();
+            (cache, mirror)
+        }
+
+        #[test]
+        fn sparse_worktree_limits_checkout() {
+            let (_upstream, url, _, second) = setup_upstream();
+            let (cache, mirror) = setup_mirror(&url);
+
+            let commit = fetch_ref(&mirror, None, false).unwrap().0;
+            let worktree = cache.path().join(WORKTREES_DIR).join(&commit);
+            ensure_worktree(&mirror, &worktree, &commit, &["skills".to_string()], false).unwrap();
+
+            assert_eq!(commit, second);
+            assert!(worktree.join("skills/review/SKILL.md").is_file());
+            assert!(!worktree.join("docs").exists());
+            assert!(!worktree.join("commands").exists());
+            assert_eq!(
+                run_git(&mirror, &["rev-parse", "--is-shallow-repository"]).unwrap(),
+                "true"
+            );
+        }
+
+        #[test]
+        fn sparse_worktree_widens_on_demand() {
+            let (_upstream, url, _, _) = setup_upstream();
+            let (cache, mirror) = setup_mirror(&url);
+
+            let commit = fetch_ref(&mirror, None, false).unwrap().0;
+            let worktree = cache.path().join(WORKTREES_DIR).join(&commit);
+            ensure_worktree(&mirror, &worktree, &commit, &["skills".to_string()], false).unwrap();
+
+            ensure_worktree(
+                &mirror,
+                &worktree,
+                &commit,
+                &["commands".to_string()],
+                false,
+            )
+            .unwrap();
+            assert!(worktree.join("skills/review/SKILL.md").is_file());
+            assert!(worktree.join("commands/deploy.md").is_file());
+
+            ensure_worktree(&mirror, &worktree, &commit, &[], false).unwrap();
+            assert!(worktree.join("docs/big.md").is_file());
+        }
+
+        #[test]
+        fn refs_get_separate_worktrees() {
+            let (_upstream, url, first, second) = setup_upstream();
+            let (cache, mirror) = setup_mirror(&url);
+            let paths = ["skills".to_string()];
+
+            let head = fetch_ref(&mirror, None, false).unwrap().0;
+            let head_tree = cache.path().join(WORKTREES_DIR).join(&head);
+            ensure_worktree(&mirror, &head_tree, &head, &paths, false).unwrap();
+
+            let old = fetch_ref(&mirror, Some(&first), false).unwrap().0;
+            let old_tree = cache.path().join(WORKTREES_DIR).join(&old);
+            ensure_worktree(&mirror, &old_tree, &old, &paths, false).unwrap();
+
+            assert_eq!(head, second);
+            assert_eq!(old, first);
+            // Resolving the older ref must not disturb the newer checkout
+            assert_eq!(
+                fs::read_to_string(head_tree.join("skills/review/SKILL.md")).unwrap(),
+                "# Review v2"
+            );
+            assert_eq!(
+                fs::read_to_string(old_tree.join("skills/review/SKILL.md")).unwrap(),
+                "# Review v1"
+            );
+        }
+
+        #[test]
+        fn unused_worktrees_are_removed() {
+            let (_upstream, url, first, second) = setup_upstream();
+            let (cache, mirror) = setup_mirror(&url);
+            for git_ref in [&first, &second] {
+                let commit = fetch_ref(&mirror, Some(git_ref), false).unwrap().0;
+                let worktree = worktree_path(cache.path(), &commit);
+                ensure_worktree(&mirror, &worktree, &commit, &[], false).unwrap();
+            }
+
+            remove_unused_worktrees(cache.path(), &HashSet::from([second.as_str()])).unwrap();
+
+            assert_eq!(cached_worktrees(cache.path()), vec![second.clone()]);
+            let listed = run_git(&mirror, &["worktree", "list"]).unwrap();
+            assert!(listed.contains(&second));
+            assert!(!listed.contains(&first));
+        }
+
+        #[test]
+        fn abbreviated_commit_deepens_history() {
+            let (_upstream, url, first, _) = setup_upstream();
+            let (_cache, mirror) = setup_mirror(&url);
+
+            let commit = fetch_ref(&mirror, Some(&first[..10]), false).unwrap().0;
+            assert_eq!(commit, first);
+        }
+
+        #[test]
+        fn unknown_ref_is_an_error() {
+            let (_upstream, url, _, _) = setup_upstream();
+            let (_cache, mirror) = setup_mirror(&url);
+
+            let err = fetch_ref(&mirror, Some("no-such-branch"), false).unwrap_err();
+            assert!(err.to_string().contains("no-such-branch"));
+        }
+
+        #[test]
+        fn cached_tags_and_commits_skip_the_fetch() {
+            let (upstream, url, first, _) = setup_upstream();
+            run_git(upstream.path(), &["tag", "v1", &first]).unwrap();
+            let (_cache, mirror) = setup_mirror(&url);
+
+            assert_eq!(
+                fetch_ref(&mirror, Some("v1"), false).unwrap(),
+                (first.clone(), true)
+            );
+            let head = fetch_ref(&mirror, None, false).unwrap().0;
+
+            // With the remote gone, immutable refs still resolve from cache...
+            fs::remove_dir_all(upstream.path()).unwrap();
+            assert_eq!(
+                fetch_ref(&mirror, Some("v1"), false).unwrap(),
+                (first, false)
+            );
+            assert_eq!(
+                fetch_ref(&mirror, Some(&head), false).unwrap(),
+                (head, false)
+            );
+            // ...while a branch still needs the network
+            assert!(fetch_ref(&mirror, None, false).is_err());
+        }
+
+        #[test]
+        fn offline_uses_only_the_cache() {
+            let (_upstream, url, first, second) = setup_upstream();
+            let (cache, mirror) = setup_mirror(&url);
+
+            // Nothing cached yet: offline resolution fails without fetching
+            let err = fetch_ref(&mirror, None, true).unwrap_err();
+            assert!(err.to_string().contains("offline"));
+
+            let branch = run_git(&mirror, &["ls-remote", "--symref", "origin", "HEAD"]).unwrap();
+            let branch = branch
+                .lines()
+                .next()
+                .and_then(|l| l.strip_prefix("ref: refs/heads/"))
+                .and_then(|l| l.split('\t').next())
+                .unwrap()
+                .to_string();
+            fetch_ref(&mirror, Some(&branch), false).unwrap();
+            fetch_ref(&mirror, None, false).unwrap();
+
+            assert_eq!(
+                fetch_ref(&mirror, None, true).unwrap(),
+                (second.clone(), false)
+            );
+            assert_eq!(
+                fetch_ref(&mirror, Some(&branch), true).unwrap(),
+                (second.clone(), false)
+            );
+            assert!(fetch_ref(&mirror, Some(&first), true).is_err());
+
+            // Checkouts work offline as long as the content is cached
+            let worktree = cache.path().join(WORKTREES_DIR).join(&second);
+            ensure_worktree(&mirror, &worktree, &second, &["skills".to_string()], false).unwrap();
+            ensure_worktree(&mirror, &worktree, &second, &["skills".to_string()], true).unwrap();
+            // ...but widening it would need blobs from the remote
+            assert!(
+                ensure_worktree(&mirror, &worktree, &second, &["commands".to_string()], true)
+                    .is_err()
+            );
+        }
+
+        #[test]
+        fn signatures_are_checked_against_trusted_keys() {
+            let (upstream, url, first, _) = setup_upstream();
+            let root = upstream.path();
+            let keys = TempDir::new().unwrap();
+            let keygen = |name: &str| {
+                let key = keys.path().join(name);
+                let status = Command::new("ssh-keygen")
+                    .args(["-q", "-t", "ed25519", "-N", "", "-C", name, "-f"])
+                    .arg(&key)
+                    .status()
+                    .unwrap();
+                assert!(status.success());
+                key.display().to_string()
+            };
+            let (trusted, untrusted) = (keygen("trusted"), keygen("untrusted"));
+            let public = fs::read_to_string(format!("{trusted}.pub")).unwrap();
+            let allowed_signers = keys.path().join("allowed_signers");
+            fs::write(&allowed_signers, format!("test@example.com {public}")).unwrap();
+            let signed = |key: &str, args: &[&str]| {
+                let signing_key = format!("user.signingkey={key}");
+                let mut full = vec![
+                    "-c",
+                    "gpg.format=ssh",
+                    "-c",
+                    &signing_key,
+                    "-c",
+                    "user.name=test",
+                    "-c",
+                    "user.email=test@example.com",
+                ];
+                full.extend_from_slice(args);
+                run_git(root, &full).unwrap();
+            };
+            signed(&trusted, &["tag", "-s", "good", "-m", "good", &first]);
+            signed(&untrusted, &["tag", "-s", "forged", "-m", "forged", &first]);
+            run_git(
+                root,
+                &[
+                    "-c",
+                    "user.name=test",
+                    "-c",
+                    "user.email=test@example.com",
+                    "tag",
+                    "-a",
+                    "plain",
+                    "-m",
+                    "plain",
+                    &first,
+                ],
+            )
+            .unwrap();
+
+            let (_cache, mirror) = setup_mirror(&url);
+            let policy = SignaturePolicy {
+                allowed_signers: Some(allowed_signers),
+                gpg_home: None,
+            };
+            let verify = |git_ref: Option<&str>| {
+                let commit = fetch_ref(&mirror, git_ref, false).unwrap().0;
+                verify_signature(&mirror, git_ref, &commit, &policy)
+            };
+
+            assert!(verify(Some("good")).is_ok());
+            assert!(verify(Some("forged")).is_err());
+            assert!(verify(Some("plain")).is_err());
+            // The default branch head is an unsigned commit
+            assert!(verify(None).is_err());
+
+            signed(
+                &trusted,
+                &["commit", "--quiet", "--allow-empty", "-S", "-m", "signed"],
+            );
+            assert!(verify(None).is_ok());
+        }
+    }
 }
@@ -0,0 +1,304 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use log::debug;
+
+/// Convert an installed target path into an ignore-file entry.
+///
+/// Entries are anchored to the project root (`/.claude/skills/review`) so
+/// that they never match same-named files elsewhere in the tree. Returns
+/// `None` for targets outside `project_root` (e.g. global installs).
+pub(crate) fn target_entry(project_root: &Path, target: &Path) -> Option<String> {
+    let rel = target.strip_prefix(project_root).ok()?;
+    let parts: Vec<String> = rel
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    if parts.is_empty() {
+        return None;
+    }
+    Some(format!("/{}", parts.join("/")))
+}
+
+/// Add and remove entries in the agentfiles-managed block of an ignore file.
+///
+/// The block is delimited by `# >>> agentfiles <label> >>>` and
+/// `# <<< agentfiles <label> <<<` marker lines. Lines outside the block are
+/// never touched. Entries inside the block are kept sorted and unique, and
+/// the block is dropped entirely once it becomes empty.
+///
+/// Creates the file (and its parent directory) when entries are added to a
+/// file that doesn't exist yet.
+pub(crate) fn update_block(
+    path: &Path,
+    label: &str,
+    add: &[String],
+    remove: &[String],
+) -> Result<()> {
+    debug!(
+        "Updating '{}' block in {} (+{}, -{})",
+        label,
+        path.display(),
+        add.len(),
+        remove.len()
+    );
+    if !path.is_file() && add.is_empty() {
+        return Ok(());
+    }
+    let content = read_file(path)?;
+
+    let mut entries = read_block(&content, label);
+    entries.retain(|e| !remove.contains(e));
+    entries.extend(add.iter().cloned());
+    write_block(path, &content, label, entries)
+}
+
+/// Add `add` to the agentfiles-managed block for `label`, dropping the
+/// existing entries for which `keep` returns false.
+///
+/// Same format and guarantees as `update_block`, for callers that know the
+/// block's current entries may be stale (e.g. targets that were since
+/// deleted) but not which ones.
+pub(crate) fn merge_block(
+    path: &Path,
+    label: &str,
+    add: &[String],
+    keep: impl Fn(&str) -> bool,
+) -> Result<()> {
+    debug!(
+        "Merging {} entries into '{}' block in {}",
+        add.len(),
+        label,
+        path.display()
+    );
+    if !path.is_file() && add.is_empty() {
+        return Ok(());
+    }
+    let content = read_file(path)?;
+
+    let mut entries = read_block(&content, label);
+    entries.retain(|e| keep(e));
+    entries.extend(add.iter().cloned());
+    write_block(path, &content, label, entries)
+}
+
+fn read_file(path: &Path) -> Result<String> {
+    if path.is_file() {
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))
+    } else {
+        Ok(String::new())
+    }
+}
+
+/// Sort, deduplicate and write `entries` as the block for `label`, leaving
+/// the file untouched if nothing changed.
+fn write_block(path: &Path, content: &str, label: &str, mut entries: Vec<String>) -> Result<()> {
+    entries.sort();
+    entries.dedup();
+
+    let updated = render_block(content, label, &entries);
+    if updated == content {
+        return Ok(());
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("failed to create directory: {}", parent.display()))?;
+    }
+    fs::write(path, updated).with_context(|| format!("failed to write {}", path.display()))
+}
+
+/// Entries currently listed in the managed block for `label`.
+pub(crate) fn read_block(content: &str, label: &str) -> Vec<String> {
+    let (begin, end) = markers(label);
+    content
+        .lines()
+        .skip_while(|l| l.trim() != begin)
+        .skip(1)
+        .take_while(|l| l.trim() != end)
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+/// Rewrite `content` with the managed block replaced by `entries`.
+///
+/// An existing block is replaced in place; a new block is appended at the
+/// end of the file.
+fn render_block(content: &str, label: &str, entries: &[String]) -> String {
+    let (begin, end) = markers(label);
+
+    let mut lines: Vec<&str> = Vec::new();
+    let mut after: Vec<&str> = Vec::new();
+    let mut found = false;
+    let mut inside = false;
+    for line in content.lines() {
+        if !found && line.trim() == begin {
+            found = true;
+            inside = true;
+        } else if inside {
+            inside = line.trim() != end;
+        } else if found {
+            after.push(line);
+        } else {
+            lines.push(line);
+        }
+    }
+
+    if entries.is_empty() {
+        if found {
+            // Drop the blank separator that preceded the block
+            while lines.last().is_some_and(|l| l.trim().is_empty()) {
+                lines.pop();
+            }
+        }
+    } else {
+        if !found && lines.last().is_some_and(|l| !l.trim().is_empty()) {
+            // Separate a freshly appended block from hand-written lines
+            lines.push("");
+        }
+        lines.push(&begin);
+        lines.extend(entries.iter().map(String::as_str));
+        lines.push(&end);
+    }
+    lines.extend(after);
+
+    if lines.is_empty() {
+        String::new()
+    } else {
+        lines.join("\n") + "\n"
+    }
+}
+
+fn markers(label: &str) -> (String, String) {
+    (
+        format!("# >>> agentfiles {label} >>>"),
+        format!("# <<< agentfiles {label} <<<"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn target_entry_is_anchored_to_root() {
+        let root = Path::new("/project");
+        assert_eq!(
+            target_entry(root, Path::new("/project/.claude/skills/review")),
+            Some("/.claude/skills/review".to_string())
+        );
+        assert_eq!(target_entry(root, Path::new("/home/u/.claude/x")), None);
+        assert_eq!(target_entry(root, root), None);
+    }
+
+    #[test]
+    fn creates_block_in_new_file() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join("info").join("exclude");
+
+        update_block(&path, "local", &["/b".to_string(), "/a".to_string()], &[])?;
+
+        assert_eq!(
+            fs::read_to_string(&path)?,
+            "# >>> agentfiles local >>>\n/a\n/b\n# <<< agentfiles local <<<\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn preserves_hand_written_lines() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join(".gitignore");
+        fs::write(&path, "target/\n*.log\n")?;
+
+        update_block(&path, "local", &["/.claude/commands/x.md".to_string()], &[])?;
+        let content = fs::read_to_string(&path)?;
+        assert!(content.starts_with("target/\n*.log\n\n# >>> agentfiles local >>>\n"));
+
+        // Merging keeps existing entries
+        update_block(&path, "local", &["/.claude/commands/y.md".to_string()], &[])?;
+        assert_eq!(
+            read_block(&fs::read_to_string(&path)?, "local"),
+            vec!["/.claude/commands/x.md", "/.claude/commands/y.md"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn removing_last_entry_drops_block() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join(".gitignore");
+        fs::write(&path, "target/\n")?;
+
+        update_block(&path, "local", &["/a".to_string()], &[])?;
+        update_block(&path, "local", &[], &["/a".to_string()])?;
+
+        assert_eq!(fs::read_to_string(&path)?, "target/\n");
+        Ok(())
+    }
+
+    #[test]
+    fn block_replaced_in_place() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join(".gitignore");
+        fs::write(
+            &path,
+            "a\n# >>> agentfiles local >>>\n/old\n# <<< agentfiles local <<<\nb\n",
+        )?;
+
+        update_block(&path, "local", &["/new".to_string()], &["/old".to_string()])?;
+
+        assert_eq!(
+            fs::read_to_string(&path)?,
+            "a\n# >>> agentfiles local >>>\n/new\n# <<< agentfiles local <<<\nb\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn blocks_with_different_labels_are_independent() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join(".gitignore");
+
+        update_block(&path, "local", &["/a".to_string()], &[])?;
+        update_block(&path, "managed", &["/b".to_string()], &[])?;
+
+        let content = fs::read_to_string(&path)?;
+        assert_eq!(read_block(&content, "local"), vec!["/a"]);
+        assert_eq!(read_block(&content, "managed"), vec!["/b"]);
+        Ok(())
+    }
+
+    #[test]
+    fn merge_block_keeps_entries_it_is_told_to() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join(".gitignore");
+        fs::write(&path, "node_modules/\n")?;
+
+        update_block(
+            &path,
+            "installed",
+            &["/old".to_string(), "/keep".to_string()],
+            &[],
+        )?;
+        merge_block(&path, "installed", &["/new".to_string()], |e| e != "/old")?;
+
+        let content = fs::read_to_string(&path)?;
+        assert!(content.starts_with("node_modules/\n"));
+        assert_eq!(read_block(&content, "installed"), vec!["/keep", "/new"]);
+        Ok(())
+    }
+
+    #[test]
+    fn missing_file_with_nothing_to_add_is_untouched() -> Result<()> {
+        let dir = TempDir::new()?;
+        let path = dir.path().join(".gitignore");
+        update_block(&path, "local", &[], &["/a".to_string()])?;
+        assert!(!path.exists());
+        Ok(())
+    }
+}
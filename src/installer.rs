@@ -1,11 +1,16 @@
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use log::debug;
 
+use crate::ledger::{Drift, Ledger};
 use crate::manifest::FileMapping;
+use crate::template::{self, Vars};
 use crate::types::{AgentProvider, FileKind, FileScope, FileStrategy};
+use crate::{overlay, scanner, store};
 
 /// Result of installing a single file to a single provider.
 #[derive(Debug)]
@@ -15,34 +20,141 @@ pub(crate) struct InstallResult {
     pub target: String,
     pub strategy: FileStrategy,
     pub kind: FileKind,
+    /// Store snapshot the target links to, for link installs.
+    pub snapshot: Option<String>,
+    /// Content hash of what was installed (see `FileMapping::hash`).
+    pub hash: String,
+    /// Files of a copied directory that differ from the copy it replaced.
+    pub changes: Vec<FileChange>,
+    /// Whether the target was a symlink left by a previous `link` install.
+    pub replaced_link: bool,
+    /// Where the previous target was moved to (see `PlannedInstall::backup`).
+    pub backup: Option<PathBuf>,
 }
 
-/// Install all files from a list of file mappings to the specified providers.
+/// How a file inside a copied directory differs from the previous install.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum FileChange {
+    Added(PathBuf),
+    Modified(PathBuf),
+    /// In the previous copy but no longer in the source; removed on install.
+    Removed(PathBuf),
+}
+
+/// A file to install for one provider, as decided by `plan`.
+#[derive(Debug)]
+pub(crate) struct PlannedInstall {
+    pub provider: AgentProvider,
+    pub file: FileMapping,
+    /// Absolute path of the file or directory to install.
+    pub source_path: PathBuf,
+    /// The provider's directory for the file's kind.
+    pub target_dir: PathBuf,
+    /// Where the file lands inside `target_dir`.
+    pub target: PathBuf,
+    /// See `InstallResult::changes`.
+    pub changes: Vec<FileChange>,
+    /// See `InstallResult::replaced_link`.
+    pub replaced_link: bool,
+    /// Keep the existing target as `<name>.orig` instead of discarding it.
+    pub backup: bool,
+    /// Content of files with template placeholders, rendered for this
+    /// provider (see `template::render_item`). Rendered items are copied.
+    pub rendered: Option<Vec<(PathBuf, String)>>,
+}
+
+/// Why an existing target shouldn't be overwritten without asking.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Conflict {
+    /// Edited since agentfiles installed it.
+    Modified,
+    /// Not installed by agentfiles.
+    Unmanaged,
+}
+
+impl std::fmt::Display for Conflict {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Conflict::Modified => write!(f, "modified locally"),
+            Conflict::Unmanaged => write!(f, "not installed by agentfiles"),
+        }
+    }
+}
+
+/// Check whether carrying out `item` would lose local work at its target,
+/// using the hash `ledger` recorded when it was installed.
 ///
-/// For each file, iterates over `providers` and installs the file to every
-/// provider that supports the file's kind. The `source_root` is the directory
-/// containing the source files (used to resolve relative source paths).
+/// A target missing from the ledger that already holds exactly what `item`
+/// installs is not reported: it can only be an earlier install. Targets
+/// recorded without a hash can't be checked and are never reported.
+pub(crate) fn conflict(item: &PlannedInstall, ledger: &Ledger) -> Option<Conflict> {
+    let target = item.target.as_path();
+    if !target.exists() && !target.is_symlink() {
+        return None;
+    }
+    match ledger.entries.iter().find(|e| e.target == target) {
+        None if item.rendered.is_none()
+            && store::hash_tree(target).is_ok_and(|hash| hash == item.file.hash) =>
+        {
+            None
+        }
+        None => Some(Conflict::Unmanaged),
+        Some(entry) => (entry.drift() == Some(Drift::Modified)).then_some(Conflict::Modified),
+    }
+}
+
+/// Where `target` is kept when it is backed up: `<name>.orig` beside it.
+pub(crate) fn orig_path(target: &Path) -> PathBuf {
+    let mut name = target.file_name().unwrap_or_default().to_os_string();
+    name.push(".orig");
+    target.with_file_name(name)
+}
+
+impl PlannedInstall {
+    fn result(
+        &self,
+        snapshot: Option<String>,
+        hash: Option<String>,
+        backup: Option<PathBuf>,
+    ) -> InstallResult {
+        InstallResult {
+            provider: self.provider,
+            source: self.file.path.display().to_string(),
+            target: self.target.display().to_string(),
+            strategy: self.file.strategy,
+            kind: self.file.kind,
+            snapshot,
+            hash: hash.unwrap_or_else(|| self.file.hash.clone()),
+            changes: self.changes.clone(),
+            replaced_link: self.replaced_link,
+            backup,
+        }
+    }
+}
+
+/// Decide where each of `files` is installed, without touching the disk.
 ///
-/// When `dry_run` is true, resolves target paths and builds `InstallResult`
-/// entries without creating directories or copying/linking files.
+/// For each file, iterates over `providers` and plans an install for every
+/// provider that supports the file's kind. The `source_root` is the
+/// directory containing the source files (used to resolve relative source
+/// paths). Both real installs and `--dry-run` go through this, so a preview
+/// always matches what would be written.
 ///
-/// Returns a list of `InstallResult` entries describing what was installed.
-pub(crate) fn install(
+/// Files with `{{ var }}` placeholders are rendered with `vars` here, so an
+/// undefined variable fails before anything is installed. Items in
+/// `renames` (see `check_renames`) are installed under their new name, with
+/// the frontmatter `name` to match. Either way the content differs from
+/// the source, so such items are always copied.
+pub(crate) fn plan(
     files: &[FileMapping],
     providers: &[AgentProvider],
     scope: &FileScope,
     project_root: &Path,
     source_root: &Path,
-    dry_run: bool,
-) -> Result<Vec<InstallResult>> {
-    debug!(
-        "Installing {} file(s) to {} provider(s) (dry_run={})",
-        files.len(),
-        providers.len(),
-        dry_run
-    );
-    let mut results = Vec::new();
-
+    vars: &Vars,
+    renames: &BTreeMap<String, String>,
+) -> Result<Vec<PlannedInstall>> {
+    let mut planned = Vec::new();
     for file in files {
         let source_path = source_root.join(&file.path);
         debug!(
@@ -73,107 +185,415 @@ pub(crate) fn install(
             }
 
             let target_dir = provider.get_target_dir(scope, &file.kind, project_root)?;
-            let target_path = resolve_target_path(&file.path, &target_dir)?;
+            let target = resolve_target_path(file, &target_dir, renames)?;
             debug!(
                 "Target: {} -> {} (provider={})",
                 source_path.display(),
-                target_path.display(),
+                target.display(),
                 provider
             );
+            let mut rendered = template::render_item(&source_path, &file.path, vars, *provider)?;
+            if let Some(name) = renames.get(&scanner::item_key(file)) {
+                rename_markdown(&source_path, file.kind, name, &mut rendered)?;
+            }
+            let mut file = file.clone();
+            if rendered.is_some() && file.strategy == FileStrategy::Link {
+                debug!("Copying changed {} instead of linking", file.path.display());
+                file.strategy = FileStrategy::Copy;
+            }
+            let replaced_link = target.is_symlink();
+            let changes = if file.strategy == FileStrategy::Copy
+                && source_path.is_dir()
+                && target.is_dir()
+                && !replaced_link
+            {
+                dir_changes(
+                    &source_path,
+                    &target,
+                    rendered.as_deref().unwrap_or_default(),
+                )?
+            } else {
+                vec![]
+            };
+            planned.push(PlannedInstall {
+                provider: *provider,
+                file,
+                source_path: source_path.clone(),
+                target_dir,
+                target,
+                changes,
+                replaced_link,
+                backup: false,
+                rendered,
+            });
+        }
+    }
+    Ok(planned)
+}
+
+/// Name of the directories where new files are staged and replaced ones
+/// are kept until the transaction ends. The scanner skips them.
+pub(crate) const STAGING_PREFIX: &str = ".agentfiles-staging-";
 
-            if !dry_run {
-                if let Some(parent) = target_path.parent() {
-                    fs::create_dir_all(parent).with_context(|| {
-                        format!("failed to create directory: {}", parent.display())
+/// Staging directories older than this are treated as leftovers of an
+/// install that was killed before it could clean up.
+const STALE_STAGING_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// A set of installs that is applied all together or not at all.
+///
+/// Each target is first written to a staging directory beside its
+/// provider's base directory (so agents never pick up a half-written item,
+/// and the final move is a same-filesystem rename), then swapped into
+/// place; the file it replaces is moved aside rather than deleted.
+/// `commit` discards the replaced files. Dropping the transaction without
+/// committing — including on an early `?` return — restores every replaced
+/// target, removes new ones and any directories that were created for them.
+///
+/// Link-strategy files are first snapshotted into the content-addressed
+/// `store` (see `store::snapshot`) and the target links to the snapshot, so
+/// later updates of the source never change what an installed link shows.
+#[derive(Debug)]
+pub(crate) struct Transaction {
+    dry_run: bool,
+    committed: bool,
+    /// Staging directory for each directory staging happens in.
+    staging: BTreeMap<PathBuf, PathBuf>,
+    /// Directories created for targets, outermost first.
+    created_dirs: Vec<PathBuf>,
+    /// Targets swapped into place, with where their previous content went.
+    swapped: Vec<(PathBuf, Option<PathBuf>)>,
+    /// Counter for unique names in the staging directories.
+    next: usize,
+}
+
+impl Transaction {
+    /// Start a transaction. With `dry_run`, `install` only reports what it
+    /// would do.
+    pub(crate) fn new(dry_run: bool) -> Self {
+        Transaction {
+            dry_run,
+            committed: false,
+            staging: BTreeMap::new(),
+            created_dirs: Vec::new(),
+            swapped: Vec::new(),
+            next: 0,
+        }
+    }
+
+    /// Install everything in `planned` as part of this transaction.
+    ///
+    /// All files are staged before any target is touched, so an unreadable
+    /// source fails without changing anything.
+    pub(crate) fn install(
+        &mut self,
+        planned: &[PlannedInstall],
+        store: &Path,
+    ) -> Result<Vec<InstallResult>> {
+        if self.dry_run {
+            return Ok(planned
+                .iter()
+                .map(|p| {
+                    let backup = (p.backup && p.target.exists()).then(|| orig_path(&p.target));
+                    p.result(None, None, backup)
+                })
+                .collect());
+        }
+
+        // Snapshot once per source; every provider links to the same copy
+        let mut snapshots: BTreeMap<&Path, (String, PathBuf)> = BTreeMap::new();
+        let mut staged = Vec::with_capacity(planned.len());
+        for item in planned {
+            let path = self.staging_path(&item.target_dir)?;
+            let snapshot = match item.file.strategy {
+                FileStrategy::Copy => {
+                    debug!(
+                        "Copying {} -> {}",
+                        item.source_path.display(),
+                        item.target.display()
+                    );
+                    if item.source_path.is_dir() {
+                        copy_dir_recursive(&item.source_path, &path)?;
+                    } else {
+                        fs::copy(&item.source_path, &path).with_context(|| {
+                            format!(
+                                "failed to copy {} -> {}",
+                                item.source_path.display(),
+                                item.target.display()
+                            )
+                        })?;
+                    }
+                    for (relative, text) in item.rendered.iter().flatten() {
+                        let rendered = template::item_path(&path, relative);
+                        fs::write(&rendered, text)
+                            .with_context(|| format!("failed to write {}", rendered.display()))?;
+                    }
+                    None
+                }
+                FileStrategy::Link => {
+                    debug!(
+                        "Symlinking {} -> {}",
+                        item.source_path.display(),
+                        item.target.display()
+                    );
+                    if !snapshots.contains_key(item.source_path.as_path()) {
+                        let snapshot = store::snapshot(&item.source_path, store)?;
+                        snapshots.insert(&item.source_path, snapshot);
+                    }
+                    let (hash, abs_source) = &snapshots[item.source_path.as_path()];
+                    symlink(abs_source, &path).with_context(|| {
+                        format!(
+                            "failed to symlink {} -> {}",
+                            abs_source.display(),
+                            item.target.display()
+                        )
                     })?;
+                    Some(hash.clone())
                 }
+            };
+            // What is installed differs from the source once rendered
+            let hash = match item.rendered {
+                Some(_) => Some(store::hash_tree(&path)?),
+                None => None,
+            };
+            staged.push((path, snapshot, hash));
+        }
 
-                // Place the file
-                match file.strategy {
-                    FileStrategy::Copy => {
-                        debug!(
-                            "Copying {} -> {}",
-                            source_path.display(),
-                            target_path.display()
-                        );
-                        if source_path.is_dir() {
-                            copy_dir_recursive(&source_path, &target_path)?;
-                        } else {
-                            fs::copy(&source_path, &target_path).with_context(|| {
-                                format!(
-                                    "failed to copy {} -> {}",
-                                    source_path.display(),
-                                    target_path.display()
-                                )
-                            })?;
-                        }
-                    }
-                    FileStrategy::Link => {
-                        debug!(
-                            "Symlinking {} -> {}",
-                            source_path.display(),
-                            target_path.display()
-                        );
-                        if target_path.exists() || target_path.is_symlink() {
-                            if target_path.is_dir() && !target_path.is_symlink() {
-                                fs::remove_dir_all(&target_path)?;
-                            } else {
-                                fs::remove_file(&target_path)?;
-                            }
-                        }
-
-                        let abs_source = source_path.canonicalize().with_context(|| {
-                            format!("failed to resolve absolute path: {}", source_path.display())
-                        })?;
+        let mut results = Vec::with_capacity(planned.len());
+        for (item, (path, snapshot, hash)) in planned.iter().zip(staged) {
+            let backup = self.swap(&path, &item.target, item.backup)?;
+            results.push(item.result(snapshot, hash, backup));
+        }
+        Ok(results)
+    }
 
-                        #[cfg(unix)]
-                        std::os::unix::fs::symlink(&abs_source, &target_path).with_context(
-                            || {
-                                format!(
-                                    "failed to symlink {} -> {}",
-                                    abs_source.display(),
-                                    target_path.display()
-                                )
-                            },
-                        )?;
-
-                        #[cfg(windows)]
-                        {
-                            if abs_source.is_dir() {
-                                std::os::windows::fs::symlink_dir(&abs_source, &target_path)
-                                    .with_context(|| {
-                                        format!(
-                                            "failed to symlink {} -> {}",
-                                            abs_source.display(),
-                                            target_path.display()
-                                        )
-                                    })?;
-                            } else {
-                                std::os::windows::fs::symlink_file(&abs_source, &target_path)
-                                    .with_context(|| {
-                                        format!(
-                                            "failed to symlink {} -> {}",
-                                            abs_source.display(),
-                                            target_path.display()
-                                        )
-                                    })?;
-                            }
-                        }
-                    }
+    /// Keep everything installed so far and discard the replaced files.
+    pub(crate) fn commit(mut self) {
+        debug!("Committing {} install(s)", self.swapped.len());
+        self.committed = true;
+        self.swapped.clear();
+        self.remove_staging();
+    }
+
+    /// A fresh path in the staging directory for `target_dir`, creating both
+    /// directories as needed.
+    ///
+    /// Staging happens in the directory containing the provider's base
+    /// directory (the parent of `.claude` for `.claude/skills`), resolved
+    /// through symlinks so it is on the same filesystem as the target.
+    /// Stale staging directories found there are removed first.
+    fn staging_path(&mut self, target_dir: &Path) -> Result<PathBuf> {
+        if !target_dir.is_dir() {
+            let missing: Vec<PathBuf> = target_dir
+                .ancestors()
+                .take_while(|dir| !dir.exists())
+                .map(Path::to_path_buf)
+                .collect();
+            fs::create_dir_all(target_dir)
+                .with_context(|| format!("failed to create directory: {}", target_dir.display()))?;
+            self.created_dirs.extend(missing.into_iter().rev());
+        }
+        let resolved = fs::canonicalize(target_dir)
+            .with_context(|| format!("failed to resolve {}", target_dir.display()))?;
+        let parent = resolved
+            .parent()
+            .and_then(Path::parent)
+            .unwrap_or(&resolved)
+            .to_path_buf();
+        if !self.staging.contains_key(&parent) {
+            // Earlier versions staged inside the target directory itself
+            remove_stale_staging(target_dir);
+            remove_stale_staging(&parent);
+            let staging = parent.join(format!("{STAGING_PREFIX}{}", std::process::id()));
+            fs::create_dir_all(&staging)
+                .with_context(|| format!("failed to create directory: {}", staging.display()))?;
+            self.staging.insert(parent.clone(), staging);
+        }
+        self.next += 1;
+        Ok(self.staging[&parent].join(self.next.to_string()))
+    }
+
+    /// Move `staged` to `target`, keeping whatever was there beside it:
+    /// in the staging directory until the transaction ends, or as
+    /// `<name>.orig` for good with `keep`, whose path is returned.
+    fn swap(&mut self, staged: &Path, target: &Path, keep: bool) -> Result<Option<PathBuf>> {
+        let backup = if target.exists() || target.is_symlink() {
+            let backup = if keep {
+                let orig = orig_path(target);
+                if orig.exists() || orig.is_symlink() {
+                    bail!(
+                        "cannot back up {}: {} already exists",
+                        target.display(),
+                        orig.display()
+                    );
                 }
+                orig
+            } else {
+                staged.with_extension("old")
+            };
+            debug!("Moving aside {}", target.display());
+            fs::rename(target, &backup)
+                .with_context(|| format!("failed to replace {}", target.display()))?;
+            Some(backup)
+        } else {
+            None
+        };
+        if let Err(err) = fs::rename(staged, target) {
+            if let Some(backup) = &backup {
+                let _ = fs::rename(backup, target);
             }
+            return Err(err).with_context(|| format!("failed to install {}", target.display()));
+        }
+        self.swapped.push((target.to_path_buf(), backup.clone()));
+        Ok(backup.filter(|_| keep))
+    }
 
-            results.push(InstallResult {
-                provider: *provider,
-                source: file.path.display().to_string(),
-                target: target_path.display().to_string(),
-                strategy: file.strategy,
-                kind: file.kind,
+    /// Undo every swap, newest first, then clean up.
+    fn rollback(&mut self) {
+        if !self.swapped.is_empty() {
+            debug!("Rolling back {} install(s)", self.swapped.len());
+        }
+        for (target, backup) in self.swapped.drain(..).rev() {
+            let restored = remove_path(&target).and_then(|()| match &backup {
+                Some(backup) => fs::rename(backup, &target).map_err(Into::into),
+                None => Ok(()),
             });
+            if let Err(err) = restored {
+                eprintln!("warning: could not restore {}: {err:#}", target.display());
+            }
+        }
+        self.remove_staging();
+        for dir in self.created_dirs.drain(..).rev() {
+            // Only succeeds for directories that are still empty
+            let _ = fs::remove_dir(&dir);
         }
     }
 
-    Ok(results)
+    fn remove_staging(&mut self) {
+        for (_, staging) in std::mem::take(&mut self.staging) {
+            if let Err(err) = fs::remove_dir_all(&staging) {
+                debug!("Could not remove {}: {}", staging.display(), err);
+            }
+        }
+    }
+}
+
+impl Drop for Transaction {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.rollback();
+        }
+    }
+}
+
+/// Remove the staging directories in `dir` that were left behind more than
+/// `STALE_STAGING_AGE` ago.
+fn remove_stale_staging(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let stale = entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(STAGING_PREFIX)
+            && entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|t| t.elapsed().ok())
+                .is_some_and(|age| age > STALE_STAGING_AGE);
+        if stale {
+            debug!(
+                "Removing stale staging directory {}",
+                entry.path().display()
+            );
+            if let Err(err) = fs::remove_dir_all(entry.path()) {
+                debug!("Could not remove {}: {}", entry.path().display(), err);
+            }
+        }
+    }
+}
+
+/// Remove a file, symlink or directory.
+fn remove_path(path: &Path) -> Result<()> {
+    if path.is_dir() && !path.is_symlink() {
+        fs::remove_dir_all(path)?;
+    } else if path.exists() || path.is_symlink() {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Create a symlink at `link` pointing to `target`.
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(target, link);
+
+    #[cfg(windows)]
+    if target.is_dir() {
+        std::os::windows::fs::symlink_dir(target, link)
+    } else {
+        std::os::windows::fs::symlink_file(target, link)
+    }
+}
+
+/// Differences between a source directory and the copy of it at `target`.
+///
+/// A copy replaces the whole directory, so files that are no longer in the
+/// source are reported as removed rather than left behind. Files in
+/// `rendered` are compared by their rendered content.
+fn dir_changes(
+    source: &Path,
+    target: &Path,
+    rendered: &[(PathBuf, String)],
+) -> Result<Vec<FileChange>> {
+    let old = dir_files(target, target, true)?;
+    let new = dir_files(source, source, false)?;
+    let mut changes = Vec::new();
+    for (path, new_path) in &new {
+        match old.get(path) {
+            None => changes.push(FileChange::Added(path.clone())),
+            Some(old_path) => {
+                let content = match rendered.iter().find(|(r, _)| r == path) {
+                    Some((_, text)) => text.as_bytes().to_vec(),
+                    None => fs::read(new_path)?,
+                };
+                let same = !old_path.is_symlink() && fs::read(old_path).ok() == Some(content);
+                if !same {
+                    changes.push(FileChange::Modified(path.clone()));
+                }
+            }
+        }
+    }
+    for path in old.keys().filter(|path| !new.contains_key(*path)) {
+        changes.push(FileChange::Removed(path.clone()));
+    }
+    changes.sort_by(|a, b| change_path(a).cmp(change_path(b)));
+    Ok(changes)
+}
+
+fn change_path(change: &FileChange) -> &Path {
+    match change {
+        FileChange::Added(path) | FileChange::Modified(path) | FileChange::Removed(path) => path,
+    }
+}
+
+/// Files under `dir`, keyed by their path relative to `root`. Symlinks are
+/// skipped unless `with_symlinks`, matching what `copy_dir_recursive` copies.
+fn dir_files(root: &Path, dir: &Path, with_symlinks: bool) -> Result<BTreeMap<PathBuf, PathBuf>> {
+    let mut files = BTreeMap::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("failed to read {}", dir.display()))? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let path = entry.path();
+        if file_type.is_dir() {
+            files.extend(dir_files(root, &path, with_symlinks)?);
+        } else if !file_type.is_symlink() || with_symlinks {
+            let relative = path.strip_prefix(root)?.to_path_buf();
+            files.insert(relative, path);
+        }
+    }
+    Ok(files)
 }
 
 /// Resolve where the file should land inside the target directory.
@@ -181,16 +601,95 @@ pub(crate) fn install(
 /// Uses the last component of the relative path as the target name:
 /// - Skills (directories): `skills/review` -> `<target_dir>/review`
 /// - Commands/agents (files): `commands/deploy.md` -> `<target_dir>/deploy.md`
-fn resolve_target_path(relative_path: &Path, target_dir: &Path) -> Result<std::path::PathBuf> {
-    let file_name = relative_path
-        .file_name()
-        .context("file path has no filename")?;
+///
+/// An item in `renames` keeps its extension under the new name:
+/// `commands/deploy.md` renamed to `ship` -> `<target_dir>/ship.md`.
+pub(crate) fn resolve_target_path(
+    file: &FileMapping,
+    target_dir: &Path,
+    renames: &BTreeMap<String, String>,
+) -> Result<PathBuf> {
+    let file_name = file.path.file_name().context("file path has no filename")?;
+    let Some(name) = renames.get(&scanner::item_key(file)) else {
+        return Ok(target_dir.join(file_name));
+    };
+    let mut renamed = PathBuf::from(name);
+    if file.kind != FileKind::Skill
+        && let Some(extension) = file.path.extension()
+    {
+        renamed.set_extension(extension);
+    }
+    Ok(target_dir.join(renamed))
+}
+
+/// Check a dependency's `renames`, keyed by `scanner::item_key` like
+/// kind-prefixed `pick` entries (`commands/ops/deploy`), against the
+/// `files` it installs from `source`: every entry must name one of them,
+/// new names must be plain file names, and no two items of a kind may end
+/// up with the same name.
+pub(crate) fn check_renames(
+    files: &[FileMapping],
+    renames: &BTreeMap<String, String>,
+    source: &str,
+) -> Result<()> {
+    for (item, name) in renames {
+        if !files.iter().any(|f| scanner::item_key(f) == *item) {
+            bail!("rename for '{item}' matches no item of {source}");
+        }
+        if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+            bail!("cannot rename '{item}' to '{name}': not a valid name");
+        }
+    }
+    let mut names: HashMap<(FileKind, PathBuf), &Path> = HashMap::new();
+    for file in files {
+        let name = resolve_target_path(file, Path::new(""), renames)?;
+        if let Some(other) = names.insert((file.kind, name.clone()), &file.path) {
+            bail!(
+                "{} and {} would both be installed as {}; rename one of them",
+                other.display(),
+                file.path.display(),
+                name.display()
+            );
+        }
+    }
+    Ok(())
+}
 
-    Ok(target_dir.join(file_name))
+/// Set the frontmatter `name` of a renamed item's markdown file to `name`,
+/// in its `rendered` content (see `PlannedInstall::rendered`). Skills always
+/// get one; commands and agents only when they already declare a name.
+fn rename_markdown(
+    source_path: &Path,
+    kind: FileKind,
+    name: &str,
+    rendered: &mut Option<Vec<(PathBuf, String)>>,
+) -> Result<()> {
+    let relative = if kind == FileKind::Skill {
+        PathBuf::from("SKILL.md")
+    } else {
+        PathBuf::new()
+    };
+    let files = rendered.get_or_insert_with(Vec::new);
+    let text = match files.iter().position(|(r, _)| *r == relative) {
+        Some(i) => files.remove(i).1,
+        None => {
+            let path = template::item_path(source_path, &relative);
+            fs::read_to_string(&path)
+                .with_context(|| format!("failed to read {}", path.display()))?
+        }
+    };
+    let text = if kind == FileKind::Skill || overlay::has_frontmatter_field(&text, "name") {
+        let fields = BTreeMap::from([("name".to_string(), serde_json::json!(name))]);
+        overlay::set_frontmatter(&text, &fields)
+    } else {
+        text
+    };
+    files.push((relative, text));
+    Ok(())
 }
 
 /// Recursively copy a directory, skipping symlinks to avoid infinite loops.
-fn copy_dir_recursive(src: &Path, dst: &Path) -> Result<()> {
+pub(crate) fn copy_dir_recursive(src: &Path, dst: &Path) -> Result<()> {
     debug!("Recursively copying {} -> {}", src.display(), dst.display());
     fs::create_dir_all(dst)?;
     for entry in fs::read_dir(src)? {
@@ -216,9 +715,33 @@ fn copy_dir_recursive(src: &Path, dst: &Path) -> Result<()> {
 mod tests {
     use super::*;
     use crate::types::FileKind;
-    use std::path::PathBuf;
     use tempfile::TempDir;
 
+    /// Plan and apply an install in one transaction.
+    fn install(
+        files: &[FileMapping],
+        providers: &[AgentProvider],
+        scope: &FileScope,
+        project_root: &Path,
+        source_root: &Path,
+        store: &Path,
+        dry_run: bool,
+    ) -> Result<Vec<InstallResult>> {
+        let planned = plan(
+            files,
+            providers,
+            scope,
+            project_root,
+            source_root,
+            &Vars::default(),
+            &BTreeMap::new(),
+        )?;
+        let mut transaction = Transaction::new(dry_run);
+        let results = transaction.install(&planned, store)?;
+        transaction.commit();
+        Ok(results)
+    }
+
     #[test]
     fn install_skill_directory_to_claude_code() -> Result<()> {
         let src_dir = TempDir::new()?;
@@ -236,6 +759,7 @@ mod tests {
             path: PathBuf::from("skills/review"),
             kind: FileKind::Skill,
             strategy: FileStrategy::Copy,
+            hash: String::new(),
         }];
 
         let results = install(
@@ -244,6 +768,7 @@ mod tests {
             &FileScope::Project,
             dst_dir.path(),
             src_dir.path(),
+            &src_dir.path().join(".store"),
             false,
         )?;
 
@@ -277,6 +802,7 @@ mod tests {
             path: PathBuf::from("commands/deploy.md"),
             kind: FileKind::Command,
             strategy: FileStrategy::Copy,
+            hash: String::new(),
         }];
 
         let results = install(
@@ -285,6 +811,7 @@ mod tests {
             &FileScope::Project,
             dst_dir.path(),
             src_dir.path(),
+            &src_dir.path().join(".store"),
             false,
         )?;
 
@@ -309,6 +836,7 @@ mod tests {
             path: PathBuf::from("skills/review"),
             kind: FileKind::Skill,
             strategy: FileStrategy::Copy,
+            hash: String::new(),
         }];
 
         let results = install(
@@ -317,6 +845,7 @@ mod tests {
             &FileScope::Project,
             dst_dir.path(),
             src_dir.path(),
+            &src_dir.path().join(".store"),
             false,
         )?;
 
@@ -367,6 +896,7 @@ mod tests {
             path: PathBuf::from("commands/deploy.md"),
             kind: FileKind::Command,
             strategy: FileStrategy::Link,
+            hash: String::new(),
         }];
 
         let results = install(
@@ -375,6 +905,7 @@ mod tests {
             &FileScope::Project,
             dst_dir.path(),
             src_dir.path(),
+            &src_dir.path().join(".store"),
             false,
         )?;
 
@@ -403,6 +934,7 @@ mod tests {
             path: PathBuf::from("skills/review"),
             kind: FileKind::Skill,
             strategy: FileStrategy::Link,
+            hash: String::new(),
         }];
 
         let results = install(
@@ -411,6 +943,7 @@ mod tests {
             &FileScope::Project,
             dst_dir.path(),
             src_dir.path(),
+            &src_dir.path().join(".store"),
             false,
         )?;
 
@@ -433,6 +966,7 @@ mod tests {
             path: PathBuf::from("nonexistent"),
             kind: FileKind::Skill,
             strategy: FileStrategy::Copy,
+            hash: String::new(),
         }];
 
         let result = install(
@@ -441,6 +975,7 @@ mod tests {
             &FileScope::Project,
             dst_dir.path(),
             src_dir.path(),
+            &src_dir.path().join(".store"),
             false,
         );
 
@@ -460,6 +995,7 @@ mod tests {
             path: PathBuf::from("skills/review"),
             kind: FileKind::Skill,
             strategy: FileStrategy::Copy,
+            hash: String::new(),
         }];
 
         let results = install(
@@ -468,6 +1004,7 @@ mod tests {
             &FileScope::Project,
             dst_dir.path(),
             src_dir.path(),
+            &src_dir.path().join(".store"),
             true, // dry_run
         )?;
 
@@ -497,6 +1034,413 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn uncommitted_transaction_rolls_back() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+
+        fs::create_dir_all(src_dir.path().join("commands"))?;
+        fs::write(src_dir.path().join("commands/deploy.md"), "# New deploy")?;
+        fs::write(src_dir.path().join("commands/lint.md"), "# Lint")?;
+        let existing = dst_dir.path().join(".claude/commands/deploy.md");
+        fs::create_dir_all(existing.parent().unwrap())?;
+        fs::write(&existing, "# Old deploy")?;
+
+        let files: Vec<_> = ["commands/deploy.md", "commands/lint.md"]
+            .into_iter()
+            .map(|path| FileMapping {
+                path: PathBuf::from(path),
+                kind: FileKind::Command,
+                strategy: FileStrategy::Copy,
+                hash: String::new(),
+            })
+            .collect();
+        let planned = plan(
+            &files,
+            &[AgentProvider::ClaudeCode, AgentProvider::OpenCode],
+            &FileScope::Project,
+            dst_dir.path(),
+            src_dir.path(),
+            &Vars::default(),
+            &BTreeMap::new(),
+        )?;
+
+        let mut transaction = Transaction::new(false);
+        transaction.install(&planned, &src_dir.path().join(".store"))?;
+        assert_eq!(fs::read_to_string(&existing)?, "# New deploy");
+        assert!(dst_dir.path().join(".opencode/commands/lint.md").exists());
+        drop(transaction);
+
+        // Replaced files are restored, new ones and their directories removed
+        assert_eq!(fs::read_to_string(&existing)?, "# Old deploy");
+        assert!(!dst_dir.path().join(".claude/commands/lint.md").exists());
+        assert!(!dst_dir.path().join(".opencode").exists());
+        assert_eq!(fs::read_dir(existing.parent().unwrap())?.count(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn committed_transaction_leaves_no_staging() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+
+        let skill_dir = src_dir.path().join("skills/review");
+        fs::create_dir_all(&skill_dir)?;
+        fs::write(skill_dir.join("SKILL.md"), "# Review v2")?;
+        let target = dst_dir.path().join(".claude/skills/review");
+        fs::create_dir_all(&target)?;
+        fs::write(target.join("SKILL.md"), "# Review v1")?;
+        // Left behind by an install that was killed a day ago
+        let leftover = dst_dir.path().join(".claude/skills/.agentfiles-staging-1");
+        fs::create_dir_all(leftover.join("1"))?;
+        fs::write(leftover.join("1/SKILL.md"), "# Half-written")?;
+        let yesterday = std::time::SystemTime::now() - Duration::from_secs(24 * 60 * 60);
+        fs::File::open(&leftover)?.set_modified(yesterday)?;
+
+        let files = vec![FileMapping {
+            path: PathBuf::from("skills/review"),
+            kind: FileKind::Skill,
+            strategy: FileStrategy::Copy,
+            hash: String::new(),
+        }];
+        install(
+            &files,
+            &[AgentProvider::ClaudeCode],
+            &FileScope::Project,
+            dst_dir.path(),
+            src_dir.path(),
+            &src_dir.path().join(".store"),
+            false,
+        )?;
+
+        assert_eq!(fs::read_to_string(target.join("SKILL.md"))?, "# Review v2");
+        let entries: Vec<_> = fs::read_dir(dst_dir.path().join(".claude/skills"))?
+            .map(|e| e.map(|e| e.file_name()))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(entries, ["review"]);
+        let entries: Vec<_> = fs::read_dir(dst_dir.path())?
+            .map(|e| e.map(|e| e.file_name()))
+            .collect::<Result<_, _>>()?;
+        assert_eq!(entries, [".claude"]);
+        Ok(())
+    }
+
+    #[test]
+    fn stages_beside_the_provider_directory() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+        fs::create_dir_all(src_dir.path().join("commands"))?;
+        fs::write(src_dir.path().join("commands/deploy.md"), "# Deploy")?;
+
+        let files = vec![FileMapping {
+            path: PathBuf::from("commands/deploy.md"),
+            kind: FileKind::Command,
+            strategy: FileStrategy::Copy,
+            hash: String::new(),
+        }];
+        let planned = plan(
+            &files,
+            &[AgentProvider::ClaudeCode],
+            &FileScope::Project,
+            dst_dir.path(),
+            src_dir.path(),
+            &Vars::default(),
+            &BTreeMap::new(),
+        )?;
+        let mut transaction = Transaction::new(false);
+        transaction.install(&planned, &src_dir.path().join(".store"))?;
+
+        // Until the transaction ends, nothing but the target is in `.claude`
+        let staging = dst_dir
+            .path()
+            .canonicalize()?
+            .join(format!("{STAGING_PREFIX}{}", std::process::id()));
+        assert!(staging.is_dir());
+        assert_eq!(
+            fs::read_dir(dst_dir.path().join(".claude/commands"))?.count(),
+            1
+        );
+        transaction.commit();
+        assert!(!staging.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn reinstalled_skill_mirrors_source() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+
+        let skill_dir = src_dir.path().join("skills/review");
+        fs::create_dir_all(skill_dir.join("templates"))?;
+        fs::write(skill_dir.join("SKILL.md"), "# Review v2")?;
+        fs::write(skill_dir.join("helper.py"), "# renamed helper")?;
+        fs::write(skill_dir.join("templates/base.html"), "<html>")?;
+        let target = dst_dir.path().join(".claude/skills/review");
+        fs::create_dir_all(target.join("templates"))?;
+        fs::write(target.join("SKILL.md"), "# Review v1")?;
+        fs::write(target.join("helper.sh"), "# old helper")?;
+        fs::write(target.join("templates/base.html"), "<html>")?;
+        fs::write(target.join("templates/old.html"), "<old>")?;
+
+        let files = vec![FileMapping {
+            path: PathBuf::from("skills/review"),
+            kind: FileKind::Skill,
+            strategy: FileStrategy::Copy,
+            hash: String::new(),
+        }];
+        let results = install(
+            &files,
+            &[AgentProvider::ClaudeCode],
+            &FileScope::Project,
+            dst_dir.path(),
+            src_dir.path(),
+            &src_dir.path().join(".store"),
+            false,
+        )?;
+
+        assert_eq!(
+            results[0].changes,
+            [
+                FileChange::Modified("SKILL.md".into()),
+                FileChange::Added("helper.py".into()),
+                FileChange::Removed("helper.sh".into()),
+                FileChange::Removed("templates/old.html".into()),
+            ]
+        );
+        assert!(!target.join("helper.sh").exists());
+        assert!(!target.join("templates/old.html").exists());
+        assert!(target.join("helper.py").exists());
+        assert!(target.join("templates/base.html").exists());
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copy_replaces_previous_link() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+
+        let skill_dir = src_dir.path().join("skills/review");
+        fs::create_dir_all(&skill_dir)?;
+        fs::write(skill_dir.join("SKILL.md"), "# Review")?;
+        let mut files = vec![FileMapping {
+            path: PathBuf::from("skills/review"),
+            kind: FileKind::Skill,
+            strategy: FileStrategy::Link,
+            hash: String::new(),
+        }];
+        let store = src_dir.path().join(".store");
+        let install_with = |files: &[FileMapping]| {
+            install(
+                files,
+                &[AgentProvider::ClaudeCode],
+                &FileScope::Project,
+                dst_dir.path(),
+                src_dir.path(),
+                &store,
+                false,
+            )
+        };
+        install_with(&files)?;
+
+        files[0].strategy = FileStrategy::Copy;
+        let results = install_with(&files)?;
+
+        let target = dst_dir.path().join(".claude/skills/review");
+        assert!(results[0].replaced_link);
+        assert!(results[0].changes.is_empty());
+        assert!(!target.is_symlink());
+        assert_eq!(fs::read_to_string(target.join("SKILL.md"))?, "# Review");
+        // The snapshot the link pointed to is left intact
+        assert!(fs::read_dir(&store)?.next().is_some());
+        Ok(())
+    }
+
+    #[test]
+    fn rendered_files_are_copied() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+
+        let skill_dir = src_dir.path().join("skills/release");
+        fs::create_dir_all(&skill_dir)?;
+        fs::write(
+            skill_dir.join("SKILL.md"),
+            "Release {{ repo }} for {{ provider.id }}",
+        )?;
+        fs::write(skill_dir.join("notes.md"), "Plain notes")?;
+        let files = vec![FileMapping {
+            path: PathBuf::from("skills/release"),
+            kind: FileKind::Skill,
+            strategy: FileStrategy::Link,
+            hash: store::hash_tree(&skill_dir)?,
+        }];
+        let vars = Vars::new(
+            dst_dir.path(),
+            None,
+            &BTreeMap::from([("repo".to_string(), "acme/shop".to_string())]),
+            &[],
+        );
+        let planned = plan(
+            &files,
+            &[AgentProvider::ClaudeCode, AgentProvider::Codex],
+            &FileScope::Project,
+            dst_dir.path(),
+            src_dir.path(),
+            &vars,
+            &BTreeMap::new(),
+        )?;
+        let mut transaction = Transaction::new(false);
+        let results = transaction.install(&planned, &src_dir.path().join(".store"))?;
+        transaction.commit();
+
+        for (result, id) in results.iter().zip(["claude-code", "codex"]) {
+            let target = PathBuf::from(&result.target);
+            assert_eq!(result.strategy, FileStrategy::Copy);
+            assert!(!target.is_symlink());
+            assert_eq!(
+                fs::read_to_string(target.join("SKILL.md"))?,
+                format!("Release acme/shop for {id}")
+            );
+            assert_eq!(fs::read_to_string(target.join("notes.md"))?, "Plain notes");
+            // The recorded hash matches the rendered copy, not the source
+            assert_eq!(result.hash, store::hash_tree(&target)?);
+        }
+
+        fs::remove_file(skill_dir.join("notes.md"))?;
+        fs::write(skill_dir.join("SKILL.md"), "Release {{ branch }}")?;
+        let err = plan(
+            &files,
+            &[AgentProvider::ClaudeCode],
+            &FileScope::Project,
+            dst_dir.path(),
+            src_dir.path(),
+            &vars,
+            &BTreeMap::new(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "undefined variable 'branch' in skills/release/SKILL.md:1"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn renamed_items_install_under_new_name() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+
+        let skill_dir = src_dir.path().join("skills/review");
+        fs::create_dir_all(&skill_dir)?;
+        fs::write(
+            skill_dir.join("SKILL.md"),
+            "---\nname: review\ndescription: Review code\n---\n# Review\n",
+        )?;
+        fs::create_dir_all(src_dir.path().join("commands"))?;
+        fs::write(src_dir.path().join("commands/deploy.md"), "# Deploy\n")?;
+        let files = vec![
+            FileMapping {
+                path: PathBuf::from("skills/review"),
+                kind: FileKind::Skill,
+                strategy: FileStrategy::Link,
+                hash: String::new(),
+            },
+            FileMapping {
+                path: PathBuf::from("commands/deploy.md"),
+                kind: FileKind::Command,
+                strategy: FileStrategy::Copy,
+                hash: String::new(),
+            },
+        ];
+        let renames = BTreeMap::from([
+            ("skills/review".to_string(), "acme-review".to_string()),
+            ("commands/deploy".to_string(), "ship".to_string()),
+        ]);
+        check_renames(&files, &renames, "src")?;
+
+        let planned = plan(
+            &files,
+            &[AgentProvider::ClaudeCode],
+            &FileScope::Project,
+            dst_dir.path(),
+            src_dir.path(),
+            &Vars::default(),
+            &renames,
+        )?;
+        let mut transaction = Transaction::new(false);
+        let results = transaction.install(&planned, &src_dir.path().join(".store"))?;
+        transaction.commit();
+
+        let skill = dst_dir.path().join(".claude/skills/acme-review");
+        assert_eq!(results[0].target, skill.display().to_string());
+        assert_eq!(results[0].strategy, FileStrategy::Copy);
+        assert_eq!(
+            fs::read_to_string(skill.join("SKILL.md"))?,
+            "---\nname: acme-review\ndescription: Review code\n---\n# Review\n"
+        );
+        // Without a `name` in its frontmatter, a command keeps its content
+        assert_eq!(
+            fs::read_to_string(dst_dir.path().join(".claude/commands/ship.md"))?,
+            "# Deploy\n"
+        );
+        assert!(!dst_dir.path().join(".claude/skills/review").exists());
+
+        // Names only have to be unique within a kind
+        let other_kind = BTreeMap::from([("skills/review".to_string(), "deploy".to_string())]);
+        assert!(check_renames(&files, &other_kind, "src").is_ok());
+        let missing = BTreeMap::from([("skills/revew".to_string(), "x".to_string())]);
+        assert_eq!(
+            check_renames(&files, &missing, "src")
+                .unwrap_err()
+                .to_string(),
+            "rename for 'skills/revew' matches no item of src"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn nested_commands_are_renamed_by_their_pick_key() -> Result<()> {
+        let src_dir = TempDir::new()?;
+        let dst_dir = TempDir::new()?;
+        fs::create_dir_all(src_dir.path().join("commands/ops"))?;
+        fs::write(src_dir.path().join("commands/ops/deploy.md"), "# Deploy\n")?;
+        let files = vec![FileMapping {
+            path: PathBuf::from("commands/ops/deploy.md"),
+            kind: FileKind::Command,
+            strategy: FileStrategy::Copy,
+            hash: String::new(),
+        }];
+
+        // Keyed by the path inside `commands/`, as `pick` matches it
+        let renames = BTreeMap::from([("commands/ops/deploy".to_string(), "ship".to_string())]);
+        check_renames(&files, &renames, "src")?;
+        let planned = plan(
+            &files,
+            &[AgentProvider::ClaudeCode],
+            &FileScope::Project,
+            dst_dir.path(),
+            src_dir.path(),
+            &Vars::default(),
+            &renames,
+        )?;
+        let mut transaction = Transaction::new(false);
+        transaction.install(&planned, &src_dir.path().join(".store"))?;
+        transaction.commit();
+        assert_eq!(
+            fs::read_to_string(dst_dir.path().join(".claude/commands/ship.md"))?,
+            "# Deploy\n"
+        );
+
+        let by_stem = BTreeMap::from([("commands/deploy".to_string(), "ship".to_string())]);
+        assert_eq!(
+            check_renames(&files, &by_stem, "src")
+                .unwrap_err()
+                .to_string(),
+            "rename for 'commands/deploy' matches no item of src"
+        );
+        Ok(())
+    }
+
     #[test]
     fn install_empty_files_list() -> Result<()> {
         let src_dir = TempDir::new()?;
@@ -508,6 +1452,7 @@ mod tests {
             &FileScope::Project,
             dst_dir.path(),
             src_dir.path(),
+            &src_dir.path().join(".store"),
             false,
         )?;
 
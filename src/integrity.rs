@@ -0,0 +1,173 @@
+use anyhow::{Context, Result, bail};
+use sha2::{Digest, Sha256};
+
+use crate::manifest::{FileMapping, Integrity};
+use crate::scanner;
+
+/// Algorithm prefix of integrity values.
+const PREFIX: &str = "sha256:";
+
+/// Parse an integrity value of the form `sha256:<hex digest>`, returning
+/// the lowercase digest.
+pub(crate) fn parse(value: &str) -> Result<String> {
+    let digest = value
+        .strip_prefix(PREFIX)
+        .filter(|d| d.len() == 64 && d.bytes().all(|b| b.is_ascii_hexdigit()))
+        .with_context(|| {
+            format!("invalid integrity '{value}': expected '{PREFIX}' followed by 64 hex digits")
+        })?;
+    Ok(digest.to_ascii_lowercase())
+}
+
+/// Format a hex digest as an integrity value.
+pub(crate) fn format(digest: &str) -> String {
+    format!("{PREFIX}{digest}")
+}
+
+/// Combined hash of the items a dependency installs.
+///
+/// Covers each item's key (see `scanner::item_key`) and content hash, in
+/// key order, so it changes when an item is added, removed, renamed or
+/// edited, but not when the source is merely reorganized.
+pub(crate) fn dependency_hash(files: &[FileMapping]) -> String {
+    let mut items: Vec<_> = files
+        .iter()
+        .map(|f| (scanner::item_key(f), f.hash.as_str()))
+        .collect();
+    items.sort();
+
+    let mut hasher = Sha256::new();
+    for (key, hash) in items {
+        hasher.update(key.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(hash.as_bytes());
+        hasher.update(b"\n");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Check the items `source` is about to install against `expected`.
+///
+/// For archive sources a dependency-wide value is the archive checksum,
+/// which is verified when the archive is downloaded, not here.
+pub(crate) fn verify(
+    source: &str,
+    expected: &Integrity,
+    files: &[FileMapping],
+    is_archive: bool,
+) -> Result<()> {
+    match expected {
+        Integrity::Dependency(_) if is_archive => Ok(()),
+        Integrity::Dependency(value) => {
+            let expected = parse(value)?;
+            let actual = dependency_hash(files);
+            if actual != expected {
+                bail!(
+                    "integrity mismatch for {source}:\n  expected {}\n  got      {}",
+                    format(&expected),
+                    format(&actual)
+                );
+            }
+            Ok(())
+        }
+        Integrity::Items(items) => {
+            let mut mismatches = Vec::new();
+            for (key, value) in items {
+                let expected = parse(value)?;
+                let file = files
+                    .iter()
+                    .find(|f| scanner::item_key(f) == *key)
+                    .with_context(|| {
+                        format!("integrity is set for '{key}', but {source} does not install it")
+                    })?;
+                if file.hash != expected {
+                    mismatches.push(format!(
+                        "  {key}: expected {}, got {}",
+                        format(&expected),
+                        format(&file.hash)
+                    ));
+                }
+            }
+            if !mismatches.is_empty() {
+                bail!(
+                    "integrity mismatch for {source}:\n{}",
+                    mismatches.join("\n")
+                );
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{FileKind, FileStrategy};
+    use std::collections::BTreeMap;
+
+    fn item(path: &str, kind: FileKind, hash: &str) -> FileMapping {
+        FileMapping {
+            path: path.into(),
+            kind,
+            strategy: FileStrategy::Copy,
+            hash: hash.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_values() {
+        let digest = "AB".repeat(32);
+        assert_eq!(parse(&format(&digest)).unwrap(), "ab".repeat(32));
+        assert!(parse(&digest).is_err());
+        assert!(parse("sha256:abc").is_err());
+    }
+
+    #[test]
+    fn dependency_hash_ignores_layout_but_not_content() {
+        let a = vec![
+            item("skills/review", FileKind::Skill, "1"),
+            item("commands/deploy.md", FileKind::Command, "2"),
+        ];
+        let moved = vec![
+            item(".claude/commands/deploy.md", FileKind::Command, "2"),
+            item(".claude/skills/review", FileKind::Skill, "1"),
+        ];
+        let edited = vec![
+            item("skills/review", FileKind::Skill, "1"),
+            item("commands/deploy.md", FileKind::Command, "3"),
+        ];
+        assert_eq!(dependency_hash(&a), dependency_hash(&moved));
+        assert_ne!(dependency_hash(&a), dependency_hash(&edited));
+    }
+
+    #[test]
+    fn verifies_dependency_hash() {
+        let files = vec![item("skills/review", FileKind::Skill, "1")];
+        let good = Integrity::Dependency(format(&dependency_hash(&files)));
+        assert!(verify("src", &good, &files, false).is_ok());
+
+        let bad = Integrity::Dependency(format(&"0".repeat(64)));
+        let err = verify("src", &bad, &files, false).unwrap_err();
+        assert!(err.to_string().contains("integrity mismatch for src"));
+        // Archive checksums are checked at download time
+        assert!(verify("src", &bad, &files, true).is_ok());
+    }
+
+    #[test]
+    fn verifies_item_hashes() {
+        let review = "a".repeat(64);
+        let files = vec![
+            item("skills/review", FileKind::Skill, &review),
+            item("commands/deploy.md", FileKind::Command, &"b".repeat(64)),
+        ];
+        let pinned = |key: &str, hash: &str| {
+            Integrity::Items(BTreeMap::from([(key.to_string(), format(hash))]))
+        };
+
+        assert!(verify("src", &pinned("skills/review", &review), &files, false).is_ok());
+        let err = verify("src", &pinned("commands/deploy", &review), &files, false).unwrap_err();
+        assert!(err.to_string().contains("commands/deploy: expected"));
+        let err = verify("src", &pinned("agents/missing", &review), &files, false).unwrap_err();
+        assert!(err.to_string().contains("does not install it"));
+    }
+}
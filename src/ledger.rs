@@ -0,0 +1,246 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::store;
+use crate::types::{AgentProvider, FileKind, FileStrategy};
+
+/// Record of the files agentfiles installed under one root directory.
+///
+/// Ledgers live in `<data_dir>/ledgers/`, one JSON file per root (a project
+/// directory, or the home directory for global installs), so that the store
+/// can tell which snapshots are still in use across every project.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub(crate) struct Ledger {
+    /// Directory the installs belong to.
+    pub root: PathBuf,
+
+    /// Installed targets, one entry per provider target path.
+    #[serde(default)]
+    pub entries: Vec<LedgerEntry>,
+}
+
+/// A single installed target.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub(crate) struct LedgerEntry {
+    /// Absolute path of the installed file or directory.
+    pub target: PathBuf,
+
+    /// Dependency source the target was installed from.
+    pub source: String,
+
+    /// Path of the item inside the source (e.g. `skills/review`).
+    pub item: String,
+
+    pub provider: AgentProvider,
+    pub kind: FileKind,
+    pub strategy: FileStrategy,
+
+    /// Store snapshot hash the target links to, for link installs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<String>,
+
+    /// Content hash of the item as installed (see `store::hash_tree`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+
+    /// Commit the source resolved to, for git sources, so `cache prune`
+    /// keeps its checkout.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+}
+
+/// How an installed target differs from what was installed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Drift {
+    /// The content was edited or replaced.
+    Modified,
+    /// The target no longer exists.
+    Missing,
+}
+
+impl LedgerEntry {
+    /// Compare the target on disk with the hash recorded at install time.
+    ///
+    /// Entries recorded without a hash are never reported.
+    pub fn drift(&self) -> Option<Drift> {
+        let expected = self.hash.as_ref()?;
+        if !self.target.exists() {
+            return Some(Drift::Missing);
+        }
+        match store::hash_tree(&self.target) {
+            Ok(actual) if actual == *expected => None,
+            _ => Some(Drift::Modified),
+        }
+    }
+}
+
+impl Ledger {
+    /// Load the ledger for `root`, or an empty one if nothing was recorded yet.
+    pub fn load(data_dir: &Path, root: &Path) -> Result<Self> {
+        let path = ledger_path(data_dir, root);
+        if !path.is_file() {
+            return Ok(Ledger {
+                root: root.to_path_buf(),
+                entries: Vec::new(),
+            });
+        }
+        read_ledger(&path)
+    }
+
+    /// Load every ledger in `data_dir`.
+    pub fn load_all(data_dir: &Path) -> Result<Vec<Self>> {
+        let dir = data_dir.join(LEDGERS_DIR);
+        if !dir.is_dir() {
+            return Ok(Vec::new());
+        }
+        let mut ledgers = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "json") {
+                ledgers.push(read_ledger(&path)?);
+            }
+        }
+        Ok(ledgers)
+    }
+
+    /// Add `entries`, replacing any existing entry for the same target.
+    pub fn record(&mut self, entries: Vec<LedgerEntry>) {
+        self.forget(&entries.iter().map(|e| e.target.clone()).collect::<Vec<_>>());
+        self.entries.extend(entries);
+        self.entries.sort_by(|a, b| a.target.cmp(&b.target));
+    }
+
+    /// Whether any target was recorded as installed from `source`.
+    pub fn has_source(&self, source: &str) -> bool {
+        self.entries.iter().any(|e| e.source == source)
+    }
+
+    /// Drop the entries for `targets`.
+    pub fn forget(&mut self, targets: &[PathBuf]) {
+        self.entries.retain(|e| !targets.contains(&e.target));
+    }
+
+    /// Write the ledger back, removing its file once it has no entries.
+    pub fn save(&self, data_dir: &Path) -> Result<()> {
+        let path = ledger_path(data_dir, &self.root);
+        if self.entries.is_empty() {
+            if path.is_file() {
+                debug!("Removing empty ledger {}", path.display());
+                fs::remove_file(&path)
+                    .with_context(|| format!("failed to remove {}", path.display()))?;
+            }
+            return Ok(());
+        }
+
+        debug!(
+            "Saving ledger for {} ({} entries) to {}",
+            self.root.display(),
+            self.entries.len(),
+            path.display()
+        );
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("failed to create directory: {}", parent.display()))?;
+        }
+        let json = serde_json::to_string_pretty(self).context("failed to serialize ledger")?;
+        fs::write(&path, json + "\n").with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+/// Subdirectory of the data directory holding ledgers.
+const LEDGERS_DIR: &str = "ledgers";
+
+/// Ledger file for `root`, named by a hash of the root path.
+fn ledger_path(data_dir: &Path, root: &Path) -> PathBuf {
+    let digest = Sha256::digest(root.to_string_lossy().as_bytes());
+    let name: String = digest[..8].iter().map(|b| format!("{b:02x}")).collect();
+    data_dir.join(LEDGERS_DIR).join(format!("{name}.json"))
+}
+
+fn read_ledger(path: &Path) -> Result<Ledger> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    serde_json::from_str(&content).with_context(|| format!("failed to parse {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn entry(target: &str, snapshot: Option<&str>) -> LedgerEntry {
+        LedgerEntry {
+            target: PathBuf::from(target),
+            source: "github.com/org/repo".to_string(),
+            item: "skills/review".to_string(),
+            provider: AgentProvider::ClaudeCode,
+            kind: FileKind::Skill,
+            strategy: FileStrategy::Link,
+            snapshot: snapshot.map(str::to_string),
+            hash: None,
+            commit: None,
+        }
+    }
+
+    #[test]
+    fn record_replaces_same_target() {
+        let mut ledger = Ledger::default();
+        ledger.record(vec![entry("/p/.claude/skills/review", Some("aaa"))]);
+        ledger.record(vec![entry("/p/.claude/skills/review", Some("bbb"))]);
+
+        assert_eq!(ledger.entries.len(), 1);
+        assert_eq!(ledger.entries[0].snapshot.as_deref(), Some("bbb"));
+    }
+
+    #[test]
+    fn roundtrip_per_root() -> Result<()> {
+        let data = TempDir::new()?;
+        let mut a = Ledger::load(data.path(), Path::new("/project-a"))?;
+        a.record(vec![entry("/project-a/.claude/skills/review", Some("aaa"))]);
+        a.save(data.path())?;
+        let mut b = Ledger::load(data.path(), Path::new("/project-b"))?;
+        b.record(vec![entry("/project-b/.claude/skills/review", None)]);
+        b.save(data.path())?;
+
+        assert_eq!(Ledger::load(data.path(), Path::new("/project-a"))?, a);
+        assert_eq!(Ledger::load_all(data.path())?.len(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn detects_drift_from_recorded_hash() -> Result<()> {
+        let dir = TempDir::new()?;
+        let target = dir.path().join("deploy.md");
+        fs::write(&target, "# Deploy")?;
+        let mut entry = entry(&target.to_string_lossy(), None);
+        assert_eq!(entry.drift(), None);
+
+        entry.hash = Some(store::hash_tree(&target)?);
+        assert_eq!(entry.drift(), None);
+        fs::write(&target, "# Deploy, edited")?;
+        assert_eq!(entry.drift(), Some(Drift::Modified));
+        fs::remove_file(&target)?;
+        assert_eq!(entry.drift(), Some(Drift::Missing));
+        Ok(())
+    }
+
+    #[test]
+    fn empty_ledger_file_is_removed() -> Result<()> {
+        let data = TempDir::new()?;
+        let root = Path::new("/project");
+        let mut ledger = Ledger::load(data.path(), root)?;
+        ledger.record(vec![entry("/project/x", None)]);
+        ledger.save(data.path())?;
+        assert!(ledger_path(data.path(), root).is_file());
+
+        ledger.forget(&[PathBuf::from("/project/x")]);
+        ledger.save(data.path())?;
+        assert!(!ledger_path(data.path(), root).exists());
+        Ok(())
+    }
+}
@@ -1,8 +1,20 @@
+pub mod archive;
+pub mod audit;
+pub(crate) mod cache;
 pub mod cli;
 pub mod commands;
+pub(crate) mod condition;
+pub(crate) mod credentials;
 pub mod git;
+pub(crate) mod ignore;
 pub mod installer;
+pub(crate) mod integrity;
+pub(crate) mod ledger;
 pub mod manifest;
+pub(crate) mod overlay;
 pub mod provider;
 pub mod scanner;
+pub mod store;
+pub(crate) mod template;
+pub(crate) mod trust;
 pub mod types;
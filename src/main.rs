@@ -1,4 +1,4 @@
-use agentfiles::{cli, commands};
+use agentfiles::{cli, commands, git, manifest, store, types};
 use anyhow::Result;
 use clap::Parser;
 
@@ -11,6 +11,13 @@ fn main() -> Result<()> {
 
     env_logger::init();
 
+    // Before any source is parsed, so URLs and cache keys agree everywhere
+    if parses_sources(&args.command)
+        && let Some(remotes) = manifest::load_user_manifest()?.and_then(|m| m.remotes)
+    {
+        git::configure_remotes(remotes);
+    }
+
     match args.command {
         cli::Command::Install {
             source,
@@ -18,8 +25,14 @@ fn main() -> Result<()> {
             providers,
             strategy,
             pick,
+            exclude,
+            alias,
             no_save,
             dry_run,
+            force,
+            backup,
+            keep_local,
+            jobs,
             root,
         } => commands::cmd_install(commands::InstallOptions {
             source,
@@ -27,20 +40,95 @@ fn main() -> Result<()> {
             providers,
             strategy,
             pick,
+            exclude,
+            alias,
             no_save,
             dry_run,
+            offline: args.offline,
+            jobs,
+            on_conflict: on_conflict(force, backup, keep_local),
             root,
+            data_dir: store::data_dir()?,
+            config_dir: manifest::user_manifest_dir()?,
         }),
         cli::Command::Init { path, name } => commands::cmd_init(path, name),
-        cli::Command::Scan { source } => commands::cmd_scan(source),
+        cli::Command::Scan { source } => commands::cmd_scan(source, args.offline),
+        cli::Command::Audit {
+            source,
+            scope,
+            root,
+        } => commands::cmd_audit(source, scope, root, args.offline),
         cli::Command::Remove {
             source,
             clean,
             scope,
             providers,
+            force,
+            backup,
+            keep_local,
+            root,
+        } => commands::cmd_remove(commands::RemoveOptions {
+            source,
+            clean,
+            scope,
+            providers,
+            on_conflict: on_conflict(force, backup, keep_local),
+            offline: args.offline,
             root,
-        } => commands::cmd_remove(source, clean, scope, providers, root),
-        cli::Command::List { root } => commands::cmd_list(root),
+            data_dir: store::data_dir()?,
+        }),
+        cli::Command::List { root, scope } => commands::cmd_list(root, scope, store::data_dir()?),
         cli::Command::Matrix => commands::cmd_matrix(),
+        cli::Command::Cache { command } => match command {
+            cli::CacheCommand::List => commands::cmd_cache_list(git::cache_root()?),
+            cli::CacheCommand::Prune {
+                older_than,
+                dry_run,
+            } => commands::cmd_cache_prune(
+                git::cache_root()?,
+                store::data_dir()?,
+                older_than,
+                dry_run,
+            ),
+            cli::CacheCommand::Clear { source } => {
+                commands::cmd_cache_clear(git::cache_root()?, source)
+            }
+            cli::CacheCommand::Path { source } => {
+                commands::cmd_cache_path(git::cache_root()?, source)
+            }
+        },
+        cli::Command::Store { command } => match command {
+            cli::StoreCommand::Gc { dry_run } => {
+                commands::cmd_store_gc(store::data_dir()?, dry_run)
+            }
+            cli::StoreCommand::Path => commands::cmd_store_path(store::data_dir()?),
+        },
+    }
+}
+
+/// Whether `command` parses sources, and so needs the user manifest's
+/// `remotes`. The others keep working with a broken user manifest.
+fn parses_sources(command: &cli::Command) -> bool {
+    !matches!(
+        command,
+        cli::Command::Init { .. }
+            | cli::Command::Matrix
+            | cli::Command::Store { .. }
+            | cli::Command::Cache {
+                command: cli::CacheCommand::List
+                    | cli::CacheCommand::Path { source: None }
+                    | cli::CacheCommand::Clear { source: None },
+            }
+    )
+}
+
+/// What to do with edited or unknown files, from the `--force`, `--backup`
+/// and `--keep-local` flags.
+fn on_conflict(force: bool, backup: bool, keep_local: bool) -> types::OnConflict {
+    match (force, backup, keep_local) {
+        (true, _, _) => types::OnConflict::Force,
+        (_, true, _) => types::OnConflict::Backup,
+        (_, _, true) => types::OnConflict::Keep,
+        _ => types::OnConflict::Refuse,
     }
 }
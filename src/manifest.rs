@@ -1,11 +1,13 @@
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result, bail};
 use log::debug;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 
+use crate::audit::{Action, Check};
 use crate::git;
-use crate::types::{FileKind, FileStrategy};
+use crate::types::{FileKind, FileScope, FileStrategy};
 
 /// A single discovered agent file used by the scanner and installer.
 ///
@@ -21,6 +23,10 @@ pub(crate) struct FileMapping {
 
     /// How to place the file at the target. Defaults to Copy.
     pub strategy: FileStrategy,
+
+    /// Content hash of the file or skill directory (see `store::hash_tree`),
+    /// computed at scan time.
+    pub hash: String,
 }
 
 /// A dependency source -- either a simple URL/path string or a detailed spec.
@@ -29,6 +35,7 @@ pub(crate) struct FileMapping {
 /// Detailed form: `{ "source": "...", "ref": "v1.0", "pick": [...], ... }`
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 #[serde(untagged)]
+#[allow(clippy::large_enum_variant)]
 pub enum Dependency {
     Simple(String),
     Detailed(DependencySpec),
@@ -62,6 +69,13 @@ impl Dependency {
         self.spec().and_then(|d| d.pick.as_deref())
     }
 
+    /// Items to leave out, if any.
+    pub fn exclude(&self) -> &[String] {
+        self.spec()
+            .and_then(|d| d.exclude.as_deref())
+            .unwrap_or_default()
+    }
+
     /// Per-dependency strategy override, if any.
     pub fn strategy(&self) -> Option<FileStrategy> {
         self.spec().and_then(|d| d.strategy)
@@ -72,6 +86,57 @@ impl Dependency {
     pub fn paths(&self) -> Option<&[PathMapping]> {
         self.spec().and_then(|d| d.paths.as_deref())
     }
+
+    /// Explicit subdirectory within the source, if any. Note that `//subdir`
+    /// in source strings is handled by git::parse_remote, not here.
+    pub fn subdir(&self) -> Option<&str> {
+        self.spec().and_then(|d| d.subdir.as_deref())
+    }
+
+    /// Expected content hashes, if any.
+    pub fn integrity(&self) -> Option<&Integrity> {
+        self.spec().and_then(|d| d.integrity.as_ref())
+    }
+
+    /// Per-dependency signature verification override, if any.
+    pub fn verify(&self) -> Option<bool> {
+        self.spec().and_then(|d| d.verify)
+    }
+
+    /// Per-dependency audit policy, layered over the manifest's.
+    pub fn policy(&self) -> Option<&Policy> {
+        self.spec().and_then(|d| d.policy.as_ref())
+    }
+
+    /// Local patches to apply to this dependency's items.
+    pub fn patches(&self) -> &[Patch] {
+        self.spec()
+            .and_then(|d| d.patches.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Template variables set for this dependency.
+    pub fn vars(&self) -> Option<&BTreeMap<String, String>> {
+        self.spec().and_then(|d| d.vars.as_ref())
+    }
+
+    /// Install conditions for this dependency's items, by item.
+    pub fn when(&self) -> Option<&BTreeMap<String, Condition>> {
+        self.spec().and_then(|d| d.when.as_ref())
+    }
+
+    /// New names for this dependency's items, by item.
+    pub fn rename(&self) -> Option<&BTreeMap<String, String>> {
+        self.spec().and_then(|d| d.rename.as_ref())
+    }
+
+    /// Normalized identity of this dependency, used for deduplication.
+    ///
+    /// Two dependencies on different subdirectories of the same repository
+    /// are distinct, even though they share one cached clone.
+    pub fn key(&self) -> String {
+        git::source_key(self.source(), self.subdir())
+    }
 }
 
 /// Detailed dependency specification with optional configuration.
@@ -85,10 +150,16 @@ pub struct DependencySpec {
     pub git_ref: Option<String>,
 
     /// Cherry-pick specific items by name. Supports kind prefix:
-    /// `"skills/review"`, `"commands/deploy"`, or plain `"review"`.
+    /// `"skills/review"`, `"commands/deploy"`, or plain `"review"`, and
+    /// globs: `"skills/review-*"`, `"commands/**/deploy"`.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub pick: Option<Vec<String>>,
 
+    /// Items to leave out, written like `pick` entries. Applied after
+    /// `pick`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exclude: Option<Vec<String>>,
+
     /// Override the installation strategy for all files from this dependency.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub strategy: Option<FileStrategy>,
@@ -97,6 +168,203 @@ pub struct DependencySpec {
     /// when specified.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub paths: Option<Vec<PathMapping>>,
+
+    /// Subdirectory within the source to treat as its root (e.g. for a
+    /// monorepo). Scanning and `paths` are relative to it. Takes precedence
+    /// over a `//subdir` suffix in `source`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subdir: Option<String>,
+
+    /// Expected content hashes, for the whole dependency or per item.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub integrity: Option<Integrity>,
+
+    /// Require the checked-out tag or commit to be signed by a trusted key.
+    /// Overrides the manifest-wide `verify` setting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify: Option<bool>,
+
+    /// Audit policy for this dependency's content, taking precedence over
+    /// the manifest-wide `policy`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<Policy>,
+
+    /// Local changes applied to items before they are installed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub patches: Option<Vec<Patch>>,
+
+    /// Template variables for this dependency's files, overriding the
+    /// manifest-wide `vars`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vars: Option<BTreeMap<String, String>>,
+
+    /// When items are installed, keyed like kind-prefixed `pick` entries
+    /// (`"skills/review"`). Replaces a `when` in the item's frontmatter.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<BTreeMap<String, Condition>>,
+
+    /// New names to install items under, keyed like kind-prefixed `pick`
+    /// entries: `{ "skills/review": "acme-review" }`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rename: Option<BTreeMap<String, String>>,
+}
+
+/// When an item is installed. Every field that is set has to match; each
+/// accepts a single value or a list.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    /// Providers to install to (`claude-code`, `opencode`, ...); others are
+    /// skipped.
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub providers: Vec<String>,
+
+    /// Paths relative to the project root that must all exist.
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub files_exist: Vec<String>,
+
+    /// Operating systems to install on, as named by Rust (`linux`, `macos`,
+    /// `windows`).
+    #[serde(
+        default,
+        deserialize_with = "one_or_many",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub os: Vec<String>,
+}
+
+/// Deserialize a string or a list of strings into a list.
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+/// A local change to one item of a dependency, applied on top of the
+/// upstream content at install time.
+///
+/// File paths are relative to the manifest's directory. Changes are applied
+/// in field order: `diff`, then `frontmatter`, then `append`, with every
+/// diff for an item applied before any other change.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Patch {
+    /// Item to change, keyed like kind-prefixed `pick` entries
+    /// (`"skills/review"`).
+    pub item: String,
+
+    /// Unified diff (as written by `git diff`) with paths relative to the
+    /// item: `a/SKILL.md` for a skill, `a/deploy.md` for a command.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+
+    /// Fields to set in the frontmatter of the item's markdown file.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub frontmatter: BTreeMap<String, serde_json::Value>,
+
+    /// Markdown file appended to the item's markdown file.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub append: Option<String>,
+}
+
+/// Expected hashes of a dependency's content, each written as
+/// `sha256:<hex digest>`.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum Integrity {
+    /// One hash for the whole dependency: the archive checksum for archive
+    /// sources (verified before extraction), otherwise the combined hash of
+    /// every item it installs.
+    Dependency(String),
+
+    /// A hash per item, keyed like kind-prefixed `pick` entries
+    /// (`"skills/review"`).
+    Items(BTreeMap<String, String>),
+}
+
+/// Keys trusted to sign verified dependencies.
+///
+/// Paths are relative to the manifest's directory; `~/` expands to the home
+/// directory.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Signing {
+    /// SSH allowed-signers file, in the format of `ssh-keygen -Y verify`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed_signers: Option<String>,
+
+    /// GnuPG home directory whose keyring holds the trusted public keys.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpg_home: Option<String>,
+}
+
+impl Signing {
+    /// Signature policy with paths resolved against `base`, the directory of
+    /// the manifest this configuration was read from.
+    pub fn policy(&self, base: &Path) -> Result<git::SignaturePolicy> {
+        let resolve = |path: &str| -> Result<PathBuf> {
+            Ok(match path.strip_prefix("~/") {
+                Some(rest) => dirs::home_dir()
+                    .context("could not determine home directory")?
+                    .join(rest),
+                None => base.join(path),
+            })
+        };
+        Ok(git::SignaturePolicy {
+            allowed_signers: self.allowed_signers.as_deref().map(resolve).transpose()?,
+            gpg_home: self.gpg_home.as_deref().map(resolve).transpose()?,
+        })
+    }
+}
+
+/// What to do with audit findings (see `audit::audit`).
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Policy {
+    /// Action per check; checks not listed are warnings.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub checks: BTreeMap<Check, Action>,
+
+    /// Paths, relative to the source root, whose findings are accepted. A
+    /// directory covers everything inside it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allow: Vec<String>,
+}
+
+/// Which sources dependencies may come from (see `trust::SourceRules`).
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct SourcePolicy {
+    /// Hosts whose repositories and archives are trusted, e.g. `github.com`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_hosts: Vec<String>,
+
+    /// Organizations trusted on a host, written `host/org`. Together with
+    /// `allowed_hosts`, forms the allowlist; when both are empty, any host
+    /// is allowed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_orgs: Vec<String>,
+
+    /// URL schemes to refuse: `https`, `http`, `git`, `ssh` (including
+    /// `git@host:` URLs) or `file` (including local paths).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub denied_schemes: Vec<String>,
+
+    /// Require git sources to be pinned to a commit hash and archives to an
+    /// `integrity` checksum.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub require_pinned: bool,
 }
 
 /// Maps a custom path in a source repository to a file kind.
@@ -135,6 +403,44 @@ pub struct Manifest {
 
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub dependencies: Vec<Dependency>,
+
+    /// Maintain an agentfiles block in the project's `.gitignore` listing
+    /// every installed target, so installed files are treated as generated
+    /// output rather than committed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub gitignore: bool,
+
+    /// Require every dependency's tag or commit to be signed by a trusted
+    /// key, unless the dependency sets `verify` itself.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub verify: bool,
+
+    /// Keys trusted for signature verification. When unset, the user
+    /// manifest's `signing` applies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signing: Option<Signing>,
+
+    /// Audit policy applied to every dependency before it is installed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<Policy>,
+
+    /// Restrictions on where dependencies may come from. The user
+    /// manifest's `sources` applies to every manifest as well.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sources: Option<SourcePolicy>,
+
+    /// Extra shorthand hosts and URL rewrites. Only read from the user
+    /// manifest, where it applies to every source (see `git::RemoteConfig`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remotes: Option<git::RemoteConfig>,
+
+    /// Values for `{{ var }}` placeholders in installed files.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub vars: BTreeMap<String, String>,
+
+    /// Environment variables that `{{ env.NAME }}` placeholders may read.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<String>,
 }
 
 fn default_version() -> String {
@@ -150,6 +456,14 @@ impl Default for Manifest {
             author: None,
             repository: None,
             dependencies: vec![],
+            gitignore: false,
+            verify: false,
+            signing: None,
+            policy: None,
+            sources: None,
+            remotes: None,
+            vars: BTreeMap::new(),
+            env: vec![],
         }
     }
 }
@@ -188,25 +502,56 @@ impl Manifest {
     /// Add a dependency if one with the same source doesn't already exist.
     /// Returns true if the dependency was added, false if it was already present.
     pub fn add_dependency(&mut self, dep: Dependency) -> bool {
-        let source = dep.source().to_string();
-        if self.has_dependency(&source) {
-            debug!("Dependency already exists: {}", source);
+        let key = dep.key();
+        if self.dependencies.iter().any(|d| d.key() == key) {
+            debug!("Dependency already exists: {}", key);
             return false;
         }
-        debug!("Adding dependency: {}", source);
+        debug!("Adding dependency: {}", key);
         self.dependencies.push(dep);
         true
     }
 
+    /// Find the dependency matching `source`, using the same normalized
+    /// comparison as `has_dependency`.
+    pub fn find_dependency(&self, source: &str) -> Option<&Dependency> {
+        let normalized = git::normalize_source(source);
+        self.dependencies.iter().find(|d| d.key() == normalized)
+    }
+
     /// Check whether a dependency with the given source already exists.
     ///
     /// Compares using normalized URLs so that `github.com/org/repo` and
     /// `https://github.com/org/repo.git` are treated as the same source.
     pub fn has_dependency(&self, source: &str) -> bool {
         let normalized = git::normalize_source(source);
-        self.dependencies
+        self.dependencies.iter().any(|d| d.key() == normalized)
+    }
+
+    /// Layer a personal (local) manifest over this one.
+    ///
+    /// Returns every dependency paired with the scope to install it in. A
+    /// local dependency replaces a project dependency with the same
+    /// normalized source, in place; the remaining local dependencies follow
+    /// in their own order. Local entries always use `FileScope::Local`.
+    pub fn layer_local(&self, local: &Manifest) -> Vec<(Dependency, FileScope)> {
+        let mut layered: Vec<(Dependency, FileScope)> = self
+            .dependencies
             .iter()
-            .any(|d| git::normalize_source(d.source()) == normalized)
+            .map(|d| (d.clone(), FileScope::Project))
+            .collect();
+
+        for dep in &local.dependencies {
+            let key = dep.key();
+            match layered.iter_mut().find(|(d, _)| d.key() == key) {
+                Some(entry) => {
+                    debug!("Local manifest overrides: {}", dep.source());
+                    *entry = (dep.clone(), FileScope::Local);
+                }
+                None => layered.push((dep.clone(), FileScope::Local)),
+            }
+        }
+        layered
     }
 
     /// Remove a dependency by source. Returns true if a dependency was removed.
@@ -216,24 +561,91 @@ impl Manifest {
         debug!("Removing dependency: {}", source);
         let normalized = git::normalize_source(source);
         let before = self.dependencies.len();
-        self.dependencies
-            .retain(|d| git::normalize_source(d.source()) != normalized);
+        self.dependencies.retain(|d| d.key() != normalized);
         self.dependencies.len() < before
     }
 }
 
+/// Directory holding the user-level manifest for global installs.
+///
+/// Resolves to `$XDG_CONFIG_HOME/agentfiles/` when the variable is set,
+/// otherwise to the platform config directory (via `dirs::config_dir()`).
+pub fn user_manifest_dir() -> Result<PathBuf> {
+    user_manifest_dir_in(
+        std::env::var_os("XDG_CONFIG_HOME")
+            .as_deref()
+            .map(Path::new),
+    )
+}
+
+/// `user_manifest_dir` for the given `XDG_CONFIG_HOME` value, if any.
+pub(crate) fn user_manifest_dir_in(xdg_config_home: Option<&Path>) -> Result<PathBuf> {
+    let base = match xdg_config_home {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => dirs::config_dir().context("could not determine config directory")?,
+    };
+    Ok(base.join("agentfiles"))
+}
+
+/// File name of the shared project manifest.
+pub const MANIFEST_FILE: &str = "agentfiles.json";
+
+/// File name of the personal manifest layered over `agentfiles.json` for
+/// local-scope installs.
+pub const LOCAL_MANIFEST_FILE: &str = "agentfiles.local.json";
+
+/// Path of the manifest that tracks installs for `scope`.
+///
+/// - **Project** scope: `<project_root>/agentfiles.json`.
+/// - **Local** scope: `<project_root>/agentfiles.local.json`.
+/// - **Global** scope: `agentfiles.json` in the user manifest directory
+///   (see `user_manifest_dir`).
+pub fn manifest_path(scope: &FileScope, project_root: &Path) -> Result<PathBuf> {
+    // Only the global scope needs the user manifest directory
+    let user_dir = match scope {
+        FileScope::Global => user_manifest_dir()?,
+        FileScope::Project | FileScope::Local => PathBuf::new(),
+    };
+    Ok(manifest_path_in(scope, project_root, &user_dir))
+}
+
+/// `manifest_path`, with the user manifest directory given as `user_dir`.
+pub fn manifest_path_in(scope: &FileScope, project_root: &Path, user_dir: &Path) -> PathBuf {
+    match scope {
+        FileScope::Project => project_root.join(MANIFEST_FILE),
+        FileScope::Local => project_root.join(LOCAL_MANIFEST_FILE),
+        FileScope::Global => user_dir.join(MANIFEST_FILE),
+    }
+}
+
 /// Load a manifest from a file path or directory.
 ///
 /// If `path` is a directory, looks for `agentfiles.json` inside it.
 pub fn load_manifest(path: &Path) -> Result<Manifest> {
     debug!("Loading manifest from {}", path.display());
     if path.is_dir() {
-        return load_manifest(&path.join("agentfiles.json"));
+        return load_manifest(&path.join(MANIFEST_FILE));
     }
     let content = std::fs::read_to_string(path).context("failed to read manifest")?;
     serde_json::from_str(&content).context("failed to parse manifest file")
 }
 
+/// Load the user manifest (see `user_manifest_dir`), if it exists.
+pub fn load_user_manifest() -> Result<Option<Manifest>> {
+    load_user_manifest_in(&user_manifest_dir()?)
+}
+
+/// Load the user manifest from the directory `user_dir`, if it exists.
+pub fn load_user_manifest_in(user_dir: &Path) -> Result<Option<Manifest>> {
+    let path = user_dir.join(MANIFEST_FILE);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    load_manifest(&path)
+        .with_context(|| format!("failed to load user manifest {}", path.display()))
+        .map(Some)
+}
+
 /// Save a manifest to a directory as `agentfiles.json`.
 ///
 /// Returns the full path of the written file.
@@ -243,12 +655,19 @@ pub fn save_manifest(manifest: &Manifest, path: &Path) -> Result<PathBuf> {
     if path.is_file() {
         bail!("cannot save manifest to a file, provide a directory path.");
     }
-    let content = serde_json::to_string_pretty(manifest)? + "\n";
-    let output_path = path.join("agentfiles.json");
-    std::fs::write(&output_path, content).context("failed to write manifest")?;
+    let output_path = path.join(MANIFEST_FILE);
+    save_manifest_file(manifest, &output_path)?;
     Ok(output_path)
 }
 
+/// Save a manifest to an explicit file path, e.g. one returned by
+/// `manifest_path`. Overwrites the file if it exists.
+pub fn save_manifest_file(manifest: &Manifest, file: &Path) -> Result<()> {
+    debug!("Saving manifest file {}", file.display());
+    let content = serde_json::to_string_pretty(manifest)? + "\n";
+    std::fs::write(file, content).context("failed to write manifest")
+}
+
 #[cfg(test)]
 mod tests {
     mod dependency_serialization {
@@ -279,11 +698,20 @@ mod tests {
                     "skills/review".to_string(),
                     "commands/deploy".to_string(),
                 ]),
+                exclude: None,
                 strategy: Some(FileStrategy::Link),
                 paths: Some(vec![PathMapping {
                     path: "prompts".to_string(),
                     kind: FileKind::Skill,
                 }]),
+                subdir: Some("tools/agents".to_string()),
+                integrity: None,
+                verify: None,
+                policy: None,
+                patches: None,
+                vars: None,
+                when: None,
+                rename: None,
             });
 
             let json = serde_json::to_string_pretty(&dep)?;
@@ -295,6 +723,7 @@ mod tests {
             assert_eq!(parsed.pick().unwrap().len(), 2);
             assert_eq!(parsed.strategy(), Some(FileStrategy::Link));
             assert_eq!(parsed.paths().unwrap().len(), 1);
+            assert_eq!(parsed.subdir(), Some("tools/agents"));
             Ok(())
         }
 
@@ -342,8 +771,17 @@ mod tests {
                         source: "github.com/mitsuhiko/agent-stuff".to_string(),
                         git_ref: Some("main".to_string()),
                         pick: Some(vec!["skills/commit".to_string()]),
+                        exclude: None,
                         strategy: None,
                         paths: None,
+                        subdir: None,
+                        integrity: None,
+                        verify: None,
+                        policy: None,
+                        patches: None,
+                        vars: None,
+                        when: None,
+                        rename: None,
                     }),
                 ],
                 ..Default::default()
@@ -379,6 +817,37 @@ mod tests {
             Ok(())
         }
 
+        #[test]
+        fn gitignore_flag_roundtrip() -> Result<()> {
+            let parsed: Manifest = serde_json::from_str(r#"{"name": "test", "gitignore": true}"#)?;
+            assert!(parsed.gitignore);
+            assert!(serde_json::to_string(&parsed)?.contains(r#""gitignore":true"#));
+
+            // Disabled flag is omitted
+            let serialized = serde_json::to_string(&Manifest::default())?;
+            assert!(!serialized.contains("gitignore"));
+            Ok(())
+        }
+
+        #[test]
+        fn signing_paths_resolve_against_manifest_dir() -> Result<()> {
+            let parsed: Manifest = serde_json::from_str(
+                r#"{"name": "test", "verify": true, "signing": {"allowed_signers": "keys/allowed"}}"#,
+            )?;
+            assert!(parsed.verify);
+            let policy = parsed.signing.unwrap().policy(Path::new("/project"))?;
+            assert_eq!(
+                policy.allowed_signers,
+                Some(PathBuf::from("/project/keys/allowed"))
+            );
+            assert_eq!(policy.gpg_home, None);
+
+            let serialized = serde_json::to_string(&Manifest::default())?;
+            assert!(!serialized.contains("verify"));
+            assert!(!serialized.contains("signing"));
+            Ok(())
+        }
+
         #[test]
         fn load_from_directory_path() -> Result<()> {
             let dir = TempDir::new()?;
@@ -427,6 +896,41 @@ mod tests {
             assert!(!manifest.has_dependency("github.com/other/repo"));
         }
 
+        #[test]
+        fn subdirs_of_one_repo_are_distinct() {
+            let mut manifest = Manifest::default();
+            assert!(manifest.add_dependency(Dependency::Simple(
+                "github.com/org/mono//tools/agents".to_string()
+            )));
+            assert!(
+                manifest.add_dependency(Dependency::Detailed(DependencySpec {
+                    source: "github.com/org/mono".to_string(),
+                    git_ref: None,
+                    pick: None,
+                    exclude: None,
+                    strategy: None,
+                    paths: None,
+                    subdir: Some("tools/other".to_string()),
+                    integrity: None,
+                    verify: None,
+                    policy: None,
+                    patches: None,
+                    vars: None,
+                    when: None,
+                    rename: None,
+                }))
+            );
+            assert!(!manifest.add_dependency(Dependency::Simple(
+                "https://github.com/org/mono.git//tools/agents@v2".to_string()
+            )));
+
+            assert!(manifest.has_dependency("github.com/org/mono//tools/other"));
+            assert!(!manifest.has_dependency("github.com/org/mono"));
+
+            assert!(manifest.remove_dependency("github.com/org/mono//tools/other"));
+            assert_eq!(manifest.dependencies.len(), 1);
+        }
+
         #[test]
         fn builder_methods() {
             let manifest = Manifest::default()
@@ -445,6 +949,81 @@ mod tests {
         }
     }
 
+    mod manifest_location {
+        use super::super::*;
+
+        #[test]
+        fn project_scope_uses_project_root() -> Result<()> {
+            let root = Path::new("/project");
+            assert_eq!(
+                manifest_path(&FileScope::Project, root)?,
+                root.join("agentfiles.json")
+            );
+            Ok(())
+        }
+
+        #[test]
+        fn local_scope_uses_local_manifest() -> Result<()> {
+            let root = Path::new("/project");
+            assert_eq!(
+                manifest_path(&FileScope::Local, root)?,
+                root.join("agentfiles.local.json")
+            );
+            Ok(())
+        }
+
+        #[test]
+        fn global_scope_uses_user_manifest_dir() -> Result<()> {
+            let config = Path::new("/config");
+            let user_dir = user_manifest_dir_in(Some(config))?;
+            assert_eq!(user_dir, config.join("agentfiles"));
+            assert_eq!(
+                manifest_path_in(&FileScope::Global, Path::new("/ignored"), &user_dir),
+                config.join("agentfiles/agentfiles.json")
+            );
+            // An empty XDG_CONFIG_HOME counts as unset
+            assert_eq!(
+                user_manifest_dir_in(Some(Path::new("")))?,
+                user_manifest_dir_in(None)?
+            );
+            Ok(())
+        }
+    }
+
+    mod local_layering {
+        use super::super::*;
+
+        #[test]
+        fn local_overrides_matching_source_in_place() {
+            let project = Manifest::default().with_dependencies(vec![
+                Dependency::Simple("github.com/org/a".to_string()),
+                Dependency::Simple("github.com/org/b".to_string()),
+            ]);
+            let local = Manifest::default().with_dependencies(vec![
+                Dependency::Simple("https://github.com/org/a.git@dev".to_string()),
+                Dependency::Simple("../my-agents".to_string()),
+            ]);
+
+            let layered = project.layer_local(&local);
+            assert_eq!(layered.len(), 3);
+            assert_eq!(layered[0].0.source(), "https://github.com/org/a.git@dev");
+            assert_eq!(layered[0].1, FileScope::Local);
+            assert_eq!(layered[1].0.source(), "github.com/org/b");
+            assert_eq!(layered[1].1, FileScope::Project);
+            assert_eq!(layered[2].0.source(), "../my-agents");
+            assert_eq!(layered[2].1, FileScope::Local);
+        }
+
+        #[test]
+        fn empty_local_keeps_project() {
+            let project = Manifest::default()
+                .with_dependencies(vec![Dependency::Simple("github.com/org/a".to_string())]);
+            let layered = project.layer_local(&Manifest::default());
+            assert_eq!(layered.len(), 1);
+            assert_eq!(layered[0].1, FileScope::Project);
+        }
+    }
+
     mod path_mapping {
         use super::super::*;
 
@@ -0,0 +1,391 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{Context, Result, bail};
+use log::debug;
+
+use crate::manifest::{FileMapping, Patch};
+use crate::{git, installer, scanner, store};
+
+/// Directory next to the manifest holding patches found by convention:
+/// `overrides/skills/review.diff` and `overrides/skills/review.append.md`
+/// apply to the `skills/review` item of whichever dependency provides it.
+pub(crate) const OVERRIDES_DIR: &str = "overrides";
+
+/// Patched copies of a dependency's items, removed when dropped.
+#[derive(Debug)]
+pub(crate) struct Overlay {
+    /// Stands in for the dependency's source directory.
+    pub dir: PathBuf,
+}
+
+impl Drop for Overlay {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_dir_all(&self.dir) {
+            debug!("Could not remove overlay {}: {}", self.dir.display(), err);
+        }
+    }
+}
+
+/// Apply `patches` and any patches in `base`'s overrides directory to the
+/// items in `files`, which were scanned from `source_dir`.
+///
+/// Upstream content is never changed: when anything applies, every item is
+/// copied into an `Overlay` that is patched instead, and the hash of each
+/// patched item is updated to match what will be installed. Returns `None`
+/// when there is nothing to apply.
+///
+/// A manifest patch for an item the dependency doesn't provide, or a diff
+/// that no longer applies to the upstream content, is an error.
+pub(crate) fn apply(
+    source: &str,
+    source_dir: &Path,
+    files: &mut [FileMapping],
+    patches: &[Patch],
+    base: &Path,
+) -> Result<Option<Overlay>> {
+    for patch in patches {
+        if !files.iter().any(|f| scanner::item_key(f) == patch.item) {
+            bail!("patch for '{}' matches no item of {source}", patch.item);
+        }
+    }
+
+    let mut pending: Vec<(usize, Patch)> = Vec::new();
+    for (i, file) in files.iter().enumerate() {
+        let key = scanner::item_key(file);
+        pending.extend(convention_patches(base, &key).into_iter().map(|p| (i, p)));
+        pending.extend(
+            patches
+                .iter()
+                .filter(|p| p.item == key)
+                .map(|p| (i, p.clone())),
+        );
+    }
+    if pending.is_empty() {
+        return Ok(None);
+    }
+
+    let overlay = Overlay { dir: overlay_dir() };
+    debug!(
+        "Applying {} patch(es) to {} in {}",
+        pending.len(),
+        source,
+        overlay.dir.display()
+    );
+    for file in files.iter() {
+        let from = source_dir.join(&file.path);
+        let to = overlay.dir.join(&file.path);
+        if from.is_dir() {
+            installer::copy_dir_recursive(&from, &to)?;
+        } else {
+            if let Some(parent) = to.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::copy(&from, &to).with_context(|| format!("failed to copy {}", from.display()))?;
+        }
+    }
+
+    // All diffs go first, as they are written against upstream content
+    for step in [Step::Diff, Step::Frontmatter, Step::Append] {
+        for (i, patch) in &pending {
+            let item = overlay.dir.join(&files[*i].path);
+            apply_patch(&item, patch, base, step)
+                .with_context(|| format!("could not patch '{}' from {source}", patch.item))?;
+        }
+    }
+    for (i, _) in &pending {
+        let file = &mut files[*i];
+        file.hash = store::hash_tree(&overlay.dir.join(&file.path))?;
+    }
+    Ok(Some(overlay))
+}
+
+/// Patches for the item `key` found in `base`'s overrides directory.
+fn convention_patches(base: &Path, key: &str) -> Vec<Patch> {
+    let dir = base.join(OVERRIDES_DIR);
+    let diff = dir.join(format!("{key}.diff"));
+    let append = dir.join(format!("{key}.append.md"));
+    let mut patches = Vec::new();
+    if diff.is_file() {
+        patches.push(Patch {
+            item: key.to_string(),
+            diff: Some(diff.to_string_lossy().into_owned()),
+            ..Default::default()
+        });
+    }
+    if append.is_file() {
+        patches.push(Patch {
+            item: key.to_string(),
+            append: Some(append.to_string_lossy().into_owned()),
+            ..Default::default()
+        });
+    }
+    patches
+}
+
+/// A fresh directory for an overlay in the system temp directory.
+fn overlay_dir() -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    std::env::temp_dir().join(format!(
+        "agentfiles-overlay-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ))
+}
+
+/// The parts of a patch, in the order they are applied.
+#[derive(Clone, Copy, PartialEq)]
+enum Step {
+    Diff,
+    Frontmatter,
+    Append,
+}
+
+/// Apply one `step` of a patch to the copy of an item at `item`.
+fn apply_patch(item: &Path, patch: &Patch, base: &Path, step: Step) -> Result<()> {
+    if step == Step::Diff
+        && let Some(diff) = &patch.diff
+    {
+        let diff = base.join(diff);
+        // Diff paths are relative to the item: the skill directory, or the
+        // directory holding a command or agent file
+        let dir = if item.is_dir() {
+            item
+        } else {
+            item.parent().unwrap_or(item)
+        };
+        git::apply_patch(dir, &diff).with_context(|| {
+            format!(
+                "{} no longer applies; update it against the new upstream version",
+                diff.display()
+            )
+        })?;
+    }
+
+    let markdown = if item.is_dir() {
+        item.join("SKILL.md")
+    } else {
+        item.to_path_buf()
+    };
+    if step == Step::Frontmatter && !patch.frontmatter.is_empty() {
+        let text = fs::read_to_string(&markdown)
+            .with_context(|| format!("failed to read {}", markdown.display()))?;
+        fs::write(&markdown, set_frontmatter(&text, &patch.frontmatter))?;
+    }
+    if step == Step::Append
+        && let Some(append) = &patch.append
+    {
+        let append = base.join(append);
+        let extra = fs::read_to_string(&append)
+            .with_context(|| format!("failed to read {}", append.display()))?;
+        let mut text = fs::read_to_string(&markdown)
+            .with_context(|| format!("failed to read {}", markdown.display()))?;
+        if !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        text.push('\n');
+        text.push_str(&extra);
+        fs::write(&markdown, text)?;
+    }
+    Ok(())
+}
+
+/// Set top-level `fields` in the YAML frontmatter of `text`, replacing
+/// existing values (including indented continuation lines) and adding new
+/// fields at the end. A frontmatter block is created if there is none.
+pub(crate) fn set_frontmatter(
+    text: &str,
+    fields: &std::collections::BTreeMap<String, serde_json::Value>,
+) -> String {
+    let (mut lines, body): (Vec<String>, &str) = match frontmatter_bounds(text) {
+        Some((yaml, body)) => (yaml.lines().map(str::to_string).collect(), body),
+        None => (Vec::new(), text),
+    };
+
+    for (key, value) in fields {
+        let line = format!("{key}: {}", yaml_value(value));
+        let prefix = format!("{key}:");
+        match lines.iter().position(|l| l.starts_with(&prefix)) {
+            Some(start) => {
+                let end = lines[start + 1..]
+                    .iter()
+                    .position(|l| !l.starts_with([' ', '\t', '-']) && !l.trim().is_empty())
+                    .map_or(lines.len(), |n| start + 1 + n);
+                lines.splice(start..end, [line]);
+            }
+            None => lines.push(line),
+        }
+    }
+    format!("---\n{}\n---\n{body}", lines.join("\n"))
+}
+
+/// Whether the YAML frontmatter of `text` sets the top-level `key`.
+pub(crate) fn has_frontmatter_field(text: &str, key: &str) -> bool {
+    frontmatter_bounds(text).is_some_and(|(yaml, _)| {
+        yaml.lines()
+            .any(|line| line.strip_prefix(key).is_some_and(|r| r.starts_with(':')))
+    })
+}
+
+/// The YAML between the leading `---` lines of `text`, and the rest.
+fn frontmatter_bounds(text: &str) -> Option<(&str, &str)> {
+    let rest = text
+        .strip_prefix("---\n")
+        .or_else(|| text.strip_prefix("---\r\n"))?;
+    if let Some(body) = rest.strip_prefix("---\n") {
+        return Some(("", body));
+    }
+    let end = rest.find("\n---")?;
+    let after = &rest[end + 4..];
+    let body = after
+        .strip_prefix("\r\n")
+        .or_else(|| after.strip_prefix('\n'))
+        .unwrap_or(after);
+    Some((&rest[..end], body))
+}
+
+/// Render a JSON value as YAML. Plain strings stay unquoted; everything
+/// else uses JSON syntax, which is valid YAML.
+fn yaml_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s)
+            if !s.is_empty()
+                && s.chars()
+                    .all(|c| c.is_alphanumeric() || " -_./".contains(c))
+                && !s.starts_with([' ', '-'])
+                && !s.ends_with(' ') =>
+        {
+            s.clone()
+        }
+        other => other.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{FileKind, FileStrategy};
+    use std::collections::BTreeMap;
+    use tempfile::TempDir;
+
+    #[test]
+    fn sets_frontmatter_fields() {
+        let fields = BTreeMap::from([
+            ("model".to_string(), serde_json::json!("opus")),
+            ("tools".to_string(), serde_json::json!(["Read", "Grep"])),
+        ]);
+        let text = "---\nname: review\nmodel: sonnet\ntools:\n  - Bash\n---\n# Review\n";
+        assert_eq!(
+            set_frontmatter(text, &fields),
+            "---\nname: review\nmodel: opus\ntools: [\"Read\",\"Grep\"]\n---\n# Review\n"
+        );
+        assert_eq!(
+            set_frontmatter("# Deploy\n", &fields),
+            "---\nmodel: opus\ntools: [\"Read\",\"Grep\"]\n---\n# Deploy\n"
+        );
+    }
+
+    fn skill() -> FileMapping {
+        FileMapping {
+            path: PathBuf::from("skills/review"),
+            kind: FileKind::Skill,
+            strategy: FileStrategy::Copy,
+            hash: String::new(),
+        }
+    }
+
+    #[test]
+    fn applies_patches_to_a_copy() -> Result<()> {
+        let source = TempDir::new()?;
+        let base = TempDir::new()?;
+        let skill_dir = source.path().join("skills/review");
+        fs::create_dir_all(&skill_dir)?;
+        fs::write(
+            skill_dir.join("SKILL.md"),
+            "---\nname: review\n---\n# Review\n\nCheck the code.\n",
+        )?;
+        fs::write(
+            base.path().join("review.diff"),
+            "--- a/SKILL.md\n+++ b/SKILL.md\n@@ -3,3 +3,3 @@\n # Review\n \n-Check the code.\n+Check the code against our style guide.\n",
+        )?;
+        fs::create_dir_all(base.path().join("overrides/skills"))?;
+        fs::write(
+            base.path().join("overrides/skills/review.append.md"),
+            "## Team notes\n",
+        )?;
+
+        let mut files = vec![skill()];
+        let patches = [Patch {
+            item: "skills/review".to_string(),
+            diff: Some("review.diff".to_string()),
+            frontmatter: BTreeMap::from([("model".to_string(), serde_json::json!("opus"))]),
+            append: None,
+        }];
+        let overlay = apply("upstream", source.path(), &mut files, &patches, base.path())?
+            .expect("patches apply");
+
+        let patched = fs::read_to_string(overlay.dir.join("skills/review/SKILL.md"))?;
+        assert_eq!(
+            patched,
+            "---\nname: review\nmodel: opus\n---\n# Review\n\nCheck the code against our style guide.\n\n## Team notes\n"
+        );
+        assert_eq!(
+            files[0].hash,
+            store::hash_tree(&overlay.dir.join("skills/review"))?
+        );
+        // Upstream is untouched, and the overlay goes away with its guard
+        assert!(fs::read_to_string(skill_dir.join("SKILL.md"))?.contains("Check the code."));
+        let dir = overlay.dir.clone();
+        drop(overlay);
+        assert!(!dir.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn stale_patches_are_errors() -> Result<()> {
+        let source = TempDir::new()?;
+        let base = TempDir::new()?;
+        let skill_dir = source.path().join("skills/review");
+        fs::create_dir_all(&skill_dir)?;
+        fs::write(skill_dir.join("SKILL.md"), "# Review, rewritten\n")?;
+        fs::write(
+            base.path().join("review.diff"),
+            "--- a/SKILL.md\n+++ b/SKILL.md\n@@ -1 +1 @@\n-# Review\n+# Our review\n",
+        )?;
+
+        let patch = |item: &str| Patch {
+            item: item.to_string(),
+            diff: Some("review.diff".to_string()),
+            ..Default::default()
+        };
+        let err = apply(
+            "upstream",
+            source.path(),
+            &mut [skill()],
+            &[patch("skills/review")],
+            base.path(),
+        )
+        .unwrap_err();
+        let message = format!("{err:#}");
+        assert!(message.contains("could not patch 'skills/review' from upstream"));
+        assert!(message.contains("review.diff no longer applies"));
+
+        let err = apply(
+            "upstream",
+            source.path(),
+            &mut [skill()],
+            &[patch("skills/missing")],
+            base.path(),
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("patch for 'skills/missing' matches no item of upstream")
+        );
+
+        // Nothing to apply means no overlay at all
+        assert!(apply("upstream", source.path(), &mut [skill()], &[], base.path())?.is_none());
+        Ok(())
+    }
+}
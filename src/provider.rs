@@ -39,7 +39,7 @@ impl ProviderLayout {
     /// Returns the base directory for a given scope.
     fn base(&self, scope: &FileScope) -> &'static str {
         match scope {
-            FileScope::Project => self.project_base,
+            FileScope::Project | FileScope::Local => self.project_base,
             FileScope::Global => self.global_base,
         }
     }
@@ -107,7 +107,7 @@ impl AgentProvider {
 
     /// Resolves the full target directory for a given scope and file kind.
     ///
-    /// - **Project** scope: `<project_root>/<base>/<kind_dir>/`
+    /// - **Project** and **Local** scope: `<project_root>/<base>/<kind_dir>/`
     /// - **Global** scope: `$HOME/<base>/<kind_dir>/`
     ///
     /// Returns an error if the provider does not support the file kind,
@@ -124,7 +124,7 @@ impl AgentProvider {
             .with_context(|| format!("{self} does not support {kind} files"))?;
 
         let root = match scope {
-            FileScope::Project => project_root.to_path_buf(),
+            FileScope::Project | FileScope::Local => project_root.to_path_buf(),
             FileScope::Global => dirs::home_dir().context("could not determine home directory")?,
         };
 
@@ -225,6 +225,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn local_dirs_match_project_dirs() {
+        let root = Path::new("/project");
+        for provider in AgentProvider::ALL {
+            for kind in provider.supported_kinds() {
+                assert_eq!(
+                    provider
+                        .get_target_dir(&FileScope::Local, &kind, root)
+                        .unwrap(),
+                    provider
+                        .get_target_dir(&FileScope::Project, &kind, root)
+                        .unwrap()
+                );
+            }
+        }
+    }
+
     #[test]
     fn global_dirs_resolve_to_home() {
         let home = dirs::home_dir().expect("need $HOME for this test");
@@ -1,11 +1,12 @@
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
 use log::debug;
 
 use crate::manifest::{FileMapping, PathMapping};
 use crate::types::{AgentProvider, FileKind, FileStrategy};
+use crate::{installer, store};
 
 /// Subdirectory names and their corresponding file kind.
 const KIND_DIRS: &[(&str, FileKind)] = &[
@@ -55,36 +56,148 @@ pub(crate) fn scan_agent_files(
     Ok(mappings)
 }
 
-/// Filter a list of file mappings by a pick list.
+/// Repository-relative paths that `scan_agent_files` may look at.
 ///
-/// Pick items can be kind-prefixed (`"skills/review"`, `"commands/deploy"`)
-/// or plain names (`"review"`). A plain name matches any kind.
-pub(crate) fn filter_by_pick(mappings: Vec<FileMapping>, pick: &[String]) -> Vec<FileMapping> {
+/// Mirrors the scanning rules: the custom `paths` when given, otherwise
+/// every provider-prefixed and bare kind directory. Used to restrict git
+/// checkouts to the parts of a repository that can contain agent files.
+pub(crate) fn scan_roots(custom_paths: Option<&[PathMapping]>) -> Vec<String> {
+    if let Some(paths) = custom_paths {
+        return paths
+            .iter()
+            .map(|m| m.path.trim_matches('/').to_string())
+            .collect();
+    }
+
+    let mut roots = Vec::new();
+    for prefix in AgentProvider::PROJECT_BASES {
+        for &(kind_name, _) in KIND_DIRS {
+            roots.push(format!("{prefix}/{kind_name}"));
+        }
+    }
+    for &(kind_name, _) in KIND_DIRS {
+        roots.push(kind_name.to_string());
+    }
+    roots
+}
+
+/// Select items of `mappings` with `pick` patterns (every item when
+/// `None`), then drop those matching an `exclude` pattern. Returns the
+/// selected items and the pick patterns that matched no item at all.
+///
+/// Patterns can be kind-prefixed (`"skills/review"`, `"commands/deploy"`)
+/// or plain names (`"review"`), which match any kind. They may use globs
+/// (see `glob_match`): `skills/review-*`, `agents/*`. A pattern with a
+/// further `/` matches the item's path inside its kind directory, so
+/// `commands/**/deploy` also selects `commands/ops/deploy.md`.
+pub(crate) fn select(
+    mappings: Vec<FileMapping>,
+    pick: Option<&[String]>,
+    exclude: &[String],
+) -> (Vec<FileMapping>, Vec<String>) {
     debug!(
-        "Filtering {} mapping(s) by {} pick item(s)",
+        "Selecting from {} mapping(s) with {} pick and {} exclude pattern(s)",
         mappings.len(),
-        pick.len()
+        pick.map_or(0, <[String]>::len),
+        exclude.len()
     );
-    mappings
+    let unmatched = pick
+        .unwrap_or_default()
+        .iter()
+        .filter(|p| !mappings.iter().any(|m| pattern_matches(p, m)))
+        .cloned()
+        .collect();
+    let selected = mappings
         .into_iter()
-        .filter(|m| {
-            let name = m.path.file_stem().unwrap_or_default().to_string_lossy();
-
-            pick.iter().any(|p| {
-                if let Some((kind_prefix, pick_name)) = p.split_once('/') {
-                    let kind_matches = match kind_prefix {
-                        "skills" => m.kind == FileKind::Skill,
-                        "commands" => m.kind == FileKind::Command,
-                        "agents" => m.kind == FileKind::Agent,
-                        _ => false,
-                    };
-                    kind_matches && name == pick_name
-                } else {
-                    name == p.as_str()
-                }
-            })
-        })
-        .collect()
+        .filter(|m| pick.is_none_or(|pick| pick.iter().any(|p| pattern_matches(p, m))))
+        .filter(|m| !exclude.iter().any(|p| pattern_matches(p, m)))
+        .collect();
+    (selected, unmatched)
+}
+
+/// Whether the pick or exclude `pattern` matches `mapping`.
+fn pattern_matches(pattern: &str, mapping: &FileMapping) -> bool {
+    let pattern = pattern.trim_matches('/');
+    let (kind_dir, name) = kind_path(mapping);
+    let pattern = match pattern.split_once('/') {
+        Some((prefix, rest)) if KIND_DIRS.iter().any(|(dir, _)| *dir == prefix) => {
+            if prefix != kind_dir {
+                return false;
+            }
+            rest
+        }
+        _ => pattern,
+    };
+    if pattern.contains('/') {
+        glob_match(pattern, &name)
+    } else {
+        glob_match(pattern, name.rsplit('/').next().unwrap_or_default())
+    }
+}
+
+/// The kind directory name of `mapping` and its path inside that
+/// directory, without the `.md` extension: `("commands", "ops/deploy")`.
+/// Items found through custom `paths` are known by their name alone.
+fn kind_path(mapping: &FileMapping) -> (&'static str, String) {
+    let kind_dir = KIND_DIRS
+        .iter()
+        .find(|(_, kind)| *kind == mapping.kind)
+        .map_or("", |(dir, _)| dir);
+    let components: Vec<String> = mapping
+        .path
+        .with_extension("")
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    let inside = match components.iter().rposition(|c| c == kind_dir) {
+        Some(i) if i + 1 < components.len() => components[i + 1..].join("/"),
+        _ => components.last().cloned().unwrap_or_default(),
+    };
+    (kind_dir, inside)
+}
+
+/// Whether `path` matches the glob `pattern`. `*` matches any run of
+/// characters and `?` any one character within a `/`-separated segment;
+/// a `**` segment matches any number of segments, including none.
+fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').collect();
+    let path: Vec<&str> = path.split('/').collect();
+    match_segments(&pattern, &path)
+}
+
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| match_segments(rest, &path[i..])),
+        Some((first, rest)) => path.split_first().is_some_and(|(segment, path)| {
+            match_segment(first, segment) && match_segments(rest, path)
+        }),
+    }
+}
+
+fn match_segment(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and of the text it currently stands for
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // Let the `*` take one more character and retry
+            star = Some((star_p, star_t + 1));
+            p = star_p + 1;
+            t = star_t + 1;
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
 }
 
 /// Infer the folder name from a path to use as a manifest name.
@@ -149,6 +262,14 @@ fn scan_kind_dir(
     for entry in entries {
         let entry = entry?;
         let entry_path = entry.path();
+        if entry
+            .file_name()
+            .to_string_lossy()
+            .starts_with(installer::STAGING_PREFIX)
+        {
+            debug!("Skipping staging directory {}", entry_path.display());
+            continue;
+        }
 
         match kind {
             FileKind::Skill => {
@@ -160,11 +281,7 @@ fn scan_kind_dir(
                             .unwrap_or(&entry_path)
                             .to_path_buf();
                         debug!("Found skill: {}", rel_path.display());
-                        mappings.push(FileMapping {
-                            path: rel_path,
-                            kind: FileKind::Skill,
-                            strategy: FileStrategy::Copy,
-                        });
+                        mappings.push(file_mapping(&entry_path, rel_path, FileKind::Skill)?);
                         continue;
                     }
                     debug!("No SKILL.md in {}, recursing", entry_path.display());
@@ -182,11 +299,7 @@ fn scan_kind_dir(
                         .unwrap_or(&entry_path)
                         .to_path_buf();
                     debug!("Found {}: {}", kind, rel_path.display());
-                    mappings.push(FileMapping {
-                        path: rel_path,
-                        kind: *kind,
-                        strategy: FileStrategy::Copy,
-                    });
+                    mappings.push(file_mapping(&entry_path, rel_path, *kind)?);
                     continue;
                 }
                 if entry_path.is_dir() {
@@ -225,17 +338,31 @@ fn scan_custom_paths(
                 .strip_prefix(root)
                 .unwrap_or(&full_path)
                 .to_path_buf();
-            mappings.push(FileMapping {
-                path: rel_path,
-                kind: mapping.kind,
-                strategy: FileStrategy::Copy,
-            });
+            mappings.push(file_mapping(&full_path, rel_path, mapping.kind)?);
         }
     }
 
     Ok(())
 }
 
+/// Build the mapping for the item at `path`, hashing its content.
+fn file_mapping(path: &Path, rel_path: PathBuf, kind: FileKind) -> Result<FileMapping> {
+    Ok(FileMapping {
+        hash: store::hash_tree(path)?,
+        path: rel_path,
+        kind,
+        strategy: FileStrategy::Copy,
+    })
+}
+
+/// Identifier of an item within its source, in the kind-prefixed form
+/// `pick` accepts: `skills/review`, `commands/deploy`, and
+/// `commands/ops/deploy` for a command nested in `commands/ops/`.
+pub(crate) fn item_key(mapping: &FileMapping) -> String {
+    let (kind_dir, inside) = kind_path(mapping);
+    format!("{kind_dir}/{inside}")
+}
+
 /// Deduplicate file mappings by their name + kind.
 ///
 /// If the same skill/command/agent name appears from multiple provider
@@ -299,6 +426,20 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn skips_leftover_staging_directories() -> Result<()> {
+        let dir = TempDir::new()?;
+        setup_skill(dir.path(), ".claude", "review");
+        let staged = dir.path().join(".claude/skills/.agentfiles-staging-42/1");
+        fs::create_dir_all(&staged)?;
+        fs::write(staged.join("SKILL.md"), "# Half-written")?;
+
+        let mappings = scan_agent_files(dir.path(), None)?;
+        assert_eq!(mappings.len(), 1);
+        assert!(mappings[0].path.ends_with("review"));
+        Ok(())
+    }
+
     #[test]
     fn scans_multiple_providers() -> Result<()> {
         let dir = TempDir::new()?;
@@ -473,20 +614,23 @@ mod tests {
                 path: "skills/review".into(),
                 kind: FileKind::Skill,
                 strategy: FileStrategy::Copy,
+                hash: String::new(),
             },
             FileMapping {
                 path: "skills/deploy".into(),
                 kind: FileKind::Skill,
                 strategy: FileStrategy::Copy,
+                hash: String::new(),
             },
             FileMapping {
                 path: "commands/deploy.md".into(),
                 kind: FileKind::Command,
                 strategy: FileStrategy::Copy,
+                hash: String::new(),
             },
         ];
 
-        let filtered = filter_by_pick(mappings, &["review".to_string()]);
+        let (filtered, _) = select(mappings, Some(&["review".to_string()]), &[]);
         assert_eq!(filtered.len(), 1);
         assert_eq!(filtered[0].kind, FileKind::Skill);
     }
@@ -498,15 +642,17 @@ mod tests {
                 path: "skills/deploy".into(),
                 kind: FileKind::Skill,
                 strategy: FileStrategy::Copy,
+                hash: String::new(),
             },
             FileMapping {
                 path: "commands/deploy.md".into(),
                 kind: FileKind::Command,
                 strategy: FileStrategy::Copy,
+                hash: String::new(),
             },
         ];
 
-        let filtered = filter_by_pick(mappings, &["commands/deploy".to_string()]);
+        let (filtered, _) = select(mappings, Some(&["commands/deploy".to_string()]), &[]);
         assert_eq!(filtered.len(), 1);
         assert_eq!(filtered[0].kind, FileKind::Command);
     }
@@ -518,18 +664,73 @@ mod tests {
                 path: "skills/deploy".into(),
                 kind: FileKind::Skill,
                 strategy: FileStrategy::Copy,
+                hash: String::new(),
             },
             FileMapping {
                 path: "commands/deploy.md".into(),
                 kind: FileKind::Command,
                 strategy: FileStrategy::Copy,
+                hash: String::new(),
             },
         ];
 
-        let filtered = filter_by_pick(mappings, &["deploy".to_string()]);
+        let (filtered, _) = select(mappings, Some(&["deploy".to_string()]), &[]);
         assert_eq!(filtered.len(), 2);
     }
 
+    #[test]
+    fn select_with_globs_and_excludes() {
+        let mappings: Vec<FileMapping> = [
+            ("skills/review-code", FileKind::Skill),
+            ("skills/review-docs", FileKind::Skill),
+            ("skills/release", FileKind::Skill),
+            (".claude/commands/ops/deploy.md", FileKind::Command),
+            ("commands/deploy.md", FileKind::Command),
+            ("agents/planner.md", FileKind::Agent),
+            ("agents/tester.md", FileKind::Agent),
+        ]
+        .into_iter()
+        .map(|(path, kind)| FileMapping {
+            path: path.into(),
+            kind,
+            strategy: FileStrategy::Copy,
+            hash: String::new(),
+        })
+        .collect();
+        let patterns = |list: &[&str]| list.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        let paths = |selected: Vec<FileMapping>| {
+            selected
+                .into_iter()
+                .map(|m| m.path.display().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        let (selected, unmatched) = select(
+            mappings.clone(),
+            Some(&patterns(&[
+                "skills/review-*",
+                "commands/**/deploy",
+                "agents/*",
+                "skills/revew",
+            ])),
+            &patterns(&["review-docs", "agents/t?ster"]),
+        );
+        assert_eq!(
+            paths(selected),
+            [
+                "skills/review-code",
+                ".claude/commands/ops/deploy.md",
+                "commands/deploy.md",
+                "agents/planner.md",
+            ]
+        );
+        assert_eq!(unmatched, ["skills/revew"]);
+
+        let (selected, unmatched) = select(mappings, Some(&patterns(&["commands/ops/*"])), &[]);
+        assert_eq!(paths(selected), [".claude/commands/ops/deploy.md"]);
+        assert!(unmatched.is_empty());
+    }
+
     #[test]
     fn infer_name_from_path() {
         assert_eq!(infer_name(Path::new("/home/user/my-project")), "my-project");
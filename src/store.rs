@@ -0,0 +1,361 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use anyhow::{Context, Result};
+use log::debug;
+use sha2::{Digest, Sha256};
+
+use crate::ledger::Ledger;
+
+/// Temporary snapshots older than this are treated as leftovers of an
+/// interrupted install and removed by `gc`.
+const STALE_TMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Prefix of in-progress snapshot directories inside the store.
+const TMP_PREFIX: &str = ".tmp-";
+
+/// Directory holding agentfiles' persistent data (the link store and the
+/// install ledgers).
+///
+/// Resolves to `$XDG_DATA_HOME/agentfiles/` when the variable is set,
+/// otherwise to the platform data directory (via `dirs::data_dir()`).
+pub fn data_dir() -> Result<PathBuf> {
+    let base = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => dirs::data_dir().context("could not determine data directory")?,
+    };
+    Ok(base.join("agentfiles"))
+}
+
+/// Content-addressed store for link-strategy installs inside `data_dir`.
+pub fn store_dir(data_dir: &Path) -> PathBuf {
+    data_dir.join("store")
+}
+
+/// Compute a SHA-256 hash over a file or directory tree.
+///
+/// Directories are walked in sorted order and every regular file
+/// contributes its relative path, executable bit and contents, so the hash
+/// only changes when the installed content would. Symlinks are skipped,
+/// matching how copies are made. The item's own name is not part of the
+/// hash.
+pub(crate) fn hash_tree(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    if path.is_dir() {
+        hash_dir(path, "", &mut hasher)?;
+    } else {
+        hash_file(path, &mut hasher)?;
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn hash_dir(dir: &Path, prefix: &str, hasher: &mut Sha256) -> Result<()> {
+    let mut entries = fs::read_dir(dir)
+        .with_context(|| format!("failed to read directory: {}", dir.display()))?
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let file_type = entry.file_type()?;
+        let rel = format!("{prefix}{}", entry.file_name().to_string_lossy());
+        if file_type.is_symlink() {
+            continue;
+        } else if file_type.is_dir() {
+            hasher.update(format!("d {rel}\0").as_bytes());
+            hash_dir(&entry.path(), &format!("{rel}/"), hasher)?;
+        } else {
+            let mode = if is_executable(&entry.path()) {
+                "x"
+            } else {
+                "f"
+            };
+            hasher.update(format!("{mode} {rel}\0").as_bytes());
+            hash_file(&entry.path(), hasher)?;
+        }
+    }
+    Ok(())
+}
+
+fn hash_file(path: &Path, hasher: &mut Sha256) -> Result<()> {
+    let mut file =
+        fs::File::open(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let mut buf = [0u8; 8192];
+    let mut len: u64 = 0;
+    let mut content = Sha256::new();
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        content.update(&buf[..n]);
+        len += n as u64;
+    }
+    // Length-prefix each file so adjacent files can't run into each other
+    hasher.update(len.to_le_bytes());
+    hasher.update(content.finalize());
+    Ok(())
+}
+
+#[cfg(unix)]
+pub(crate) fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .map(|m| m.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn is_executable(_path: &Path) -> bool {
+    false
+}
+
+/// Snapshot an agent file or skill directory into the store.
+///
+/// The snapshot lives at `<store>/<sha256>/<name>`, where the hash covers
+/// the content (see `hash_tree`) and `name` is the source's file name.
+/// Existing snapshots are reused as-is; new ones are written to a temporary
+/// directory first and moved into place, so a snapshot is never observed
+/// half-written. Returns the hash and the snapshot path.
+pub(crate) fn snapshot(source: &Path, store: &Path) -> Result<(String, PathBuf)> {
+    let name = source
+        .file_name()
+        .with_context(|| format!("path has no file name: {}", source.display()))?;
+    let hash = hash_tree(source)?;
+    let entry = store.join(&hash);
+    let dest = entry.join(name);
+
+    if dest.exists() {
+        debug!("Store hit: {}", dest.display());
+        return Ok((hash, dest));
+    }
+
+    debug!("Snapshotting {} -> {}", source.display(), dest.display());
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let tmp = store.join(format!("{TMP_PREFIX}{}-{nanos}", std::process::id()));
+    fs::create_dir_all(&tmp)
+        .with_context(|| format!("failed to create directory: {}", tmp.display()))?;
+    let staged = tmp.join(name);
+    let copied = if source.is_dir() {
+        copy_tree(source, &staged)
+    } else {
+        fs::copy(source, &staged).map(|_| ()).map_err(Into::into)
+    };
+    let placed = copied.and_then(|()| {
+        fs::create_dir_all(&entry)
+            .with_context(|| format!("failed to create directory: {}", entry.display()))?;
+        if let Err(err) = fs::rename(&staged, &dest) {
+            // A concurrent install may have placed the same snapshot
+            if !dest.exists() {
+                return Err(err).with_context(|| format!("failed to write {}", dest.display()));
+            }
+        }
+        Ok(())
+    });
+    let _ = fs::remove_dir_all(&tmp);
+    placed?;
+
+    Ok((hash, dest))
+}
+
+/// Recursively copy a directory, skipping symlinks like installed copies do.
+fn copy_tree(src: &Path, dst: &Path) -> Result<()> {
+    fs::create_dir_all(dst)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let dst_path = dst.join(entry.file_name());
+        if file_type.is_symlink() {
+            continue;
+        } else if file_type.is_dir() {
+            copy_tree(&entry.path(), &dst_path)?;
+        } else {
+            fs::copy(entry.path(), &dst_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Outcome of a store garbage collection.
+#[derive(Debug, Default)]
+pub(crate) struct GcReport {
+    /// Snapshots that are still linked from some install.
+    pub kept: usize,
+    /// Snapshot directories that were (or, in a dry run, would be) removed.
+    pub removed: Vec<PathBuf>,
+}
+
+/// Remove snapshots that no ledger references.
+///
+/// A snapshot is referenced when some ledger entry records it and the
+/// entry's target is still a symlink into it. Ledger entries whose link has
+/// been removed or replaced are dropped along the way, so deleting a linked
+/// file by hand is enough to make its snapshot collectable.
+pub(crate) fn gc(data_dir: &Path, dry_run: bool) -> Result<GcReport> {
+    let store = store_dir(data_dir);
+    let mut report = GcReport::default();
+    if !store.is_dir() {
+        return Ok(report);
+    }
+
+    let mut referenced = HashSet::new();
+    for mut ledger in Ledger::load_all(data_dir)? {
+        let before = ledger.entries.len();
+        ledger.entries.retain(|e| {
+            let Some(hash) = &e.snapshot else {
+                return true;
+            };
+            let live =
+                fs::read_link(&e.target).is_ok_and(|link| link.starts_with(store.join(hash)));
+            if live {
+                referenced.insert(hash.clone());
+            }
+            live
+        });
+        if !dry_run && ledger.entries.len() != before {
+            ledger.save(data_dir)?;
+        }
+    }
+
+    for entry in fs::read_dir(&store)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = entry.path();
+        if name.starts_with(TMP_PREFIX) {
+            let stale = entry
+                .metadata()
+                .and_then(|m| m.modified())
+                .ok()
+                .and_then(|t| t.elapsed().ok())
+                .is_some_and(|age| age > STALE_TMP_AGE);
+            if !stale {
+                continue;
+            }
+        } else if referenced.contains(&name) {
+            report.kept += 1;
+            continue;
+        }
+
+        debug!("Collecting {}", path.display());
+        if !dry_run {
+            fs::remove_dir_all(&path)
+                .with_context(|| format!("failed to remove {}", path.display()))?;
+        }
+        report.removed.push(path);
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::LedgerEntry;
+    use crate::types::{AgentProvider, FileKind, FileStrategy};
+    use tempfile::TempDir;
+
+    fn write_skill(root: &Path, name: &str, body: &str) -> PathBuf {
+        let dir = root.join(name);
+        fs::create_dir_all(dir.join("scripts")).unwrap();
+        fs::write(dir.join("SKILL.md"), body).unwrap();
+        fs::write(dir.join("scripts/run.sh"), "echo hi").unwrap();
+        dir
+    }
+
+    #[test]
+    fn hash_ignores_name_but_tracks_content() -> Result<()> {
+        let dir = TempDir::new()?;
+        let a = write_skill(dir.path(), "a", "# Skill");
+        let b = write_skill(dir.path(), "b", "# Skill");
+        assert_eq!(hash_tree(&a)?, hash_tree(&b)?);
+
+        fs::write(b.join("scripts/run.sh"), "echo bye")?;
+        assert_ne!(hash_tree(&a)?, hash_tree(&b)?);
+
+        // Moving content between files changes the hash
+        let c = write_skill(dir.path(), "c", "# Skill");
+        fs::rename(c.join("scripts/run.sh"), c.join("scripts/go.sh"))?;
+        assert_ne!(hash_tree(&a)?, hash_tree(&c)?);
+        Ok(())
+    }
+
+    #[test]
+    fn snapshot_is_reused_and_immutable() -> Result<()> {
+        let src = TempDir::new()?;
+        let data = TempDir::new()?;
+        let store = store_dir(data.path());
+        let skill = write_skill(src.path(), "review", "# v1");
+
+        let (hash, path) = snapshot(&skill, &store)?;
+        assert_eq!(path, store.join(&hash).join("review"));
+        assert_eq!(fs::read_to_string(path.join("SKILL.md"))?, "# v1");
+
+        // Changing the source yields a new snapshot and leaves the old one alone
+        fs::write(skill.join("SKILL.md"), "# v2")?;
+        let (hash2, path2) = snapshot(&skill, &store)?;
+        assert_ne!(hash, hash2);
+        assert_eq!(fs::read_to_string(path.join("SKILL.md"))?, "# v1");
+        assert_eq!(fs::read_to_string(path2.join("SKILL.md"))?, "# v2");
+
+        // Identical content is not copied twice
+        assert_eq!(snapshot(&skill, &store)?.1, path2);
+        let entries: Vec<_> = fs::read_dir(&store)?.collect();
+        assert_eq!(entries.len(), 2);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn gc_removes_unreferenced_snapshots() -> Result<()> {
+        let src = TempDir::new()?;
+        let data = TempDir::new()?;
+        let project = TempDir::new()?;
+        let store = store_dir(data.path());
+
+        let used = write_skill(src.path(), "used", "# used");
+        let unused = write_skill(src.path(), "unused", "# unused");
+        let (used_hash, used_path) = snapshot(&used, &store)?;
+        let (unused_hash, _) = snapshot(&unused, &store)?;
+
+        let target = project.path().join("used");
+        std::os::unix::fs::symlink(&used_path, &target)?;
+        let mut ledger = Ledger::load(data.path(), project.path())?;
+        ledger.record(vec![LedgerEntry {
+            target: target.clone(),
+            source: "org/repo".to_string(),
+            item: "skills/used".to_string(),
+            provider: AgentProvider::ClaudeCode,
+            kind: FileKind::Skill,
+            strategy: FileStrategy::Link,
+            snapshot: Some(used_hash.clone()),
+            hash: None,
+            commit: None,
+        }]);
+        ledger.save(data.path())?;
+
+        let report = gc(data.path(), true)?;
+        assert_eq!(report.removed, vec![store.join(&unused_hash)]);
+        assert!(store.join(&unused_hash).exists());
+
+        let report = gc(data.path(), false)?;
+        assert_eq!(report.kept, 1);
+        assert!(store.join(&used_hash).exists());
+        assert!(!store.join(&unused_hash).exists());
+
+        // Once the link is gone, its snapshot is collectable too
+        fs::remove_file(&target)?;
+        gc(data.path(), false)?;
+        assert!(!store.join(&used_hash).exists());
+        assert!(
+            Ledger::load(data.path(), project.path())?
+                .entries
+                .is_empty()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,346 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+
+use crate::types::AgentProvider;
+
+/// Prefix of variables read from the environment: `{{ env.USER }}`.
+const ENV_PREFIX: &str = "env.";
+
+/// The file of a skill directory that gets rendered.
+const SKILL_FILE: &str = "SKILL.md";
+
+/// Values for `{{ var }}` placeholders in installed files.
+///
+/// Lookups try, in order: `provider.id` for the provider being installed
+/// to, the explicit values (dependency `vars` over manifest `vars` over the
+/// `project.*` built-ins), then `env.NAME` from the environment.
+///
+/// Only the environment variables the manifest lists in `env` can be read,
+/// so upstream files can't pull secrets such as tokens into installed files.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Vars {
+    values: BTreeMap<String, String>,
+    env: BTreeSet<String>,
+}
+
+impl Vars {
+    /// Built-ins for the project at `root`, plus the manifest's `vars` and
+    /// the environment variables `env` allows.
+    /// `project.name` is the manifest name, or else the directory name.
+    pub(crate) fn new(
+        root: &Path,
+        name: Option<&str>,
+        vars: &BTreeMap<String, String>,
+        env: &[String],
+    ) -> Self {
+        let name = name
+            .filter(|n| !n.is_empty())
+            .map(str::to_string)
+            .or_else(|| root.file_name().map(|n| n.to_string_lossy().into_owned()))
+            .unwrap_or_default();
+        let mut values = BTreeMap::from([
+            ("project.name".to_string(), name),
+            ("project.root".to_string(), root.display().to_string()),
+        ]);
+        values.extend(vars.clone());
+        Vars {
+            values,
+            env: env.iter().cloned().collect(),
+        }
+    }
+
+    /// These values with a dependency's own `vars` on top.
+    pub(crate) fn with(&self, overrides: Option<&BTreeMap<String, String>>) -> Self {
+        let mut values = self.values.clone();
+        values.extend(
+            overrides
+                .into_iter()
+                .flatten()
+                .map(|(k, v)| (k.clone(), v.clone())),
+        );
+        Vars {
+            values,
+            env: self.env.clone(),
+        }
+    }
+
+    fn get(&self, name: &str, provider: AgentProvider) -> Option<String> {
+        if name == "provider.id" {
+            return Some(provider.id().to_string());
+        }
+        if let Some(value) = self.values.get(name) {
+            return Some(value.clone());
+        }
+        let var = name.strip_prefix(ENV_PREFIX)?;
+        if !self.env.contains(var) {
+            return None;
+        }
+        std::env::var(var).ok()
+    }
+}
+
+/// Render the markdown of the item at `source_path` for `provider`: the
+/// `SKILL.md` of a skill directory, or the command or agent file itself.
+///
+/// Supporting files of a skill (scripts, templates, ...) are installed as
+/// they are, since their own `{{ }}` syntax isn't ours to interpret.
+///
+/// Returns the rendered content keyed by its path inside the item (empty
+/// for a single file), or `None` when nothing needs rendering. `item` is the
+/// item's path as shown in errors.
+pub(crate) fn render_item(
+    source_path: &Path,
+    item: &Path,
+    vars: &Vars,
+    provider: AgentProvider,
+) -> Result<Option<Vec<(PathBuf, String)>>> {
+    let relative = if source_path.is_dir() {
+        PathBuf::from(SKILL_FILE)
+    } else {
+        PathBuf::new()
+    };
+    let path = item_path(source_path, &relative);
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let Ok(text) = String::from_utf8(
+        fs::read(&path).with_context(|| format!("failed to read {}", path.display()))?,
+    ) else {
+        return Ok(None);
+    };
+    if !has_placeholders(&text) {
+        return Ok(None);
+    }
+    let text = render(&text, &item_path(item, &relative), |name| {
+        vars.get(name, provider)
+    })?;
+    Ok(Some(vec![(relative, text)]))
+}
+
+/// Path of a file returned by `render_item` inside the item at `base`.
+pub(crate) fn item_path(base: &Path, relative: &Path) -> PathBuf {
+    if relative.as_os_str().is_empty() {
+        base.to_path_buf()
+    } else {
+        base.join(relative)
+    }
+}
+
+/// A `{{ name }}` placeholder found in a text.
+struct Placeholder<'a> {
+    /// Byte range of the whole placeholder, braces included.
+    start: usize,
+    end: usize,
+    name: &'a str,
+    /// Whether it was written `\{{ name }}` to be kept literally; `start`
+    /// then covers the backslash.
+    escaped: bool,
+}
+
+/// Placeholders in `text`. Only names made of letters, digits, `_`, `-`
+/// and `.` count, and `${{ ... }}` (GitHub Actions expressions) is left
+/// alone, so other brace syntaxes in agent files pass through unchanged.
+fn placeholders(text: &str) -> Vec<Placeholder<'_>> {
+    let mut found = Vec::new();
+    let mut from = 0;
+    while let Some(offset) = text[from..].find("{{") {
+        let open = from + offset;
+        from = open + 2;
+        let Some(len) = text[from..].find("}}") else {
+            break;
+        };
+        let name = text[from..from + len].trim();
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+        let before = text[..open].chars().next_back();
+        if !valid || before == Some('$') {
+            continue;
+        }
+        let escaped = before == Some('\\');
+        found.push(Placeholder {
+            start: if escaped { open - 1 } else { open },
+            end: from + len + 2,
+            name,
+            escaped,
+        });
+        from += len + 2;
+    }
+    found
+}
+
+/// Whether `text` has anything `render` would replace.
+pub(crate) fn has_placeholders(text: &str) -> bool {
+    !placeholders(text).is_empty()
+}
+
+/// Replace the placeholders in `text`, read from `file`, with values from
+/// `lookup`. Every undefined variable is reported with its line.
+pub(crate) fn render(
+    text: &str,
+    file: &Path,
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Result<String> {
+    let mut out = String::with_capacity(text.len());
+    let mut undefined = Vec::new();
+    let mut last = 0;
+    for placeholder in placeholders(text) {
+        out.push_str(&text[last..placeholder.start]);
+        last = placeholder.end;
+        if placeholder.escaped {
+            out.push_str(&text[placeholder.start + 1..placeholder.end]);
+            continue;
+        }
+        match lookup(placeholder.name) {
+            Some(value) => out.push_str(&value),
+            None => {
+                let line = text[..placeholder.start].matches('\n').count() + 1;
+                undefined.push(format!(
+                    "undefined variable '{}' in {}:{line}",
+                    placeholder.name,
+                    file.display()
+                ));
+            }
+        }
+    }
+    if !undefined.is_empty() {
+        bail!("{}", undefined.join("\n"));
+    }
+    out.push_str(&text[last..]);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn vars() -> Vars {
+        Vars::new(
+            Path::new("/work/shop"),
+            None,
+            &BTreeMap::from([("branch".to_string(), "main".to_string())]),
+            &["PATH".to_string()],
+        )
+        .with(Some(&BTreeMap::from([(
+            "test.command".to_string(),
+            "cargo test".to_string(),
+        )])))
+    }
+
+    #[test]
+    fn renders_placeholders() -> Result<()> {
+        let vars = vars();
+        let text = "Project {{project.name}} on {{ branch }}: run `{{ test.command }}` \
+                    for {{ provider.id }}.\n";
+        let rendered = render(text, Path::new("commands/ci.md"), |name| {
+            vars.get(name, AgentProvider::Codex)
+        })?;
+        assert_eq!(
+            rendered,
+            "Project shop on main: run `cargo test` for codex.\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reads_only_allowed_environment_variables() {
+        let vars = vars();
+        let lookup = |name: &str| vars.get(name, AgentProvider::ClaudeCode);
+        assert_eq!(lookup("env.PATH"), std::env::var("PATH").ok());
+        // Set, but not listed in the manifest's `env`
+        assert!(std::env::var("HOME").is_ok());
+        assert_eq!(lookup("env.HOME"), None);
+    }
+
+    #[test]
+    fn leaves_other_braces_alone() -> Result<()> {
+        let text = "${{ secrets.TOKEN }} {{#each items}} \\{{ branch }} {{ }}";
+        assert!(!has_placeholders(
+            "${{ secrets.TOKEN }} {{#each items}} {{ }}"
+        ));
+        let rendered = render(text, Path::new("a.md"), |_| None)?;
+        assert_eq!(
+            rendered,
+            "${{ secrets.TOKEN }} {{#each items}} {{ branch }} {{ }}"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn undefined_variables_name_file_and_line() {
+        let err = render(
+            "# Deploy\n\nPush {{ branch }}\nthen {{ remote }} and {{ env.AGENTFILES_UNSET_VAR }}",
+            Path::new("commands/deploy.md"),
+            |name| vars().get(name, AgentProvider::ClaudeCode),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "undefined variable 'remote' in commands/deploy.md:4\n\
+             undefined variable 'env.AGENTFILES_UNSET_VAR' in commands/deploy.md:4"
+        );
+    }
+
+    #[test]
+    fn renders_only_markdown_with_placeholders() -> Result<()> {
+        let dir = TempDir::new()?;
+        let skill = dir.path().join("review");
+        fs::create_dir_all(skill.join("scripts"))?;
+        fs::write(skill.join("SKILL.md"), "Review {{ project.name }}")?;
+        fs::write(skill.join("scripts/run.sh"), "echo plain")?;
+        fs::write(skill.join("logo.png"), [0xff, 0xfe, b'{', b'{'])?;
+
+        let rendered = render_item(
+            &skill,
+            Path::new("skills/review"),
+            &vars(),
+            AgentProvider::Cursor,
+        )?;
+        assert_eq!(
+            rendered,
+            Some(vec![(PathBuf::from("SKILL.md"), "Review shop".to_string())])
+        );
+
+        fs::write(skill.join("SKILL.md"), "Review")?;
+        assert_eq!(
+            render_item(
+                &skill,
+                Path::new("skills/review"),
+                &vars(),
+                AgentProvider::Cursor
+            )?,
+            None
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn leaves_supporting_files_of_a_skill_alone() -> Result<()> {
+        let dir = TempDir::new()?;
+        let skill = dir.path().join("site");
+        fs::create_dir_all(skill.join("templates"))?;
+        fs::write(skill.join("SKILL.md"), "Build pages for {{ project.name }}")?;
+        // Another template language's placeholders, undefined for us
+        fs::write(skill.join("templates/page.html"), "<h1>{{ name }}</h1>")?;
+
+        let rendered = render_item(
+            &skill,
+            Path::new("skills/site"),
+            &vars(),
+            AgentProvider::ClaudeCode,
+        )?;
+        assert_eq!(
+            rendered,
+            Some(vec![(
+                PathBuf::from("SKILL.md"),
+                "Build pages for shop".to_string()
+            )])
+        );
+        Ok(())
+    }
+}
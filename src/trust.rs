@@ -0,0 +1,247 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{Result, bail};
+use log::debug;
+
+use crate::manifest::{self, Manifest, SourcePolicy};
+use crate::{archive, git};
+
+/// Source policies in effect for one run, each with the manifest that
+/// configured it.
+///
+/// A source must satisfy every policy: the user manifest's applies on top
+/// of the project's, so a project can't loosen what the user config allows.
+#[derive(Debug, Default)]
+pub(crate) struct SourceRules {
+    policies: Vec<(PathBuf, SourcePolicy)>,
+}
+
+impl SourceRules {
+    /// Rules from `manifest` (read from `manifest_path`), if any, and from
+    /// the user manifest in `user_dir`.
+    pub(crate) fn load(manifest: Option<(&Manifest, &Path)>, user_dir: &Path) -> Result<Self> {
+        let mut policies = Vec::new();
+        if let Some((manifest, path)) = manifest
+            && let Some(policy) = &manifest.sources
+        {
+            policies.push((path.to_path_buf(), policy.clone()));
+        }
+
+        let user_path = user_dir.join(manifest::MANIFEST_FILE);
+        let is_user_manifest = manifest.is_some_and(|(_, path)| path == user_path);
+        if !is_user_manifest
+            && user_path.is_file()
+            && let Some(policy) = manifest::load_manifest(&user_path)?.sources
+        {
+            policies.push((user_path, policy));
+        }
+        debug!("Source policies: {:?}", policies);
+        Ok(SourceRules { policies })
+    }
+
+    /// Check that `source` may be used. `git_ref` and `checksum` are the
+    /// ref and archive checksum it is pinned to, if any.
+    ///
+    /// The error names the manifest and the rule that refused the source.
+    pub(crate) fn check(
+        &self,
+        source: &str,
+        git_ref: Option<&str>,
+        checksum: Option<&str>,
+    ) -> Result<()> {
+        for (origin, policy) in &self.policies {
+            if let Some((rule, reason)) = violation(policy, source, git_ref, checksum) {
+                bail!(
+                    "{source} is not allowed: {reason}\n  (rule 'sources.{rule}' in {})",
+                    origin.display()
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that `commit`, which the remote `source` pinned to `git_ref`
+    /// resolved to, is the commit the ref names. A branch or tag that merely
+    /// looks like a commit hash doesn't count as pinned.
+    pub(crate) fn check_resolved(
+        &self,
+        source: &str,
+        git_ref: Option<&str>,
+        commit: &str,
+    ) -> Result<()> {
+        for (origin, policy) in &self.policies {
+            if policy.require_pinned
+                && let Some(git_ref) = git_ref
+                && !commit.starts_with(&git_ref.to_ascii_lowercase())
+            {
+                bail!(
+                    "{source} is not allowed: ref '{git_ref}' resolved to {commit}, \
+                     which is not the commit it names\n  (rule 'sources.require_pinned' in {})",
+                    origin.display()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The rule of `policy` that `source` breaks, and why.
+fn violation(
+    policy: &SourcePolicy,
+    source: &str,
+    git_ref: Option<&str>,
+    checksum: Option<&str>,
+) -> Option<(&'static str, String)> {
+    let is_archive = archive::is_archive(source);
+    let remote = (git::is_git_url(source) || archive::is_remote_archive(source))
+        .then(|| git::parse_remote(source));
+    let (scheme, host, path) = match &remote {
+        Some(remote) => git::url_parts(&remote.url),
+        None => ("file", "", source),
+    };
+
+    if policy
+        .denied_schemes
+        .iter()
+        .any(|denied| denied.eq_ignore_ascii_case(scheme))
+    {
+        return Some(("denied_schemes", format!("the '{scheme}' scheme is denied")));
+    }
+
+    let has_allowlist = !policy.allowed_hosts.is_empty() || !policy.allowed_orgs.is_empty();
+    if remote.is_some() && scheme != "file" && has_allowlist {
+        let org = path.split('/').next().unwrap_or_default();
+        let host_allowed = policy
+            .allowed_hosts
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host));
+        let org_allowed = policy.allowed_orgs.iter().any(|allowed| {
+            allowed
+                .trim_end_matches('/')
+                .eq_ignore_ascii_case(&format!("{host}/{org}"))
+        });
+        if !host_allowed && !org_allowed {
+            let rule = if policy.allowed_orgs.is_empty() {
+                "allowed_hosts"
+            } else {
+                "allowed_orgs"
+            };
+            return Some((
+                rule,
+                format!("'{host}/{org}' is not an allowed host or org"),
+            ));
+        }
+    }
+
+    if policy.require_pinned {
+        if is_archive && checksum.is_none() {
+            return Some((
+                "require_pinned",
+                "archives must be pinned with an integrity checksum".to_string(),
+            ));
+        }
+        if let Some(remote) = remote.as_ref().filter(|_| !is_archive) {
+            let git_ref = git_ref.or(remote.git_ref.as_deref());
+            let is_commit = git_ref.is_some_and(|r| {
+                (7..=64).contains(&r.len()) && r.bytes().all(|b| b.is_ascii_hexdigit())
+            });
+            if !is_commit {
+                let reason = match git_ref {
+                    Some(git_ref) => format!("ref '{git_ref}' is not a commit hash"),
+                    None => "no commit hash is pinned".to_string(),
+                };
+                return Some(("require_pinned", reason));
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(policy: SourcePolicy) -> SourceRules {
+        SourceRules {
+            policies: vec![(PathBuf::from("agentfiles.json"), policy)],
+        }
+    }
+
+    fn check(rules: &SourceRules, source: &str) -> Result<(), String> {
+        rules.check(source, None, None).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn denies_schemes() {
+        let rules = rules(SourcePolicy {
+            denied_schemes: vec!["http".into(), "git".into(), "file".into()],
+            ..Default::default()
+        });
+        let err = check(&rules, "http://example.com/org/repo").unwrap_err();
+        assert!(err.contains("'http' scheme is denied"));
+        assert!(err.contains("sources.denied_schemes"));
+        assert!(check(&rules, "git://example.com/org/repo").is_err());
+        assert!(check(&rules, "./local-skills").is_err());
+        assert!(check(&rules, "github.com/org/repo").is_ok());
+    }
+
+    #[test]
+    fn allows_listed_hosts_and_orgs() {
+        let rules = rules(SourcePolicy {
+            allowed_hosts: vec!["git.corp.example".into()],
+            allowed_orgs: vec!["github.com/acme".into()],
+            ..Default::default()
+        });
+        assert!(check(&rules, "https://git.corp.example/team/agents").is_ok());
+        assert!(check(&rules, "github.com/acme/skills@v1").is_ok());
+        assert!(check(&rules, "git@github.com:acme/skills.git").is_ok());
+        assert!(check(&rules, "https://github.com/ACME/skills").is_ok());
+
+        let err = check(&rules, "github.com/evil/skills").unwrap_err();
+        assert!(err.contains("'github.com/evil' is not an allowed host or org"));
+        assert!(err.contains("sources.allowed_orgs"));
+        // Local paths have no host
+        assert!(check(&rules, "./local-skills").is_ok());
+    }
+
+    #[test]
+    fn requires_pinned_refs() {
+        let rules = rules(SourcePolicy {
+            require_pinned: true,
+            ..Default::default()
+        });
+        let err = check(&rules, "github.com/org/repo@main").unwrap_err();
+        assert!(err.contains("ref 'main' is not a commit hash"));
+        assert!(check(&rules, "github.com/org/repo").is_err());
+        assert!(check(&rules, "github.com/org/repo@0123abcd").is_ok());
+        assert!(
+            rules
+                .check("github.com/org/repo", Some("0123abcd"), None)
+                .is_ok()
+        );
+
+        // A branch named like a hash resolves to some other commit
+        let commit = "0123abcd".repeat(5);
+        let err = rules
+            .check_resolved("github.com/org/repo", Some("deadbeef"), &commit)
+            .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("ref 'deadbeef' resolved to 0123abcd")
+        );
+        assert!(
+            rules
+                .check_resolved("github.com/org/repo", Some("0123ABCD"), &commit)
+                .is_ok()
+        );
+        assert!(
+            SourceRules::default()
+                .check_resolved("github.com/org/repo", Some("deadbeef"), &commit)
+                .is_ok()
+        );
+
+        let archive = "https://example.com/skills.tar.gz";
+        assert!(check(&rules, archive).is_err());
+        assert!(rules.check(archive, None, Some("sha256:00")).is_ok());
+    }
+}
@@ -3,11 +3,15 @@ use std::fmt;
 use std::str::FromStr;
 
 /// Scope determines where files are installed: relative to the project root or globally.
+///
+/// `Local` installs into the same directories as `Project`, but is tracked in
+/// a personal manifest and kept out of version control.
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum FileScope {
     Project,
     Global,
+    Local,
 }
 
 impl fmt::Display for FileScope {
@@ -15,6 +19,7 @@ impl fmt::Display for FileScope {
         match self {
             FileScope::Project => write!(f, "project"),
             FileScope::Global => write!(f, "global"),
+            FileScope::Local => write!(f, "local"),
         }
     }
 }
@@ -26,7 +31,10 @@ impl FromStr for FileScope {
         match s.to_lowercase().as_str() {
             "project" => Ok(FileScope::Project),
             "global" => Ok(FileScope::Global),
-            other => anyhow::bail!("unknown scope '{other}', expected 'project' or 'global'"),
+            "local" => Ok(FileScope::Local),
+            other => {
+                anyhow::bail!("unknown scope '{other}', expected 'project', 'global' or 'local'")
+            }
         }
     }
 }
@@ -50,6 +58,21 @@ impl fmt::Display for FileKind {
     }
 }
 
+/// What an install does with a target that was edited since agentfiles
+/// installed it, or that agentfiles didn't create.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum OnConflict {
+    /// Refuse to install, showing how the target differs.
+    #[default]
+    Refuse,
+    /// Overwrite the target.
+    Force,
+    /// Move the target aside to `<name>.orig`, then install.
+    Backup,
+    /// Leave the target as it is and skip installing over it.
+    Keep,
+}
+
 /// How a file is placed at the target location.
 #[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
 pub enum FileStrategy {
@@ -96,6 +119,16 @@ impl AgentProvider {
         AgentProvider::Codex,
         AgentProvider::Cursor,
     ];
+
+    /// Identifier used on the command line and in manifests (`claude-code`).
+    pub fn id(&self) -> &'static str {
+        match self {
+            AgentProvider::ClaudeCode => "claude-code",
+            AgentProvider::OpenCode => "opencode",
+            AgentProvider::Codex => "codex",
+            AgentProvider::Cursor => "cursor",
+        }
+    }
 }
 
 impl fmt::Display for AgentProvider {
@@ -135,6 +168,7 @@ mod tests {
     fn parse_file_scope() {
         assert_eq!("project".parse::<FileScope>().unwrap(), FileScope::Project);
         assert_eq!("global".parse::<FileScope>().unwrap(), FileScope::Global);
+        assert_eq!("local".parse::<FileScope>().unwrap(), FileScope::Local);
         assert!("invalid".parse::<FileScope>().is_err());
     }
 